@@ -0,0 +1,17 @@
+#version 430
+
+in vec3 pos;
+in vec3 norm;
+in float ao;
+in vec3 offset;
+
+out vec3 col;
+
+uniform mat4 pvmat;
+uniform mat4 tmat;
+
+void main() {
+    col = norm * ao;
+    vec4 world_pos = tmat * vec4(pos + offset, 1.0);
+    gl_Position = pvmat * world_pos;
+}
\ No newline at end of file
@@ -2,6 +2,7 @@
 
 in vec3 pos;
 in vec3 norm;
+in float ao;
 in vec2 tc;
 
 out vec3 col;
@@ -10,7 +11,7 @@ uniform mat4 pvmat;
 uniform mat4 tmat;
 
 void main() {
-    col = norm;
+    col = norm * ao;
     vec4 world_pos = tmat * vec4(pos, 1.0);
     vec4 pos = pvmat * world_pos;
     gl_Position = pos;
@@ -0,0 +1,114 @@
+//! Repair a broken mesh into a closed one without opening the viewer. Outputs ending in `.stl`
+//! are saved as binary STL in millimetres, ready to print, anything else as OBJ.
+//!
+//! ```text
+//! remesh <input> <output.obj|output.stl> [--voxel-size <size>] [--close <voxels>] [--smooth <rounds>] [--simplify <error>] [--style mc|sn|dc] [--mm-per-unit <mm>]
+//! ```
+
+use std::{fs::File, io::BufWriter, path::PathBuf, process::exit};
+
+use voxeliser::{
+    export::{
+        obj::write_obj,
+        stl::{check_printable, write_stl, StlSettings},
+    },
+    mesher::{simplify::SimplifySettings, MeshStyle},
+    model::{loader::load_model, Model},
+    remesh::{remesh, RemeshSettings},
+    voxeliser::check_scale,
+};
+
+const USAGE: &str =
+    "usage: remesh <input> <output.obj|output.stl> [--voxel-size <size>] [--close <voxels>] [--smooth <rounds>] [--simplify <error>] [--style mc|sn|dc] [--mm-per-unit <mm>]";
+
+fn main() {
+    let (input, output, settings, stl) = match parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            exit(2);
+        }
+    };
+
+    let model = match load_model(input) {
+        Ok((verts, inds)) => Model::new(verts, inds),
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(1);
+        }
+    };
+
+    let mesh = remesh([&model], &settings);
+    println!("Remeshed into {} vertices and {} triangles", mesh.verts.len(), mesh.inds.len() / 3);
+
+    let is_stl = output.extension().is_some_and(|e| e.eq_ignore_ascii_case("stl"));
+    if is_stl {
+        if let Err(e) = check_printable(&mesh) {
+            eprintln!("Can't print the remeshed model - {}", e);
+            exit(1);
+        }
+    }
+    let written = File::create(&output).and_then(|f| {
+        if is_stl {
+            write_stl(&mesh, &stl, &mut BufWriter::new(f))
+        } else {
+            write_obj(&mesh, &mut BufWriter::new(f))
+        }
+    });
+    if let Err(e) = written {
+        eprintln!("Failed to write {} - {}", output.display(), e);
+        exit(1);
+    }
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<(PathBuf, PathBuf, RemeshSettings, StlSettings), String> {
+    let mut settings = RemeshSettings::default();
+    let mut stl = StlSettings::default();
+    let mut paths = Vec::new();
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("Missing value for {}", arg));
+        match arg.as_str() {
+            "--voxel-size" => {
+                settings.voxel_size = parse(&value()?)?;
+                check_scale(settings.voxel_size)?;
+            }
+            "--close" => settings.closing = parse(&value()?)?,
+            "--smooth" => settings.smoothing = parse(&value()?)?,
+            "--simplify" => {
+                settings.simplify = Some(SimplifySettings {
+                    ratio: 0.0,
+                    max_error: parse(&value()?)?,
+                })
+            }
+            "--mm-per-unit" => stl.mm_per_unit = parse_positive(&value()?)?,
+            "--style" => {
+                settings.style = match value()?.as_str() {
+                    "mc" => MeshStyle::MarchingCubes { smoothing: 1 },
+                    "sn" => MeshStyle::SurfaceNets,
+                    "dc" => MeshStyle::DualContouring,
+                    other => return Err(format!("Unknown style {}", other)),
+                }
+            }
+            _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+
+    match <[PathBuf; 2]>::try_from(paths) {
+        Ok([input, output]) => Ok((input, output, settings, stl)),
+        Err(_) => Err(String::from("Expected an input and an output file")),
+    }
+}
+
+fn parse<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("Invalid value {}", value))
+}
+
+/// Parse a size, which has to be a finite number above 0
+fn parse_positive(value: &str) -> Result<f32, String> {
+    match parse::<f32>(value)? {
+        v if v.is_finite() && v > 0.0 => Ok(v),
+        _ => Err(format!("Invalid value {}, it has to be above 0", value)),
+    }
+}
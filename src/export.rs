@@ -0,0 +1,10 @@
+//! Saving voxel models to files
+
+pub mod binvox;
+pub mod gltf;
+pub mod minecraft;
+pub mod obj;
+pub mod ply;
+pub mod sequence;
+pub mod stl;
+pub mod vox;
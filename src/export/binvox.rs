@@ -0,0 +1,172 @@
+//! `.binvox` files, as used by a lot of machine learning and research code.
+//!
+//! A text header giving the size of the grid, where it is and how big it is, then run length
+//! encoded voxels as pairs of a byte that is 1 for filled and a byte for how many in a row.
+//! Voxels go with y changing fastest, then z, then x.
+//!
+//! Files only say which voxels are filled, so materials aren't kept.
+
+use std::io::{self, BufRead, Write};
+
+use glam::Vec3;
+
+use crate::grid::{MaterialId, VoxelGrid};
+
+/// Write a grid as binvox. The file's `translate` is the lowest corner of the grid and its
+/// `scale` is the length of the grid's longest side, the same as
+/// [`generate_voxels`](crate::voxeliser::generate_voxels) uses for the grid's `min` and voxel
+/// size.
+///
+/// Most binvox readers only handle grids the same size along every side, so the grid is padded
+/// with empty voxels up to the size of its longest side.
+pub fn write_binvox<W: Write>(grid: &VoxelGrid, w: &mut W) -> io::Result<()> {
+    let side = grid.size.into_iter().max().unwrap_or(0);
+
+    writeln!(w, "#binvox 1")?;
+    writeln!(w, "dim {} {} {}", side, side, side)?;
+    writeln!(w, "translate {} {} {}", grid.min.x, grid.min.y, grid.min.z)?;
+    writeln!(w, "scale {}", grid.scale * side as f32)?;
+    writeln!(w, "data")?;
+
+    let mut run: Option<(u8, u8)> = None;
+    for x in 0..side {
+        for z in 0..side {
+            for y in 0..side {
+                let filled = (x < grid.size[0] && y < grid.size[1] && z < grid.size[2] && grid.get(x, y, z).is_some()) as u8;
+                run = match run {
+                    Some((value, count)) if value == filled && count < u8::MAX => Some((value, count + 1)),
+                    Some((value, count)) => {
+                        w.write_all(&[value, count])?;
+                        Some((filled, 1))
+                    }
+                    None => Some((filled, 1)),
+                };
+            }
+        }
+    }
+    if let Some((value, count)) = run {
+        w.write_all(&[value, count])?;
+    }
+
+    w.flush()
+}
+
+/// Read a binvox file, giving every filled voxel `material`. The grid's `min` and voxel size come
+/// from the file's `translate` and `scale` the same way [`write_binvox`] writes them.
+pub fn read_binvox<R: BufRead>(r: &mut R, material: MaterialId) -> io::Result<VoxelGrid> {
+    let mut line = String::new();
+    r.read_line(&mut line)?;
+    if !line.starts_with("#binvox") {
+        return Err(invalid("Not a binvox file"));
+    }
+
+    let mut size = None;
+    let mut min = Vec3::ZERO;
+    let mut scale = 1.0;
+    loop {
+        line.clear();
+        if r.read_line(&mut line)? == 0 {
+            return Err(invalid("Missing voxel data"));
+        }
+        let mut parts = line.split_whitespace();
+        match parts.next() {
+            Some("dim") => {
+                let dims: Vec<usize> = parts.map(|p| p.parse().map_err(|_| invalid("Corrupt size"))).collect::<io::Result<_>>()?;
+                size = Some(<[usize; 3]>::try_from(dims).map_err(|_| invalid("Corrupt size"))?);
+            }
+            Some("translate") => {
+                let t: Vec<f32> = parts.map(|p| p.parse().map_err(|_| invalid("Corrupt translate"))).collect::<io::Result<_>>()?;
+                min = Vec3::from_slice(&<[f32; 3]>::try_from(t).map_err(|_| invalid("Corrupt translate"))?);
+            }
+            Some("scale") => scale = parts.next().and_then(|s| s.parse().ok()).ok_or_else(|| invalid("Corrupt scale"))?,
+            Some("data") => break,
+            _ => {}
+        }
+    }
+
+    let size = size.ok_or_else(|| invalid("Missing size"))?;
+    let len = size[0]
+        .checked_mul(size[1])
+        .and_then(|l| l.checked_mul(size[2]))
+        .ok_or_else(|| invalid("Grid is too big"))?;
+    // Runs of filled voxels in the file's order, with y changing fastest, then z, then x. The
+    // grid is only made once they've all been read, so a corrupt size can't ask for more memory
+    // than the file has data for.
+    let mut filled = Vec::new();
+    let mut i = 0;
+    while i < len {
+        let mut pair = [0; 2];
+        r.read_exact(&mut pair)?;
+        let [value, count] = pair;
+        let count = count as usize;
+        if count > len - i {
+            return Err(invalid("Corrupt voxel data"));
+        }
+        if value != 0 {
+            filled.push(i..i + count);
+        }
+        i += count;
+    }
+
+    let longest = size.into_iter().max().unwrap_or(0).max(1);
+    let mut grid = VoxelGrid::new(min, scale / longest as f32, size);
+    for j in filled.into_iter().flatten() {
+        let (x, z, y) = (j / (size[2] * size[1]), j / size[1] % size[2], j % size[1]);
+        grid.set(x, y, z, Some(material));
+    }
+
+    Ok(grid)
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filled_voxels_round_trip() {
+        let mut grid = VoxelGrid::new(Vec3::new(-1.0, 2.0, 0.5), 0.5, [6, 6, 6]);
+        for x in 0..6 {
+            for y in 0..6 {
+                for z in 0..6 {
+                    if (x * 3 + y + z * 2) % 5 < 2 {
+                        grid.set(x, y, z, Some(3));
+                    }
+                }
+            }
+        }
+
+        let mut data = Vec::new();
+        write_binvox(&grid, &mut data).unwrap();
+        let read = read_binvox(&mut &data[..], 3).unwrap();
+        assert_eq!(read.size, grid.size);
+        assert_eq!(read.min, grid.min);
+        assert_eq!(read.scale, grid.scale);
+        assert_eq!(read.voxels(), grid.voxels());
+    }
+
+    #[test]
+    fn grids_are_padded_to_cubes() {
+        let mut grid = VoxelGrid::new(Vec3::ZERO, 1.0, [40, 2, 1]);
+        grid.set(39, 1, 0, Some(0));
+        grid.set(0, 0, 0, Some(0));
+
+        let mut data = Vec::new();
+        write_binvox(&grid, &mut data).unwrap();
+        let read = read_binvox(&mut &data[..], 0).unwrap();
+        assert_eq!(read.size, [40; 3]);
+        assert_eq!(read.count(), 2);
+        assert_eq!(read.get(39, 1, 0), Some(0));
+        assert_eq!(read.get(0, 0, 0), Some(0));
+    }
+
+    #[test]
+    fn huge_sizes_fail_without_allocating() {
+        let data = b"#binvox 1\ndim 100000 100000 100000\ntranslate 0 0 0\nscale 1\ndata\n\x01\xff";
+        let e = read_binvox(&mut &data[..], 0).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
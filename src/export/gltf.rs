@@ -0,0 +1,285 @@
+//! glTF 2.0 scenes, either as a `.gltf` with its data in a `.bin` next to it or as one `.glb`.
+//!
+//! Meshes are in the same space as the models they were voxelised from, and glTF has y up like
+//! the viewer does, so positions are written as they are. Importing the result again puts it
+//! right back where the source models were, at the same scale.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use crate::{
+    grid::MaterialId,
+    mesher::VoxelMesh,
+    model::vertex::Vertex,
+    palette::{shade, Palette},
+};
+
+use super::obj::mesh_materials;
+
+/// How the colours of materials are stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GltfColours {
+    /// A colour on every vertex, with everything drawn using one plain material
+    Vertex,
+    /// A glTF material for each material, with the triangles of each in a primitive of their own
+    Materials,
+}
+
+/// Write meshes as a binary glTF, each as a node of its own
+pub fn write_glb<W: Write>(meshes: &[VoxelMesh], palette: &Palette, colours: GltfColours, w: &mut W) -> io::Result<()> {
+    let (json, bin) = build(meshes, palette, colours, None)?;
+    let json = padded(json.into_bytes(), b' ');
+    let bin = padded(bin, 0);
+
+    let mut length = 12 + 8 + json.len();
+    if !bin.is_empty() {
+        length += 8 + bin.len();
+    }
+    w.write_all(b"glTF")?;
+    w.write_all(&2u32.to_le_bytes())?;
+    w.write_all(&(length as u32).to_le_bytes())?;
+
+    w.write_all(&(json.len() as u32).to_le_bytes())?;
+    w.write_all(b"JSON")?;
+    w.write_all(&json)?;
+    if !bin.is_empty() {
+        w.write_all(&(bin.len() as u32).to_le_bytes())?;
+        w.write_all(b"BIN\0")?;
+        w.write_all(&bin)?;
+    }
+    w.flush()
+}
+
+/// Save meshes as a glTF at `path` with its data in a `.bin` next to it, named after it. Each
+/// mesh is a node of its own.
+pub fn export_gltf(meshes: &[VoxelMesh], palette: &Palette, colours: GltfColours, path: &Path) -> io::Result<()> {
+    let name = path.file_stem().and_then(|n| n.to_str()).unwrap_or("model");
+    let bin_name = format!("{}.bin", name);
+    let (json, bin) = build(meshes, palette, colours, Some(&percent_encode(&bin_name)))?;
+
+    let mut gltf = BufWriter::new(File::create(path)?);
+    gltf.write_all(json.as_bytes())?;
+    gltf.flush()?;
+    if !bin.is_empty() {
+        let mut data = BufWriter::new(File::create(path.with_file_name(&bin_name))?);
+        data.write_all(&bin)?;
+        data.flush()?;
+    }
+    Ok(())
+}
+
+/// The JSON of a glTF for the meshes and the data in its buffer, which is found at `uri` if
+/// given or in the same file for binary glTF
+fn build(meshes: &[VoxelMesh], palette: &Palette, colours: GltfColours, uri: Option<&str>) -> io::Result<(String, Vec<u8>)> {
+    let meshes: Vec<&VoxelMesh> = meshes.iter().filter(|m| !m.inds.is_empty()).collect();
+    if let Some(mesh) = meshes.iter().find(|m| m.verts.len() > u32::MAX as usize) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Mesh has too many vertices for glTF ({})", mesh.verts.len()),
+        ));
+    }
+
+    // Every material used by any of the meshes gets a glTF material of its own
+    let mut used: Vec<MaterialId> = meshes.iter().flat_map(|m| mesh_materials(m)).collect();
+    used.sort_unstable();
+    used.dedup();
+    let gltf_materials: Vec<String> = match colours {
+        GltfColours::Vertex => vec![String::from(
+            r#"{"name":"voxels","pbrMetallicRoughness":{"metallicFactor":0,"roughnessFactor":1}}"#,
+        )],
+        GltfColours::Materials => used.iter().map(|m| material(*m, palette.colour(*m))).collect(),
+    };
+    let material_index: HashMap<MaterialId, usize> = used.iter().enumerate().map(|(i, m)| (*m, i)).collect();
+
+    let mut doc = Document::default();
+    let mut gltf_meshes = Vec::new();
+    let mut nodes = Vec::new();
+    for (i, mesh) in meshes.iter().enumerate() {
+        let mut lo = [f32::INFINITY; 3];
+        let mut hi = [f32::NEG_INFINITY; 3];
+        for v in &mesh.verts {
+            for a in 0..3 {
+                lo[a] = lo[a].min(v.pos[a]);
+                hi[a] = hi[a].max(v.pos[a]);
+            }
+        }
+
+        let positions = floats(mesh.verts.iter().flat_map(|v| v.pos));
+        let position = doc.accessor(positions, ARRAY_BUFFER, FLOAT, mesh.verts.len(), "VEC3", Some((lo, hi)));
+        let normals = floats(mesh.verts.iter().flat_map(|v| v.norm));
+        let normal = doc.accessor(normals, ARRAY_BUFFER, FLOAT, mesh.verts.len(), "VEC3", None);
+        let mut attributes = format!(r#""POSITION":{},"NORMAL":{}"#, position, normal);
+
+        // Ambient occlusion is baked into the vertex colours. Materials are multiplied by them,
+        // so with materials the vertex colours are only the occlusion, if there is any
+        let vertex_colour = |(v, m): (&Vertex, &MaterialId)| {
+            let colour = match colours {
+                GltfColours::Vertex => palette.colour(*m),
+                GltfColours::Materials => [u8::MAX; 4],
+            };
+            let [r, g, b, a] = shade(colour, v.ao);
+            [srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b), a as f32 / 255.0]
+        };
+        if colours == GltfColours::Vertex || mesh.verts.iter().any(|v| v.ao < 1.0) {
+            let vertex_colours = mesh.verts.iter().zip(&mesh.materials).flat_map(vertex_colour);
+            let colour = doc.accessor(floats(vertex_colours), ARRAY_BUFFER, FLOAT, mesh.verts.len(), "VEC4", None);
+            attributes.push_str(&format!(r#","COLOR_0":{}"#, colour));
+        }
+
+        let primitives: Vec<String> = match colours {
+            GltfColours::Vertex => {
+                let indices = doc.indices(&mesh.inds);
+                vec![format!(r#"{{"attributes":{{{}}},"indices":{},"material":0}}"#, attributes, indices)]
+            }
+            GltfColours::Materials => mesh_materials(mesh)
+                .iter()
+                .map(|m| {
+                    let inds: Vec<usize> = mesh
+                        .inds
+                        .chunks_exact(3)
+                        .filter(|t| mesh.materials[t[0]] == *m)
+                        .flatten()
+                        .copied()
+                        .collect();
+                    let indices = doc.indices(&inds);
+                    format!(r#"{{"attributes":{{{}}},"indices":{},"material":{}}}"#, attributes, indices, material_index[m])
+                })
+                .collect(),
+        };
+
+        gltf_meshes.push(format!(r#"{{"name":"voxels_{}","primitives":[{}]}}"#, i, primitives.join(",")));
+        nodes.push(format!(r#"{{"name":"voxels_{}","mesh":{}}}"#, i, i));
+    }
+
+    let mut json = String::from(r#"{"asset":{"version":"2.0","generator":"voxeliser"},"scene":0"#);
+    let node_ids: Vec<String> = (0..nodes.len()).map(|i| i.to_string()).collect();
+    json.push_str(&format!(r#","scenes":[{{"nodes":[{}]}}]"#, node_ids.join(",")));
+    if !nodes.is_empty() {
+        json.push_str(&format!(r#","nodes":[{}]"#, nodes.join(",")));
+        json.push_str(&format!(r#","meshes":[{}]"#, gltf_meshes.join(",")));
+        json.push_str(&format!(r#","materials":[{}]"#, gltf_materials.join(",")));
+        json.push_str(&format!(r#","accessors":[{}]"#, doc.accessors.join(",")));
+        json.push_str(&format!(r#","bufferViews":[{}]"#, doc.views.join(",")));
+        match uri {
+            Some(uri) => json.push_str(&format!(r#","buffers":[{{"byteLength":{},"uri":"{}"}}]"#, doc.bin.len(), escape(uri))),
+            None => json.push_str(&format!(r#","buffers":[{{"byteLength":{}}}]"#, doc.bin.len())),
+        }
+    }
+    json.push('}');
+
+    Ok((json, doc.bin))
+}
+
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+const FLOAT: u32 = 5126;
+const UNSIGNED_INT: u32 = 5125;
+
+/// The buffer of a glTF along with the views and accessors into it
+#[derive(Default)]
+struct Document {
+    bin: Vec<u8>,
+    views: Vec<String>,
+    accessors: Vec<String>,
+}
+
+impl Document {
+    /// Add data to the buffer with a view and accessor for it, giving the index of the accessor.
+    /// Everything added is made of 4 byte values, so every view stays lined up.
+    fn accessor(&mut self, data: Vec<u8>, target: u32, component: u32, count: usize, kind: &str, bounds: Option<([f32; 3], [f32; 3])>) -> usize {
+        let view = self.views.len();
+        self.views.push(format!(
+            r#"{{"buffer":0,"byteOffset":{},"byteLength":{},"target":{}}}"#,
+            self.bin.len(),
+            data.len(),
+            target
+        ));
+        self.bin.extend(data);
+
+        let mut accessor = format!(
+            r#"{{"bufferView":{},"componentType":{},"count":{},"type":"{}""#,
+            view, component, count, kind
+        );
+        if let Some((lo, hi)) = bounds {
+            accessor.push_str(&format!(r#","min":[{},{},{}],"max":[{},{},{}]"#, lo[0], lo[1], lo[2], hi[0], hi[1], hi[2]));
+        }
+        accessor.push('}');
+        self.accessors.push(accessor);
+        self.accessors.len() - 1
+    }
+
+    fn indices(&mut self, inds: &[usize]) -> usize {
+        let data = inds.iter().flat_map(|i| (*i as u32).to_le_bytes()).collect();
+        self.accessor(data, ELEMENT_ARRAY_BUFFER, UNSIGNED_INT, inds.len(), "SCALAR", None)
+    }
+}
+
+fn material(material: MaterialId, colour: [u8; 4]) -> String {
+    let [r, g, b, a] = colour;
+    let mut json = format!(
+        r#"{{"name":"material_{}","pbrMetallicRoughness":{{"baseColorFactor":[{},{},{},{}],"metallicFactor":0,"roughnessFactor":1}}"#,
+        material,
+        srgb_to_linear(r),
+        srgb_to_linear(g),
+        srgb_to_linear(b),
+        a as f32 / 255.0
+    );
+    if a < 255 {
+        json.push_str(r#","alphaMode":"BLEND""#);
+    }
+    json.push('}');
+    json
+}
+
+/// glTF colours are linear, where palette colours are sRGB like most colour pickers
+fn srgb_to_linear(c: u8) -> f32 {
+    let c = c as f32 / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn floats(values: impl Iterator<Item = f32>) -> Vec<u8> {
+    values.flat_map(f32::to_le_bytes).collect()
+}
+
+/// Pad to a multiple of 4 bytes, as GLB chunks have to be
+fn padded(mut data: Vec<u8>, with: u8) -> Vec<u8> {
+    data.resize(data.len().next_multiple_of(4), with);
+    data
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Encode a file name for a URI, as names with spaces or other characters outside the URI
+/// unreserved set aren't valid references
+fn percent_encode(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => encoded.push(b as char),
+            _ => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uris_are_percent_encoded() {
+        assert_eq!(percent_encode("model-1_a.b~c.bin"), "model-1_a.b~c.bin");
+        assert_eq!(percent_encode("my model#2%.bin"), "my%20model%232%25.bin");
+        assert_eq!(percent_encode("café \"x\".bin"), "caf%C3%A9%20%22x%22.bin");
+    }
+}
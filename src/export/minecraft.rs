@@ -0,0 +1,110 @@
+//! Minecraft structure files and worlds, with each voxel placed as a block
+
+use std::collections::HashMap;
+
+use crate::grid::{MaterialId, VoxelGrid};
+
+use self::nbt::Tag;
+
+pub mod anvil;
+pub mod litematic;
+pub mod mcstructure;
+pub mod nbt;
+pub mod schematic;
+
+/// Data version written to files, the version of Minecraft they're made for (1.20.1)
+pub const DATA_VERSION: i32 = 3465;
+
+/// Block put wherever there isn't a voxel
+pub const AIR: &str = "minecraft:air";
+
+/// Which block each material is placed as, with any not given one using the default
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockMapping {
+    default: String,
+    blocks: HashMap<MaterialId, String>,
+}
+
+impl Default for BlockMapping {
+    fn default() -> Self {
+        BlockMapping::new("minecraft:stone")
+    }
+}
+
+impl BlockMapping {
+    /// Every material placed as `default`, a block state like `minecraft:oak_log[axis=y]`
+    pub fn new(default: impl Into<String>) -> BlockMapping {
+        BlockMapping {
+            default: default.into(),
+            blocks: HashMap::new(),
+        }
+    }
+
+    pub fn set(&mut self, material: MaterialId, block: impl Into<String>) {
+        self.blocks.insert(material, block.into());
+    }
+
+    pub fn block(&self, material: MaterialId) -> &str {
+        self.blocks.get(&material).unwrap_or(&self.default)
+    }
+}
+
+/// The blocks a grid is made of, with air first, and the index of each voxel's block in
+/// [`VoxelGrid::voxels`] order
+fn block_palette(grid: &VoxelGrid, mapping: &BlockMapping) -> (Vec<String>, Vec<u32>) {
+    let mut names = vec![String::from(AIR)];
+    let mut index: HashMap<&str, u32> = HashMap::from([(AIR, 0)]);
+    let mut by_material: HashMap<MaterialId, u32> = HashMap::new();
+
+    let blocks = grid
+        .voxels()
+        .iter()
+        .map(|voxel| match voxel {
+            None => 0,
+            Some(material) => *by_material.entry(*material).or_insert_with(|| {
+                let block = mapping.block(*material);
+                *index.entry(block).or_insert_with(|| {
+                    names.push(String::from(block));
+                    names.len() as u32 - 1
+                })
+            }),
+        })
+        .collect();
+
+    (names, blocks)
+}
+
+/// Split a block state like `minecraft:oak_log[axis=y]` into the block's name and its
+/// properties
+fn block_state(state: &str) -> (&str, Vec<(&str, &str)>) {
+    let (name, properties) = match state.split_once('[') {
+        Some((name, rest)) => (name, rest.trim_end_matches(']')),
+        None => (state, ""),
+    };
+    let properties = properties
+        .split(',')
+        .filter_map(|p| p.split_once('='))
+        .map(|(k, v)| (k.trim(), v.trim()))
+        .collect();
+    (name, properties)
+}
+
+/// A block state as the compound of its name and properties Java Edition stores in palettes,
+/// with the properties in order of their names
+fn block_state_tag(state: &str) -> Tag {
+    let (name, mut properties) = block_state(state);
+    properties.sort();
+    let mut entries = vec![("Name", Tag::String(String::from(name)))];
+    if !properties.is_empty() {
+        entries.push((
+            "Properties",
+            Tag::compound(properties.into_iter().map(|(k, v)| (k, Tag::String(String::from(v))))),
+        ));
+    }
+    Tag::compound(entries)
+}
+
+/// Where the grid's lowest corner is in whole blocks
+fn block_position(grid: &VoxelGrid) -> [i32; 3] {
+    (grid.min / grid.scale).round().to_array().map(|c| c as i32)
+}
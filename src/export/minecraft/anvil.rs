@@ -0,0 +1,599 @@
+//! Java Edition worlds, by writing blocks straight into the region files (`.mca`) in a world's
+//! `region` folder, for statues too big for schematic tools to paste.
+//!
+//! A region file holds 32 by 32 chunks, each a zlib compressed NBT compound found through a
+//! table at the start of the file. Chunks are split into sections 16 blocks high, each with a
+//! palette of block states and the index into it of every block, going along x first, then z,
+//! then y. Indices are packed into longs using as few bits as the palette needs (at least 4),
+//! with no index split over two longs.
+//!
+//! Only worlds saved by Minecraft 1.18 or later can be written to.
+
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, BufWriter, Write},
+    ops::Range,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use flate2::{
+    read::{GzDecoder, ZlibDecoder},
+    write::ZlibEncoder,
+    Compression,
+};
+
+use crate::grid::VoxelGrid;
+
+use super::{
+    block_palette, block_state_tag,
+    nbt::{read_nbt, write_nbt, Endian, Tag},
+    BlockMapping, AIR, DATA_VERSION,
+};
+
+/// Data version of Minecraft 1.18, the first with chunks in the format written here
+const MIN_DATA_VERSION: i32 = 2860;
+
+const SECTOR: usize = 4096;
+const ZLIB: u8 = 2;
+
+/// Where in a world to put a grid
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnvilSettings {
+    /// World position of the block at the grid's lowest corner
+    pub origin: [i32; 3],
+    /// Replace blocks where the grid is empty with air, rather than leaving what's already there
+    pub replace_with_air: bool,
+    /// Lowest block in the world, -64 in the overworld and 0 in the nether and the end
+    pub min_y: i32,
+    /// Height of the world, 384 in the overworld and 256 in the nether and the end
+    pub height: i32,
+}
+
+impl Default for AnvilSettings {
+    fn default() -> Self {
+        AnvilSettings {
+            origin: [0, 0, 0],
+            replace_with_air: false,
+            min_y: -64,
+            height: 384,
+        }
+    }
+}
+
+/// Place a grid into the region files in `dir`, with each voxel placed as the block its material
+/// maps to. Chunks already in the files keep all of their other blocks, and any that don't exist
+/// yet are made empty apart from the grid, so the area around it stays empty rather than being
+/// generated when the world is opened.
+///
+/// The heightmaps and light of the changed chunks are left for Minecraft to work out when they
+/// are next loaded. The world shouldn't be open in Minecraft while its files are written.
+pub fn write_region_files(grid: &VoxelGrid, blocks: &BlockMapping, settings: &AnvilSettings, dir: &Path) -> io::Result<()> {
+    if settings.min_y % 16 != 0 || settings.height <= 0 || settings.height % 16 != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "The world's lowest block and height have to be multiples of 16",
+        ));
+    }
+    // Blocks covered by the grid along each axis
+    let mut ranges = Vec::with_capacity(3);
+    for axis in 0..3 {
+        let start = settings.origin[axis] as i64;
+        let end = start + grid.size[axis] as i64;
+        if end > i32::MAX as i64 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "The grid goes past the edge of the world"));
+        }
+        ranges.push(start as i32..end as i32);
+    }
+    if ranges.iter().any(Range::is_empty) {
+        return Ok(());
+    }
+    if ranges[1].start < settings.min_y || ranges[1].end as i64 > settings.min_y as i64 + settings.height as i64 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "The grid goes above or below the world's height",
+        ));
+    }
+
+    let (names, indices) = block_palette(grid, blocks);
+    let paste = Paste {
+        grid,
+        settings,
+        indices,
+        states: names.iter().map(|name| block_state_tag(name)).collect(),
+    };
+
+    fs::create_dir_all(dir)?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i32);
+    let chunks = |range: &Range<i32>| (range.start >> 4)..=((range.end - 1) >> 4);
+    let (chunks_x, chunks_z) = (chunks(&ranges[0]), chunks(&ranges[2]));
+
+    for region_x in (chunks_x.start() >> 5)..=(chunks_x.end() >> 5) {
+        for region_z in (chunks_z.start() >> 5)..=(chunks_z.end() >> 5) {
+            let path = dir.join(format!("r.{}.{}.mca", region_x, region_z));
+            let mut region = match fs::read(&path) {
+                Ok(bytes) => Region::read(&bytes)?,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Region::new(),
+                Err(e) => return Err(e),
+            };
+
+            let in_region = |chunks: &std::ops::RangeInclusive<i32>, region: i32| {
+                (*chunks.start()).max(region * 32)..=(*chunks.end()).min(region * 32 + 31)
+            };
+            for chunk_x in in_region(&chunks_x, region_x) {
+                for chunk_z in in_region(&chunks_z, region_z) {
+                    let i = ((chunk_x & 31) + (chunk_z & 31) * 32) as usize;
+                    let mut chunk = match &region.chunks[i] {
+                        Some(data) => decode_chunk(data)?,
+                        None => new_chunk(chunk_x, chunk_z, settings),
+                    };
+                    paste.apply_to(&mut chunk, chunk_x, chunk_z)?;
+                    region.chunks[i] = Some(encode_chunk(&chunk)?);
+                    region.timestamps[i] = now;
+                }
+            }
+
+            // Written next to the old file first so a failed write doesn't lose the region
+            let temp = path.with_extension("mca.tmp");
+            let mut w = BufWriter::new(fs::File::create(&temp)?);
+            region.write(&mut w)?;
+            w.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+            fs::rename(temp, path)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The chunks of a region file, each still compressed, and when they were last saved
+struct Region {
+    /// Each chunk's compression type followed by its data
+    chunks: Vec<Option<Vec<u8>>>,
+    timestamps: Vec<i32>,
+}
+
+impl Region {
+    fn new() -> Region {
+        Region {
+            chunks: vec![None; 1024],
+            timestamps: vec![0; 1024],
+        }
+    }
+
+    fn read(bytes: &[u8]) -> io::Result<Region> {
+        let mut region = Region::new();
+        if bytes.is_empty() {
+            return Ok(region);
+        }
+        if bytes.len() < 2 * SECTOR {
+            return Err(invalid("Region file is cut short"));
+        }
+
+        for i in 0..1024 {
+            let location = u32::from_be_bytes(bytes[i * 4..i * 4 + 4].try_into().unwrap());
+            let start = (location >> 8) as usize * SECTOR;
+            if start == 0 {
+                continue;
+            }
+            let len = bytes
+                .get(start..start + 4)
+                .map(|l| u32::from_be_bytes(l.try_into().unwrap()) as usize)
+                .ok_or_else(|| invalid("Region file is cut short"))?;
+            let data = bytes
+                .get(start + 4..start + 4 + len)
+                .ok_or_else(|| invalid("Region file is cut short"))?;
+            region.chunks[i] = Some(data.to_vec());
+            region.timestamps[i] = i32::from_be_bytes(bytes[SECTOR + i * 4..SECTOR + i * 4 + 4].try_into().unwrap());
+        }
+
+        Ok(region)
+    }
+
+    /// Write the chunks one after another, each starting on a new sector
+    fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let mut locations = Vec::with_capacity(SECTOR);
+        let mut sector = 2;
+        for chunk in &self.chunks {
+            let sectors = chunk.as_ref().map_or(0, |data| (4 + data.len()).div_ceil(SECTOR));
+            if sectors > u8::MAX as usize {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "Chunk is too big for a region file"));
+            }
+            let location = if sectors == 0 { 0 } else { (sector << 8 | sectors) as u32 };
+            locations.extend(location.to_be_bytes());
+            sector += sectors;
+        }
+        w.write_all(&locations)?;
+        for timestamp in &self.timestamps {
+            w.write_all(&timestamp.to_be_bytes())?;
+        }
+
+        for data in self.chunks.iter().flatten() {
+            w.write_all(&(data.len() as u32).to_be_bytes())?;
+            w.write_all(data)?;
+            let padding = (4 + data.len()).next_multiple_of(SECTOR) - (4 + data.len());
+            w.write_all(&vec![0; padding])?;
+        }
+        w.flush()
+    }
+}
+
+fn decode_chunk(data: &[u8]) -> io::Result<Tag> {
+    let (compression, mut data) = match data.split_first() {
+        Some((compression, data)) => (*compression, data),
+        None => return Err(invalid("Empty chunk in region file")),
+    };
+    let (_, chunk) = match compression {
+        1 => read_nbt(&mut GzDecoder::new(data), Endian::Big)?,
+        2 => read_nbt(&mut ZlibDecoder::new(data), Endian::Big)?,
+        3 => read_nbt(&mut data, Endian::Big)?,
+        _ if compression & 128 != 0 => return Err(invalid("Chunks stored outside of their region file aren't supported")),
+        _ => return Err(invalid("Unsupported chunk compression")),
+    };
+    Ok(chunk)
+}
+
+fn encode_chunk(chunk: &Tag) -> io::Result<Vec<u8>> {
+    let mut zlib = ZlibEncoder::new(vec![ZLIB], Compression::default());
+    write_nbt("", chunk, Endian::Big, &mut zlib)?;
+    zlib.finish()
+}
+
+/// A chunk with nothing in it
+fn new_chunk(chunk_x: i32, chunk_z: i32, settings: &AnvilSettings) -> Tag {
+    let bottom = settings.min_y >> 4;
+    let sections = (bottom..bottom + settings.height / 16).map(new_section).collect();
+    Tag::compound([
+        ("DataVersion", Tag::Int(DATA_VERSION)),
+        ("xPos", Tag::Int(chunk_x)),
+        ("yPos", Tag::Int(bottom)),
+        ("zPos", Tag::Int(chunk_z)),
+        ("Status", Tag::String(String::from("minecraft:full"))),
+        ("LastUpdate", Tag::Long(0)),
+        ("InhabitedTime", Tag::Long(0)),
+        ("sections", Tag::List(sections)),
+        ("block_entities", Tag::List(Vec::new())),
+        ("Heightmaps", Tag::Compound(Vec::new())),
+        ("fluid_ticks", Tag::List(Vec::new())),
+        ("block_ticks", Tag::List(Vec::new())),
+    ])
+}
+
+/// A section of only air
+fn new_section(y: i32) -> Tag {
+    Tag::compound([
+        ("Y", Tag::Byte(y as i8)),
+        ("block_states", Tag::compound([("palette", Tag::List(vec![block_state_tag(AIR)]))])),
+        (
+            "biomes",
+            Tag::compound([("palette", Tag::List(vec![Tag::String(String::from("minecraft:plains"))]))]),
+        ),
+    ])
+}
+
+/// Blocks of a section, unpacked
+struct Section {
+    palette: Vec<Tag>,
+    blocks: Vec<u16>,
+    /// Index in the palette of each of the grid's block states, once they've been used
+    lookup: HashMap<u32, u16>,
+    changed: bool,
+}
+
+impl Section {
+    fn read(section: &Tag) -> io::Result<Section> {
+        let states = section.get("block_states");
+        let mut palette = match states.and_then(|s| s.get("palette")) {
+            Some(Tag::List(palette)) if !palette.is_empty() => palette.clone(),
+            _ => return Err(invalid("Chunk section is missing its palette")),
+        };
+        // So they can be compared with the grid's block states
+        for state in &mut palette {
+            if let Some(Tag::Compound(properties)) = state.get_mut("Properties") {
+                properties.sort_by(|(a, _), (b, _)| a.cmp(b));
+            }
+        }
+
+        let blocks = match states.and_then(|s| s.get("data")) {
+            Some(Tag::LongArray(data)) => unpack(data, bits_for(palette.len(), 4), 4096),
+            _ => vec![0; 4096],
+        };
+        if blocks.iter().any(|b| *b as usize >= palette.len()) {
+            return Err(invalid("Chunk section has blocks missing from its palette"));
+        }
+
+        Ok(Section {
+            palette,
+            blocks,
+            lookup: HashMap::new(),
+            changed: false,
+        })
+    }
+
+    /// The section's block states, without any that are no longer used
+    fn block_states(&self) -> Tag {
+        let mut used = vec![false; self.palette.len()];
+        for b in &self.blocks {
+            used[*b as usize] = true;
+        }
+        let mut remap = vec![0; self.palette.len()];
+        let mut palette = Vec::new();
+        for (i, state) in self.palette.iter().enumerate() {
+            if used[i] {
+                remap[i] = palette.len() as u16;
+                palette.push(state.clone());
+            }
+        }
+
+        let bits = bits_for(palette.len(), 4);
+        let mut entries = vec![("palette", Tag::List(palette))];
+        if remap.iter().any(|r| *r != 0) {
+            entries.push(("data", Tag::LongArray(pack(self.blocks.iter().map(|b| remap[*b as usize] as u64), bits))));
+        }
+        Tag::compound(entries)
+    }
+}
+
+struct Paste<'a> {
+    grid: &'a VoxelGrid,
+    settings: &'a AnvilSettings,
+    /// Index in `states` of each voxel's block
+    indices: Vec<u32>,
+    states: Vec<Tag>,
+}
+
+impl Paste<'_> {
+    /// The index in `states` of the block to put at a world position, if there is one
+    fn block_at(&self, x: i32, y: i32, z: i32) -> Option<u32> {
+        let [ox, oy, oz] = self.settings.origin;
+        let [sx, sy, sz] = self.grid.size;
+        let (x, y, z) = ((x as i64 - ox as i64) as usize, (y as i64 - oy as i64) as usize, (z as i64 - oz as i64) as usize);
+        if x >= sx || y >= sy || z >= sz {
+            return None;
+        }
+        let block = self.indices[(x * sy + y) * sz + z];
+        (block != 0 || self.settings.replace_with_air).then_some(block)
+    }
+
+    fn apply_to(&self, chunk: &mut Tag, chunk_x: i32, chunk_z: i32) -> io::Result<()> {
+        if chunk.get("DataVersion").and_then(Tag::as_int).unwrap_or(0) < MIN_DATA_VERSION {
+            return Err(invalid(&format!(
+                "Chunk {}, {} was saved before Minecraft 1.18, open the world in a newer version first",
+                chunk_x, chunk_z
+            )));
+        }
+        let bottom = chunk.get("yPos").and_then(Tag::as_int).unwrap_or(self.settings.min_y >> 4);
+        let sections = match chunk.get_mut("sections") {
+            Some(Tag::List(sections)) => sections,
+            _ => return Err(invalid("Chunk is missing its sections")),
+        };
+
+        // Sections of the chunk's height that the grid reaches into
+        let [_, oy, _] = self.settings.origin;
+        let mut unpacked = Vec::new();
+        for y in bottom..bottom + self.settings.height / 16 {
+            if y * 16 + 16 <= oy || y * 16 >= oy + self.grid.size[1] as i32 {
+                continue;
+            }
+            let i = match sections.iter().position(|s| s.get("Y").and_then(Tag::as_int) == Some(y)) {
+                Some(i) => i,
+                None => {
+                    sections.push(new_section(y));
+                    sections.len() - 1
+                }
+            };
+            unpacked.push((i, y * 16, Section::read(&sections[i])?));
+        }
+
+        let [x0, z0] = [chunk_x * 16, chunk_z * 16];
+        for (_, section_y, section) in &mut unpacked {
+            let section_y = *section_y;
+            for (i, block) in section.blocks.iter_mut().enumerate() {
+                let (x, y, z) = (x0 + (i & 15) as i32, section_y + (i >> 8) as i32, z0 + (i >> 4 & 15) as i32);
+                let state = match self.block_at(x, y, z) {
+                    Some(state) => state,
+                    None => continue,
+                };
+                let palette = &mut section.palette;
+                *block = *section.lookup.entry(state).or_insert_with(|| {
+                    let tag = &self.states[state as usize];
+                    match palette.iter().position(|p| p == tag) {
+                        Some(p) => p as u16,
+                        None => {
+                            palette.push(tag.clone());
+                            palette.len() as u16 - 1
+                        }
+                    }
+                });
+                section.changed = true;
+            }
+        }
+
+        for (i, _, section) in &unpacked {
+            if section.changed {
+                let tag = &mut sections[*i];
+                tag.set("block_states", section.block_states());
+                tag.remove("BlockLight");
+                tag.remove("SkyLight");
+            }
+        }
+
+        // Blocks placed over have lost whatever was stored with them, like a chest's items
+        if let Some(Tag::List(entities)) = chunk.get_mut("block_entities") {
+            entities.retain(|e| {
+                let pos = ["x", "y", "z"].map(|c| e.get(c).and_then(Tag::as_int));
+                match pos {
+                    [Some(x), Some(y), Some(z)] => self.block_at(x, y, z).is_none(),
+                    _ => true,
+                }
+            });
+        }
+
+        // Minecraft works out any heightmaps a chunk is missing when it's loaded, from which
+        // blocks block motion, hold fluid or are leaves, so they're dropped rather than guessed
+        chunk.set("Heightmaps", Tag::Compound(Vec::new()));
+        chunk.set("isLightOn", Tag::Byte(0));
+
+        Ok(())
+    }
+}
+
+/// Bits each value takes up, for values below `len`
+fn bits_for(len: usize, min: usize) -> usize {
+    ((usize::BITS - (len.max(1) - 1).leading_zeros()) as usize).max(min)
+}
+
+fn pack(values: impl Iterator<Item = u64>, bits: usize) -> Vec<i64> {
+    let per_long = 64 / bits;
+    let mut longs = Vec::new();
+    for (i, value) in values.enumerate() {
+        if i % per_long == 0 {
+            longs.push(0);
+        }
+        *longs.last_mut().unwrap() |= (value << (i % per_long * bits)) as i64;
+    }
+    longs
+}
+
+fn unpack(longs: &[i64], bits: usize, len: usize) -> Vec<u16> {
+    let per_long = 64 / bits;
+    let mask = (1 << bits) - 1;
+    (0..len)
+        .map(|i| {
+            let long = longs.get(i / per_long).copied().unwrap_or(0) as u64;
+            (long >> (i % per_long * bits) & mask) as u16
+        })
+        .collect()
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, process};
+
+    use glam::Vec3;
+
+    use super::*;
+
+    /// An empty folder to write region files into, unique to the test
+    fn region_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("voxeliser-anvil-{}-{}", name, process::id()));
+        fs::remove_dir_all(&dir).ok();
+        dir
+    }
+
+    /// Name of the block at a world position, read back out of the region files
+    fn block_name(dir: &Path, x: i32, y: i32, z: i32) -> String {
+        let bytes = fs::read(dir.join(format!("r.{}.{}.mca", x >> 9, z >> 9))).unwrap();
+        let region = Region::read(&bytes).unwrap();
+        let data = region.chunks[((x >> 4 & 31) + (z >> 4 & 31) * 32) as usize].as_ref().unwrap();
+        let chunk = decode_chunk(data).unwrap();
+        assert_eq!(chunk.get("xPos").and_then(Tag::as_int), Some(x >> 4));
+        assert_eq!(chunk.get("zPos").and_then(Tag::as_int), Some(z >> 4));
+        let section = match chunk.get("sections") {
+            Some(Tag::List(sections)) => sections.iter().find(|s| s.get("Y").and_then(Tag::as_int) == Some(y >> 4)).unwrap(),
+            other => panic!("No sections, found {:?}", other),
+        };
+        let section = Section::read(section).unwrap();
+        let block = section.blocks[((y & 15) << 8 | (z & 15) << 4 | (x & 15)) as usize];
+        match section.palette[block as usize].get("Name") {
+            Some(Tag::String(name)) => name.clone(),
+            other => panic!("Block state without a name, found {:?}", other),
+        }
+    }
+
+    fn blocks() -> BlockMapping {
+        let mut blocks = BlockMapping::default();
+        blocks.set(0, "minecraft:stone");
+        blocks.set(1, "minecraft:glass");
+        blocks
+    }
+
+    #[test]
+    fn pasted_blocks_read_back() {
+        // Across two regions and two sections
+        let dir = region_dir("paste");
+        let mut grid = VoxelGrid::new(Vec3::ZERO, 1.0, [20, 3, 18]);
+        for x in 0..20 {
+            for y in 0..3 {
+                for z in 0..18 {
+                    if (x + y + z) % 3 != 0 {
+                        grid.set(x, y, z, Some(((x + z) % 2) as u16));
+                    }
+                }
+            }
+        }
+        let settings = AnvilSettings {
+            origin: [-5, -2, 10],
+            ..AnvilSettings::default()
+        };
+        let blocks = blocks();
+        write_region_files(&grid, &blocks, &settings, &dir).unwrap();
+
+        for x in 0..20 {
+            for y in 0..3 {
+                for z in 0..18 {
+                    let name = block_name(&dir, x as i32 - 5, y as i32 - 2, z as i32 + 10);
+                    let block = grid.get(x, y, z).map_or(AIR, |m| blocks.block(m));
+                    assert_eq!(name, block, "block {} {} {}", x, y, z);
+                }
+            }
+        }
+        assert_eq!(block_name(&dir, -6, 0, 12), AIR);
+        assert_eq!(block_name(&dir, 0, 1, 12), AIR);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn pasting_keeps_the_other_blocks() {
+        let dir = region_dir("keep");
+        let mut grid = VoxelGrid::new(Vec3::ZERO, 1.0, [12, 6, 12]);
+        for x in 0..4 {
+            for y in 0..4 {
+                for z in 0..4 {
+                    grid.set(x, y, z, Some(0));
+                }
+            }
+        }
+        grid.set(11, 5, 11, Some(0));
+        let blocks = blocks();
+        write_region_files(&grid, &blocks, &AnvilSettings::default(), &dir).unwrap();
+
+        // Only the filled voxel replaces what's there
+        let mut grid = VoxelGrid::new(Vec3::ZERO, 1.0, [2, 1, 1]);
+        grid.set(0, 0, 0, Some(1));
+        let settings = AnvilSettings {
+            origin: [2, 0, 2],
+            ..AnvilSettings::default()
+        };
+        write_region_files(&grid, &blocks, &settings, &dir).unwrap();
+
+        for x in 0..4 {
+            for y in 0..4 {
+                for z in 0..4 {
+                    let block = if [x, y, z] == [2, 0, 2] { "minecraft:glass" } else { "minecraft:stone" };
+                    assert_eq!(block_name(&dir, x, y, z), block, "block {} {} {}", x, y, z);
+                }
+            }
+        }
+        assert_eq!(block_name(&dir, 11, 5, 11), "minecraft:stone");
+        assert_eq!(block_name(&dir, 8, 5, 8), AIR);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn packing_round_trips() {
+        for bits in [4, 5, 6] {
+            let values: Vec<u16> = (0..4096).map(|i| (i * 7 % (1 << bits)) as u16).collect();
+            let longs = pack(values.iter().map(|v| *v as u64), bits);
+            // Values don't span longs, so the top bits of each are left unused
+            assert_eq!(longs.len(), 4096usize.div_ceil(64 / bits));
+            assert_eq!(unpack(&longs, bits, 4096), values, "{} bits", bits);
+        }
+    }
+}
@@ -0,0 +1,203 @@
+//! Litematica schematics (`.litematic`), shown as an overlay in the world to build from.
+//!
+//! A gzipped NBT compound with some metadata and one or more regions. Each region has a palette
+//! of block states and the index into the palette of every block, going along x first, then z,
+//! then y. Indices are packed into longs using as few bits as the palette needs (at least 2),
+//! with an index carried over into the next long when it doesn't fit.
+
+use std::{
+    io::{self, Write},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use flate2::{write::GzEncoder, Compression};
+
+use crate::grid::VoxelGrid;
+
+use super::{
+    block_palette, block_state_tag,
+    nbt::{write_nbt, Endian, Tag},
+    BlockMapping, DATA_VERSION,
+};
+
+/// Litematica's format version for Minecraft 1.20
+const VERSION: i32 = 6;
+const SUB_VERSION: i32 = 1;
+
+/// Details shown in Litematica's schematic browser
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LitematicMetadata {
+    /// Name of the schematic, also used for its one region
+    pub name: String,
+    pub author: String,
+    pub description: String,
+}
+
+/// Write a grid as a Litematica schematic with one region, each voxel placed as the block its
+/// material maps to
+pub fn write_litematic<W: Write>(grid: &VoxelGrid, blocks: &BlockMapping, metadata: &LitematicMetadata, w: &mut W) -> io::Result<()> {
+    let too_big = || io::Error::new(io::ErrorKind::InvalidInput, "Grid is too big for a Litematica schematic");
+    let int = |n: usize| i32::try_from(n).map_err(|_| too_big());
+    let [sx, sy, sz] = grid.size;
+    // The volume and block count are written as ints too, not just the size
+    let size = vec3([int(sx)?, int(sy)?, int(sz)?]);
+    let volume = sx * sy * sz;
+    let total_volume = int(volume)?;
+    let total_blocks = int(grid.count())?;
+
+    let (names, indices) = block_palette(grid, blocks);
+    let bits = bits_for(names.len());
+    let mut states = vec![0u64; volume.checked_mul(bits).ok_or_else(too_big)?.div_ceil(64)];
+    let mut i = 0;
+    for y in 0..sy {
+        for z in 0..sz {
+            for x in 0..sx {
+                pack(&mut states, bits, i, indices[(x * sy + y) * sz + z] as u64);
+                i += 1;
+            }
+        }
+    }
+
+    let palette = names.iter().map(|name| block_state_tag(name)).collect();
+
+    let region = Tag::compound([
+        ("Position", vec3([0, 0, 0])),
+        ("Size", size.clone()),
+        ("BlockStatePalette", Tag::List(palette)),
+        ("BlockStates", Tag::LongArray(states.into_iter().map(|l| l as i64).collect())),
+        ("TileEntities", Tag::List(Vec::new())),
+        ("Entities", Tag::List(Vec::new())),
+        ("PendingBlockTicks", Tag::List(Vec::new())),
+        ("PendingFluidTicks", Tag::List(Vec::new())),
+    ]);
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as i64);
+    let root = Tag::compound([
+        ("Version", Tag::Int(VERSION)),
+        ("SubVersion", Tag::Int(SUB_VERSION)),
+        ("MinecraftDataVersion", Tag::Int(DATA_VERSION)),
+        (
+            "Metadata",
+            Tag::compound([
+                ("Name", Tag::String(metadata.name.clone())),
+                ("Author", Tag::String(metadata.author.clone())),
+                ("Description", Tag::String(metadata.description.clone())),
+                ("RegionCount", Tag::Int(1)),
+                ("TotalBlocks", Tag::Int(total_blocks)),
+                ("TotalVolume", Tag::Int(total_volume)),
+                ("EnclosingSize", size),
+                ("TimeCreated", Tag::Long(now)),
+                ("TimeModified", Tag::Long(now)),
+            ]),
+        ),
+        ("Regions", Tag::Compound(vec![(metadata.name.clone(), region)])),
+    ]);
+
+    let mut gz = GzEncoder::new(w, Compression::default());
+    write_nbt("", &root, Endian::Big, &mut gz)?;
+    gz.finish()?.flush()
+}
+
+/// Bits each palette index takes up
+fn bits_for(palette_len: usize) -> usize {
+    (usize::BITS - (palette_len.max(1) - 1).leading_zeros()).max(2) as usize
+}
+
+/// Put the `i`th value into the longs, lowest bits first, spilling into the next long if it
+/// runs over the end of one
+fn pack(longs: &mut [u64], bits: usize, i: usize, value: u64) {
+    let start = i * bits;
+    let (long, offset) = (start / 64, start % 64);
+    longs[long] |= value << offset;
+    if offset + bits > 64 {
+        longs[long + 1] |= value >> (64 - offset);
+    }
+}
+
+fn vec3([x, y, z]: [i32; 3]) -> Tag {
+    Tag::compound([("x", Tag::Int(x)), ("y", Tag::Int(y)), ("z", Tag::Int(z))])
+}
+
+#[cfg(test)]
+mod tests {
+    use flate2::read::GzDecoder;
+    use glam::Vec3;
+
+    use super::*;
+    use crate::export::minecraft::nbt::read_nbt;
+
+    #[test]
+    fn block_states_and_metadata() {
+        // Four blocks and air need 3 bits each, so some indices are split over two longs
+        let mut grid = VoxelGrid::new(Vec3::ZERO, 1.0, [5, 3, 4]);
+        for x in 0..5 {
+            for y in 0..3 {
+                for z in 0..4 {
+                    if (x + y + z) % 5 != 0 {
+                        grid.set(x, y, z, Some(((x + 2 * z) % 4) as u16));
+                    }
+                }
+            }
+        }
+        let mut blocks = BlockMapping::default();
+        for (m, block) in ["minecraft:oak_log[axis=y]", "minecraft:dirt", "minecraft:glass", "minecraft:stone"].iter().enumerate() {
+            blocks.set(m as u16, *block);
+        }
+        let metadata = LitematicMetadata {
+            name: String::from("statue"),
+            author: String::from("someone"),
+            description: String::from("a test"),
+        };
+
+        let mut data = Vec::new();
+        write_litematic(&grid, &blocks, &metadata, &mut data).unwrap();
+        let (_, root) = read_nbt(&mut GzDecoder::new(&data[..]), Endian::Big).unwrap();
+
+        let meta = root.get("Metadata").unwrap();
+        assert_eq!(meta.get("Name"), Some(&Tag::String(String::from("statue"))));
+        assert_eq!(meta.get("Author"), Some(&Tag::String(String::from("someone"))));
+        assert_eq!(meta.get("Description"), Some(&Tag::String(String::from("a test"))));
+        assert_eq!(meta.get("TotalVolume"), Some(&Tag::Int(60)));
+        assert_eq!(meta.get("TotalBlocks"), Some(&Tag::Int(grid.count() as i32)));
+        assert_eq!(meta.get("EnclosingSize"), Some(&vec3([5, 3, 4])));
+
+        let region = root.get("Regions").unwrap().get("statue").unwrap();
+        assert_eq!(region.get("Size"), Some(&vec3([5, 3, 4])));
+        let names: Vec<String> = match region.get("BlockStatePalette") {
+            Some(Tag::List(palette)) => palette
+                .iter()
+                .map(|state| match state.get("Name") {
+                    Some(Tag::String(name)) => name.clone(),
+                    other => panic!("Block state without a name, found {:?}", other),
+                })
+                .collect(),
+            other => panic!("No palette, found {:?}", other),
+        };
+        assert_eq!(names.len(), 5);
+        assert_eq!(names[0], "minecraft:air");
+        let longs: Vec<u64> = match region.get("BlockStates") {
+            Some(Tag::LongArray(longs)) => longs.iter().map(|l| *l as u64).collect(),
+            other => panic!("No block states, found {:?}", other),
+        };
+        assert_eq!(longs.len(), (60 * 3usize).div_ceil(64));
+
+        let mut i = 0;
+        for y in 0..3 {
+            for z in 0..4 {
+                for x in 0..5 {
+                    let (long, offset) = (i * 3 / 64, i * 3 % 64);
+                    let mut index = longs[long] >> offset;
+                    if offset + 3 > 64 {
+                        index |= longs[long + 1] << (64 - offset);
+                    }
+                    let name = &names[(index & 0b111) as usize];
+                    let block = grid.get(x, y, z).map_or("minecraft:air", |m| blocks.block(m));
+                    assert_eq!(name, block.split('[').next().unwrap(), "block {} {} {}", x, y, z);
+                    i += 1;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,169 @@
+//! Bedrock Edition structures (`.mcstructure`), loaded in game with a structure block.
+//!
+//! An uncompressed little endian NBT compound with a palette of block states and the index into
+//! the palette of every block, going along z first, then y, then x, the same order as
+//! [`VoxelGrid::voxels`]. Blocks can have a second layer of indices for things like water inside
+//! them, which is always left empty.
+
+use std::io::{self, Write};
+
+use crate::grid::VoxelGrid;
+
+use super::{
+    block_palette, block_position, block_state,
+    nbt::{write_nbt, Endian, Tag},
+    BlockMapping,
+};
+
+/// Version of the block states written, Bedrock 1.18.10
+const BLOCK_VERSION: i32 = 17959425;
+
+/// Write a grid as a Bedrock structure with each voxel placed as the block its material maps to.
+/// The structure's world origin is where the grid's lowest corner is in whole voxels.
+///
+/// Block names are the same as Java Edition's for most blocks, but some are different, so the
+/// mapping should use Bedrock's names. States given as `true` or `false` are written as bytes and
+/// whole numbers as ints, the way Bedrock stores them.
+pub fn write_mcstructure<W: Write>(grid: &VoxelGrid, blocks: &BlockMapping, w: &mut W) -> io::Result<()> {
+    if grid.size.iter().any(|s| *s > i32::MAX as usize) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Grid is too big for a structure"));
+    }
+
+    let (names, indices) = block_palette(grid, blocks);
+    let palette = names
+        .iter()
+        .map(|name| {
+            let (name, states) = block_state(name);
+            Tag::compound([
+                ("name", Tag::String(String::from(name))),
+                ("states", Tag::compound(states.into_iter().map(|(k, v)| (k, state_value(v))))),
+                ("version", Tag::Int(BLOCK_VERSION)),
+            ])
+        })
+        .collect();
+
+    let layer = Tag::List(indices.iter().map(|i| Tag::Int(*i as i32)).collect());
+    let empty_layer = Tag::List(vec![Tag::Int(-1); indices.len()]);
+    let ints = |v: [i32; 3]| Tag::List(v.into_iter().map(Tag::Int).collect());
+
+    let root = Tag::compound([
+        ("format_version", Tag::Int(1)),
+        ("size", ints(grid.size.map(|s| s as i32))),
+        (
+            "structure",
+            Tag::compound([
+                ("block_indices", Tag::List(vec![layer, empty_layer])),
+                ("entities", Tag::List(Vec::new())),
+                (
+                    "palette",
+                    Tag::compound([(
+                        "default",
+                        Tag::compound([
+                            ("block_palette", Tag::List(palette)),
+                            ("block_position_data", Tag::Compound(Vec::new())),
+                        ]),
+                    )]),
+                ),
+            ]),
+        ),
+        ("structure_world_origin", ints(block_position(grid))),
+    ]);
+
+    write_nbt("", &root, Endian::Little, w)?;
+    w.flush()
+}
+
+fn state_value(value: &str) -> Tag {
+    match value {
+        "true" => Tag::Byte(1),
+        "false" => Tag::Byte(0),
+        _ => match value.parse() {
+            Ok(i) => Tag::Int(i),
+            Err(_) => Tag::String(String::from(value)),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+
+    use super::*;
+    use crate::export::minecraft::{nbt::read_nbt, AIR};
+
+    #[test]
+    fn size_palette_and_block_indices() {
+        let mut grid = VoxelGrid::new(Vec3::new(-2.0, 0.0, 5.0), 1.0, [3, 4, 5]);
+        for x in 0..3 {
+            for y in 0..4 {
+                for z in 0..5 {
+                    if (x + y + z) % 4 != 0 {
+                        grid.set(x, y, z, Some(((x + y * 2 + z) % 2) as u16));
+                    }
+                }
+            }
+        }
+        let mut blocks = BlockMapping::default();
+        blocks.set(0, "minecraft:stone");
+        blocks.set(1, "minecraft:stone_block_slab[top_slot_bit=true,age=3,wood_type=oak]");
+
+        let mut data = Vec::new();
+        write_mcstructure(&grid, &blocks, &mut data).unwrap();
+        let (_, root) = read_nbt(&mut &data[..], Endian::Little).unwrap();
+
+        let ints = |v: [i32; 3]| Some(Tag::List(v.into_iter().map(Tag::Int).collect()));
+        assert_eq!(root.get("size").cloned(), ints([3, 4, 5]));
+        assert_eq!(root.get("structure_world_origin").cloned(), ints([-2, 0, 5]));
+
+        let structure = root.get("structure").unwrap();
+        let palette = match structure.get("palette").and_then(|p| p.get("default")).and_then(|d| d.get("block_palette")) {
+            Some(Tag::List(palette)) => palette,
+            other => panic!("No palette, found {:?}", other),
+        };
+        let name = |i: i32| match palette[i as usize].get("name") {
+            Some(Tag::String(name)) => name.as_str(),
+            other => panic!("Block without a name, found {:?}", other),
+        };
+        let slab = palette
+            .iter()
+            .find(|b| b.get("name") == Some(&Tag::String(String::from("minecraft:stone_block_slab"))))
+            .unwrap();
+        let states = slab.get("states").unwrap();
+        assert_eq!(states.get("top_slot_bit"), Some(&Tag::Byte(1)));
+        assert_eq!(states.get("age"), Some(&Tag::Int(3)));
+        assert_eq!(states.get("wood_type"), Some(&Tag::String(String::from("oak"))));
+
+        let layers = match structure.get("block_indices") {
+            Some(Tag::List(layers)) => layers,
+            other => panic!("No block indices, found {:?}", other),
+        };
+        assert_eq!(layers.len(), 2);
+        let (first, second) = match (&layers[0], &layers[1]) {
+            (Tag::List(first), Tag::List(second)) => (first, second),
+            other => panic!("Block indices aren't lists, found {:?}", other),
+        };
+        assert_eq!(first.len(), 60);
+        assert!(second.len() == 60 && second.iter().all(|i| *i == Tag::Int(-1)));
+
+        // Along z first, then y, then x
+        let mut indices = first.iter();
+        for x in 0..3 {
+            for y in 0..4 {
+                for z in 0..5 {
+                    let index = indices.next().and_then(Tag::as_int).unwrap();
+                    let block = grid.get(x, y, z).map_or(AIR, |m| blocks.block(m));
+                    assert_eq!(name(index), block.split('[').next().unwrap(), "block {} {} {}", x, y, z);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn state_values() {
+        assert_eq!(state_value("true"), Tag::Byte(1));
+        assert_eq!(state_value("false"), Tag::Byte(0));
+        assert_eq!(state_value("12"), Tag::Int(12));
+        assert_eq!(state_value("-1"), Tag::Int(-1));
+        assert_eq!(state_value("north"), Tag::String(String::from("north")));
+    }
+}
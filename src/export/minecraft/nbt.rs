@@ -0,0 +1,370 @@
+//! Minecraft's Named Binary Tag format, which most of its files are built from.
+//!
+//! Java Edition writes numbers big endian, Bedrock Edition little endian. Strings are written as
+//! plain UTF-8, which only differs from the modified UTF-8 Java uses for nul characters and
+//! characters outside of the Basic Multilingual Plane.
+
+use std::io::{self, Read, Write};
+
+/// A value in an NBT file
+#[derive(Debug, Clone, PartialEq)]
+pub enum Tag {
+    Byte(i8),
+    Short(i16),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    ByteArray(Vec<u8>),
+    String(String),
+    /// Values that all have to be the same kind of tag
+    List(Vec<Tag>),
+    /// Named values, in the order they're written
+    Compound(Vec<(String, Tag)>),
+    IntArray(Vec<i32>),
+    LongArray(Vec<i64>),
+}
+
+impl Tag {
+    /// A compound of the given names and values
+    pub fn compound<'a>(entries: impl IntoIterator<Item = (&'a str, Tag)>) -> Tag {
+        Tag::Compound(entries.into_iter().map(|(name, tag)| (String::from(name), tag)).collect())
+    }
+
+    /// The value of a compound with the given name
+    pub fn get(&self, name: &str) -> Option<&Tag> {
+        match self {
+            Tag::Compound(entries) => entries.iter().find(|(n, _)| n == name).map(|(_, tag)| tag),
+            _ => None,
+        }
+    }
+
+    /// The value of a compound with the given name, to change
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Tag> {
+        match self {
+            Tag::Compound(entries) => entries.iter_mut().find(|(n, _)| n == name).map(|(_, tag)| tag),
+            _ => None,
+        }
+    }
+
+    /// Set the value of a compound with the given name, replacing any already there. Does
+    /// nothing if this isn't a compound.
+    pub fn set(&mut self, name: &str, tag: Tag) {
+        match self.get_mut(name) {
+            Some(old) => *old = tag,
+            None => {
+                if let Tag::Compound(entries) = self {
+                    entries.push((String::from(name), tag));
+                }
+            }
+        }
+    }
+
+    /// Take the value of a compound with the given name out of it
+    pub fn remove(&mut self, name: &str) -> Option<Tag> {
+        match self {
+            Tag::Compound(entries) => {
+                let i = entries.iter().position(|(n, _)| n == name)?;
+                Some(entries.remove(i).1)
+            }
+            _ => None,
+        }
+    }
+
+    /// The value if this is a byte, short or int
+    pub fn as_int(&self) -> Option<i32> {
+        match self {
+            Tag::Byte(v) => Some(*v as i32),
+            Tag::Short(v) => Some(*v as i32),
+            Tag::Int(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    fn id(&self) -> u8 {
+        match self {
+            Tag::Byte(_) => 1,
+            Tag::Short(_) => 2,
+            Tag::Int(_) => 3,
+            Tag::Long(_) => 4,
+            Tag::Float(_) => 5,
+            Tag::Double(_) => 6,
+            Tag::ByteArray(_) => 7,
+            Tag::String(_) => 8,
+            Tag::List(_) => 9,
+            Tag::Compound(_) => 10,
+            Tag::IntArray(_) => 11,
+            Tag::LongArray(_) => 12,
+        }
+    }
+}
+
+/// Order the bytes of numbers are written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endian {
+    /// Java Edition
+    Big,
+    /// Bedrock Edition
+    Little,
+}
+
+const END: u8 = 0;
+
+/// Deepest tags are read, as deep as Minecraft itself reads them
+const MAX_DEPTH: usize = 512;
+
+/// Write a named root tag, usually a compound
+pub fn write_nbt<W: Write>(name: &str, tag: &Tag, endian: Endian, w: &mut W) -> io::Result<()> {
+    let mut out = Writer { w, endian };
+    out.w.write_all(&[tag.id()])?;
+    out.string(name)?;
+    out.payload(tag)
+}
+
+/// Read a named root tag
+pub fn read_nbt<R: Read>(r: &mut R, endian: Endian) -> io::Result<(String, Tag)> {
+    let mut input = Reader { r, endian };
+    let id = input.u8()?;
+    if id == END {
+        return Err(invalid("Empty NBT file"));
+    }
+    let name = input.string()?;
+    let tag = input.payload(id, 0)?;
+    Ok((name, tag))
+}
+
+struct Writer<'a, W: Write> {
+    w: &'a mut W,
+    endian: Endian,
+}
+
+impl<'a, W: Write> Writer<'a, W> {
+    fn bytes<const N: usize>(&mut self, big: [u8; N], little: [u8; N]) -> io::Result<()> {
+        match self.endian {
+            Endian::Big => self.w.write_all(&big),
+            Endian::Little => self.w.write_all(&little),
+        }
+    }
+
+    fn i16(&mut self, v: i16) -> io::Result<()> {
+        self.bytes(v.to_be_bytes(), v.to_le_bytes())
+    }
+
+    fn i32(&mut self, v: i32) -> io::Result<()> {
+        self.bytes(v.to_be_bytes(), v.to_le_bytes())
+    }
+
+    fn i64(&mut self, v: i64) -> io::Result<()> {
+        self.bytes(v.to_be_bytes(), v.to_le_bytes())
+    }
+
+    fn len(&mut self, len: usize) -> io::Result<()> {
+        let len = i32::try_from(len).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "NBT array is too long"))?;
+        self.i32(len)
+    }
+
+    fn string(&mut self, s: &str) -> io::Result<()> {
+        let len = u16::try_from(s.len()).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "NBT string is too long"))?;
+        self.bytes(len.to_be_bytes(), len.to_le_bytes())?;
+        self.w.write_all(s.as_bytes())
+    }
+
+    fn payload(&mut self, tag: &Tag) -> io::Result<()> {
+        match tag {
+            Tag::Byte(v) => self.w.write_all(&v.to_be_bytes()),
+            Tag::Short(v) => self.i16(*v),
+            Tag::Int(v) => self.i32(*v),
+            Tag::Long(v) => self.i64(*v),
+            Tag::Float(v) => self.bytes(v.to_be_bytes(), v.to_le_bytes()),
+            Tag::Double(v) => self.bytes(v.to_be_bytes(), v.to_le_bytes()),
+            Tag::ByteArray(v) => {
+                self.len(v.len())?;
+                self.w.write_all(v)
+            }
+            Tag::String(v) => self.string(v),
+            Tag::List(v) => {
+                let id = v.first().map_or(END, Tag::id);
+                if v.iter().any(|t| t.id() != id) {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, "NBT list has different kinds of tags"));
+                }
+                self.w.write_all(&[id])?;
+                self.len(v.len())?;
+                v.iter().try_for_each(|t| self.payload(t))
+            }
+            Tag::Compound(v) => {
+                for (name, t) in v {
+                    self.w.write_all(&[t.id()])?;
+                    self.string(name)?;
+                    self.payload(t)?;
+                }
+                self.w.write_all(&[END])
+            }
+            Tag::IntArray(v) => {
+                self.len(v.len())?;
+                v.iter().try_for_each(|i| self.i32(*i))
+            }
+            Tag::LongArray(v) => {
+                self.len(v.len())?;
+                v.iter().try_for_each(|i| self.i64(*i))
+            }
+        }
+    }
+}
+
+struct Reader<'a, R: Read> {
+    r: &'a mut R,
+    endian: Endian,
+}
+
+impl<'a, R: Read> Reader<'a, R> {
+    fn bytes<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        let mut buf = [0; N];
+        self.r.read_exact(&mut buf)?;
+        if self.endian == Endian::Little {
+            buf.reverse();
+        }
+        // Always big endian from here
+        Ok(buf)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.bytes::<1>()?[0])
+    }
+
+    fn i32(&mut self) -> io::Result<i32> {
+        Ok(i32::from_be_bytes(self.bytes()?))
+    }
+
+    fn i64(&mut self) -> io::Result<i64> {
+        Ok(i64::from_be_bytes(self.bytes()?))
+    }
+
+    fn len(&mut self) -> io::Result<usize> {
+        usize::try_from(self.i32()?).map_err(|_| invalid("Negative NBT length"))
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        let len = u16::from_be_bytes(self.bytes()?) as usize;
+        let mut buf = vec![0; len];
+        self.r.read_exact(&mut buf)?;
+        String::from_utf8(buf).map_err(|_| invalid("Corrupt NBT string"))
+    }
+
+    /// Read values one at a time, so a corrupt length can't ask for more memory than the file
+    /// has values
+    fn many<T>(&mut self, len: usize, mut read: impl FnMut(&mut Self) -> io::Result<T>) -> io::Result<Vec<T>> {
+        let mut values = Vec::with_capacity(len.min(4096));
+        for _ in 0..len {
+            values.push(read(self)?);
+        }
+        Ok(values)
+    }
+
+    fn payload(&mut self, id: u8, depth: usize) -> io::Result<Tag> {
+        if depth > MAX_DEPTH {
+            return Err(invalid("NBT is nested too deep"));
+        }
+        Ok(match id {
+            1 => Tag::Byte(self.u8()? as i8),
+            2 => Tag::Short(i16::from_be_bytes(self.bytes()?)),
+            3 => Tag::Int(self.i32()?),
+            4 => Tag::Long(self.i64()?),
+            5 => Tag::Float(f32::from_be_bytes(self.bytes()?)),
+            6 => Tag::Double(f64::from_be_bytes(self.bytes()?)),
+            7 => {
+                let len = self.len()?;
+                let mut buf = Vec::new();
+                (&mut *self.r).take(len as u64).read_to_end(&mut buf)?;
+                if buf.len() < len {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "NBT byte array cut short"));
+                }
+                Tag::ByteArray(buf)
+            }
+            8 => Tag::String(self.string()?),
+            9 => {
+                let element = self.u8()?;
+                let len = self.len()?;
+                if element == END && len > 0 {
+                    return Err(invalid("NBT list of nothing"));
+                }
+                Tag::List(self.many(len, |r| r.payload(element, depth + 1))?)
+            }
+            10 => {
+                let mut entries = Vec::new();
+                loop {
+                    let id = self.u8()?;
+                    if id == END {
+                        break;
+                    }
+                    let name = self.string()?;
+                    entries.push((name, self.payload(id, depth + 1)?));
+                }
+                Tag::Compound(entries)
+            }
+            11 => {
+                let len = self.len()?;
+                Tag::IntArray(self.many(len, |r| r.i32())?)
+            }
+            12 => {
+                let len = self.len()?;
+                Tag::LongArray(self.many(len, |r| r.i64())?)
+            }
+            _ => return Err(invalid("Unknown NBT tag")),
+        })
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_tag_round_trips() {
+        let tag = Tag::compound([
+            ("byte", Tag::Byte(-3)),
+            ("short", Tag::Short(-300)),
+            ("int", Tag::Int(1 << 20)),
+            ("long", Tag::Long(-1 << 40)),
+            ("float", Tag::Float(1.5)),
+            ("double", Tag::Double(-2.25)),
+            ("bytes", Tag::ByteArray(vec![1, 255, 0])),
+            ("string", Tag::String(String::from("héllo"))),
+            ("list", Tag::List(vec![Tag::Int(1), Tag::Int(2)])),
+            ("empty", Tag::List(Vec::new())),
+            ("ints", Tag::IntArray(vec![-1, i32::MAX])),
+            ("longs", Tag::LongArray(vec![i64::MIN, 7])),
+            ("compound", Tag::compound([("nested", Tag::compound([("short", Tag::Short(5))]))])),
+        ]);
+        for endian in [Endian::Big, Endian::Little] {
+            let mut data = Vec::new();
+            write_nbt("root", &tag, endian, &mut data).unwrap();
+            let (name, read) = read_nbt(&mut &data[..], endian).unwrap();
+            assert_eq!(name, "root");
+            assert_eq!(read, tag, "{:?}", endian);
+        }
+    }
+
+    #[test]
+    fn numbers_are_in_byte_order() {
+        let tag = Tag::compound([("i", Tag::Int(1))]);
+        let mut big = Vec::new();
+        write_nbt("", &tag, Endian::Big, &mut big).unwrap();
+        assert_eq!(big, [10, 0, 0, 3, 0, 1, b'i', 0, 0, 0, 1, 0]);
+        let mut little = Vec::new();
+        write_nbt("", &tag, Endian::Little, &mut little).unwrap();
+        assert_eq!(little, [10, 0, 0, 3, 1, 0, b'i', 1, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn bad_tags_fail() {
+        let mixed = Tag::List(vec![Tag::Int(1), Tag::Byte(1)]);
+        assert!(write_nbt("", &mixed, Endian::Big, &mut Vec::new()).is_err());
+        let mut data = Vec::new();
+        write_nbt("", &Tag::compound([("i", Tag::Int(1))]), Endian::Big, &mut data).unwrap();
+        assert!(read_nbt(&mut &data[..5], Endian::Big).is_err());
+    }
+}
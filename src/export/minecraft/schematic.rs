@@ -0,0 +1,304 @@
+//! Sponge schematics (`.schem`), as read by WorldEdit and most other Minecraft building tools.
+//!
+//! A gzipped NBT compound holding the size of the structure, a palette of the blocks in it and
+//! the index into the palette of every block as a varint, going along x first, then z, then y.
+
+use std::{
+    collections::HashMap,
+    io::{self, Read, Write},
+};
+
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use glam::Vec3;
+
+use crate::grid::{MaterialId, VoxelGrid};
+
+use super::{
+    block_palette, block_position,
+    nbt::{read_nbt, write_nbt, Endian, Tag},
+    BlockMapping, AIR, DATA_VERSION,
+};
+
+/// Version of the Sponge schematic format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchematicVersion {
+    /// Read by WorldEdit 7.2 and later, and most other tools
+    V2,
+    /// Read by WorldEdit 7.3 and later
+    V3,
+}
+
+/// Write a grid as a schematic with each voxel placed as the block its material maps to. The
+/// schematic's offset is where the grid's lowest corner is in whole voxels.
+pub fn write_schematic<W: Write>(grid: &VoxelGrid, blocks: &BlockMapping, version: SchematicVersion, w: &mut W) -> io::Result<()> {
+    if grid.size.iter().any(|s| *s > u16::MAX as usize) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Schematics can be at most {} blocks a side", u16::MAX),
+        ));
+    }
+    let [sx, sy, sz] = grid.size;
+
+    let (names, indices) = block_palette(grid, blocks);
+    let mut data = Vec::with_capacity(indices.len());
+    for y in 0..sy {
+        for z in 0..sz {
+            for x in 0..sx {
+                write_varint(&mut data, indices[(x * sy + y) * sz + z]);
+            }
+        }
+    }
+    let palette = Tag::Compound(names.iter().enumerate().map(|(i, name)| (name.clone(), Tag::Int(i as i32))).collect());
+
+    let head = [
+        ("DataVersion", Tag::Int(DATA_VERSION)),
+        // Unsigned, stored in signed shorts
+        ("Width", Tag::Short(sx as u16 as i16)),
+        ("Height", Tag::Short(sy as u16 as i16)),
+        ("Length", Tag::Short(sz as u16 as i16)),
+        ("Offset", Tag::IntArray(block_position(grid).to_vec())),
+    ];
+    let (name, root) = match version {
+        SchematicVersion::V2 => {
+            let mut entries = vec![("Version", Tag::Int(2))];
+            entries.extend(head);
+            entries.extend([
+                ("PaletteMax", Tag::Int(names.len() as i32)),
+                ("Palette", palette),
+                ("BlockData", Tag::ByteArray(data)),
+                ("BlockEntities", Tag::List(Vec::new())),
+            ]);
+            ("Schematic", Tag::compound(entries))
+        }
+        SchematicVersion::V3 => {
+            let mut entries = vec![("Version", Tag::Int(3))];
+            entries.extend(head);
+            entries.push((
+                "Blocks",
+                Tag::compound([
+                    ("Palette", palette),
+                    ("Data", Tag::ByteArray(data)),
+                    ("BlockEntities", Tag::List(Vec::new())),
+                ]),
+            ));
+            ("", Tag::compound([("Schematic", Tag::compound(entries))]))
+        }
+    };
+
+    let mut gz = GzEncoder::new(w, Compression::default());
+    write_nbt(name, &root, Endian::Big, &mut gz)?;
+    gz.finish()?.flush()
+}
+
+/// Read a version 2 or 3 schematic. Each kind of block other than air becomes a material,
+/// numbered in the order of the returned block names. Voxels are a size of 1 and the grid sits
+/// at the schematic's offset.
+pub fn read_schematic<R: Read>(r: &mut R) -> io::Result<(VoxelGrid, Vec<String>)> {
+    let (_, root) = read_nbt(&mut GzDecoder::new(r), Endian::Big)?;
+    let schematic = root.get("Schematic").unwrap_or(&root);
+
+    let version = schematic.get("Version").and_then(Tag::as_int);
+    let (palette, data) = match version {
+        Some(2) => (schematic.get("Palette"), schematic.get("BlockData")),
+        Some(3) => {
+            let blocks = schematic.get("Blocks");
+            (blocks.and_then(|b| b.get("Palette")), blocks.and_then(|b| b.get("Data")))
+        }
+        _ => return Err(invalid("Unsupported schematic version")),
+    };
+
+    let size = ["Width", "Height", "Length"].map(|name| {
+        schematic
+            .get(name)
+            .and_then(Tag::as_int)
+            .map(|s| s as u16 as usize)
+    });
+    let [sx, sy, sz] = match size {
+        [Some(sx), Some(sy), Some(sz)] => [sx, sy, sz],
+        _ => return Err(invalid("Missing schematic size")),
+    };
+    let offset = match schematic.get("Offset") {
+        Some(Tag::IntArray(o)) if o.len() == 3 => Vec3::new(o[0] as f32, o[1] as f32, o[2] as f32),
+        _ => Vec3::ZERO,
+    };
+
+    // Palette index to material, with air left empty
+    let mut names = Vec::new();
+    let mut materials: HashMap<u32, Option<MaterialId>> = HashMap::new();
+    let palette = match palette {
+        Some(Tag::Compound(palette)) => palette,
+        _ => return Err(invalid("Missing schematic palette")),
+    };
+    let mut by_index: Vec<(i32, &String)> = palette
+        .iter()
+        .filter_map(|(name, index)| index.as_int().map(|i| (i, name)))
+        .collect();
+    by_index.sort();
+    for (index, name) in by_index {
+        let material = if name == AIR {
+            None
+        } else {
+            names.push(name.clone());
+            Some((names.len() - 1) as MaterialId)
+        };
+        materials.insert(index as u32, material);
+    }
+
+    let data = match data {
+        Some(Tag::ByteArray(data)) => data,
+        _ => return Err(invalid("Missing schematic block data")),
+    };
+    // Every block takes at least a byte, so a corrupt size can't ask for more memory than the
+    // data could fill
+    let volume = sx.checked_mul(sy).and_then(|v| v.checked_mul(sz));
+    if volume.is_none_or(|v| v > data.len()) {
+        return Err(invalid("Schematic block data cut short"));
+    }
+    let mut grid = VoxelGrid::new(offset, 1.0, [sx, sy, sz]);
+    let mut bytes = data.iter();
+    for y in 0..sy {
+        for z in 0..sz {
+            for x in 0..sx {
+                let index = read_varint(&mut bytes)?;
+                let material = *materials.get(&index).ok_or_else(|| invalid("Block missing from the schematic palette"))?;
+                grid.set(x, y, z, material);
+            }
+        }
+    }
+
+    Ok((grid, names))
+}
+
+/// Seven bits at a time, lowest first, with the top bit set on every byte but the last
+fn write_varint(out: &mut Vec<u8>, mut value: u32) {
+    while value >= 0x80 {
+        out.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint<'a>(bytes: &mut impl Iterator<Item = &'a u8>) -> io::Result<u32> {
+    let mut value = 0;
+    for shift in (0..35).step_by(7) {
+        let byte = *bytes.next().ok_or_else(|| invalid("Schematic block data cut short"))?;
+        value |= ((byte & 0x7f) as u32) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(invalid("Corrupt schematic block data"))
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A grid using 200 materials, each placed as a different block
+    fn grid() -> (VoxelGrid, BlockMapping) {
+        let mut grid = VoxelGrid::new(Vec3::new(1.0, -2.0, 3.0), 1.0, [17, 9, 13]);
+        for x in 0..17 {
+            for y in 0..9 {
+                for z in 0..13 {
+                    if (x + y + z) % 3 != 0 {
+                        grid.set(x, y, z, Some(((x + 17 * (y * 13 + z)) % 200) as MaterialId));
+                    }
+                }
+            }
+        }
+        let mut blocks = BlockMapping::default();
+        for m in 0..200 {
+            blocks.set(m, format!("minecraft:block_{}", m));
+        }
+        (grid, blocks)
+    }
+
+    #[test]
+    fn size_palette_and_block_data() {
+        let (grid, blocks) = grid();
+        let mut data = Vec::new();
+        write_schematic(&grid, &blocks, SchematicVersion::V2, &mut data).unwrap();
+        let (name, root) = read_nbt(&mut GzDecoder::new(&data[..]), Endian::Big).unwrap();
+        assert_eq!(name, "Schematic");
+        assert_eq!(root.get("Width"), Some(&Tag::Short(17)));
+        assert_eq!(root.get("Height"), Some(&Tag::Short(9)));
+        assert_eq!(root.get("Length"), Some(&Tag::Short(13)));
+        assert_eq!(root.get("Offset"), Some(&Tag::IntArray(vec![1, -2, 3])));
+
+        let mut names = HashMap::new();
+        match root.get("Palette") {
+            Some(Tag::Compound(palette)) => {
+                for (name, index) in palette {
+                    names.insert(index.as_int().unwrap() as u32, name.as_str());
+                }
+            }
+            other => panic!("No palette, found {:?}", other),
+        }
+        assert_eq!(names.len(), 201);
+        assert_eq!(names[&0], AIR);
+
+        // Indices from 128 on take two bytes
+        let block_data = match root.get("BlockData") {
+            Some(Tag::ByteArray(data)) => data,
+            other => panic!("No block data, found {:?}", other),
+        };
+        let mut bytes = block_data.iter();
+        let mut two_bytes = 0;
+        for y in 0..9 {
+            for z in 0..13 {
+                for x in 0..17 {
+                    let index = read_varint(&mut bytes).unwrap();
+                    if index >= 128 {
+                        two_bytes += 1;
+                    }
+                    let block = grid.get(x, y, z).map_or(AIR, |m| blocks.block(m));
+                    assert_eq!(names[&index], block, "block {} {} {}", x, y, z);
+                }
+            }
+        }
+        assert!(bytes.next().is_none());
+        assert!(two_bytes > 0);
+        assert_eq!(block_data.len(), 17 * 9 * 13 + two_bytes);
+    }
+
+    #[test]
+    fn huge_sizes_fail_without_allocating() {
+        let schematic = Tag::compound([
+            ("Version", Tag::Int(2)),
+            ("Width", Tag::Short(-1)),
+            ("Height", Tag::Short(-1)),
+            ("Length", Tag::Short(-1)),
+            ("Palette", Tag::compound([(AIR, Tag::Int(0))])),
+            ("BlockData", Tag::ByteArray(vec![0; 16])),
+        ]);
+        let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+        write_nbt("Schematic", &schematic, Endian::Big, &mut gz).unwrap();
+        let data = gz.finish().unwrap();
+        let e = read_schematic(&mut &data[..]).err().unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn both_versions_read_back() {
+        let (grid, blocks) = grid();
+        for version in [SchematicVersion::V2, SchematicVersion::V3] {
+            let mut data = Vec::new();
+            write_schematic(&grid, &blocks, version, &mut data).unwrap();
+            let (read, names) = read_schematic(&mut &data[..]).unwrap();
+            assert_eq!(read.size, grid.size);
+            assert_eq!(read.min, grid.min);
+            for x in 0..17 {
+                for y in 0..9 {
+                    for z in 0..13 {
+                        let block = read.get(x, y, z).map(|m| names[m as usize].as_str());
+                        assert_eq!(block, grid.get(x, y, z).map(|m| blocks.block(m)), "{:?}", version);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,159 @@
+//! Wavefront OBJ meshes, with a position and normal for every vertex
+
+use std::{
+    collections::BTreeSet,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use crate::{
+    grid::MaterialId,
+    mesher::VoxelMesh,
+    palette::{shade, Palette},
+};
+
+/// Write a mesh as an OBJ file
+pub fn write_obj<W: Write>(mesh: &VoxelMesh, w: &mut W) -> io::Result<()> {
+    write_vertices(mesh, None, w)?;
+
+    // OBJ counts from 1
+    for t in mesh.inds.chunks_exact(3) {
+        let [a, b, c] = [t[0] + 1, t[1] + 1, t[2] + 1];
+        writeln!(w, "f {}//{} {}//{} {}//{}", a, a, b, b, c, c)?;
+    }
+
+    w.flush()
+}
+
+/// Save a mesh as an OBJ file at `path` with its materials in an MTL file next to it, named
+/// after it.
+///
+/// Each material is coloured from the palette. With a palette the colours are also put in a
+/// texture one pixel high next to the OBJ (`<name>_palette.png`), and every vertex gets the
+/// texture coordinate of its material's pixel, for tools that only take colours from textures.
+/// Without one the materials are given their [default colours](crate::palette::default_colour).
+///
+/// Vertices are also given their material's colour darkened by their ambient occlusion, for
+/// tools that read colours after the position of each vertex.
+pub fn export_obj(mesh: &VoxelMesh, palette: Option<&Palette>, path: &Path) -> io::Result<()> {
+    let name = path.file_stem().and_then(|n| n.to_str()).unwrap_or("model");
+    let mtl_name = format!("{}.mtl", name);
+    let texture_name = format!("{}_palette.png", name);
+    let texture = palette.map(|_| texture_name.as_str());
+
+    let materials = mesh_materials(mesh);
+    let defaults = Palette::new();
+    let colours = palette.unwrap_or(&defaults);
+
+    let mut obj = BufWriter::new(File::create(path)?);
+    write_obj_with_materials(mesh, colours, &mtl_name, texture.is_some(), &mut obj)?;
+    let mut mtl = BufWriter::new(File::create(path.with_file_name(&mtl_name))?);
+    write_mtl(&materials, colours, texture, &mut mtl)?;
+    if let Some(texture) = texture {
+        let mut png = BufWriter::new(File::create(path.with_file_name(texture))?);
+        write_palette_png(&materials, colours, &mut png)?;
+    }
+    Ok(())
+}
+
+/// Write a mesh as an OBJ file using the materials of the MTL file `mtl_name`, written by
+/// [`write_mtl`], with the faces of each material together. With `textured` set every vertex
+/// gets a texture coordinate on the texture from [`write_palette_png`]. Each vertex is coloured
+/// from the palette and darkened by its ambient occlusion.
+///
+/// Each triangle takes the material of its first vertex.
+pub fn write_obj_with_materials<W: Write>(mesh: &VoxelMesh, palette: &Palette, mtl_name: &str, textured: bool, w: &mut W) -> io::Result<()> {
+    writeln!(w, "mtllib {}", mtl_name)?;
+    write_vertices(mesh, Some(palette), w)?;
+
+    let materials = mesh_materials(mesh);
+    if textured {
+        for i in 0..materials.len() {
+            writeln!(w, "vt {} 0.5", (i as f32 + 0.5) / materials.len() as f32)?;
+        }
+    }
+
+    for (i, material) in materials.iter().enumerate() {
+        writeln!(w, "usemtl {}", material_name(*material))?;
+        let triangles = mesh.inds.chunks_exact(3).filter(|t| mesh.materials[t[0]] == *material);
+        for t in triangles {
+            let [a, b, c] = [t[0] + 1, t[1] + 1, t[2] + 1];
+            if textured {
+                let uv = i + 1;
+                writeln!(w, "f {}/{}/{} {}/{}/{} {}/{}/{}", a, uv, a, b, uv, b, c, uv, c)?;
+            } else {
+                writeln!(w, "f {}//{} {}//{} {}//{}", a, a, b, b, c, c)?;
+            }
+        }
+    }
+
+    w.flush()
+}
+
+/// Write an MTL file with a material for each of `materials`, coloured from the palette and
+/// optionally taking its colour from the `texture` written by [`write_palette_png`]
+pub fn write_mtl<W: Write>(materials: &[MaterialId], palette: &Palette, texture: Option<&str>, w: &mut W) -> io::Result<()> {
+    for material in materials {
+        let [r, g, b, a] = palette.colour(*material).map(|c| c as f32 / 255.0);
+        writeln!(w, "newmtl {}", material_name(*material))?;
+        writeln!(w, "Kd {} {} {}", r, g, b)?;
+        if a < 1.0 {
+            writeln!(w, "d {}", a)?;
+        }
+        if let Some(texture) = texture {
+            writeln!(w, "map_Kd {}", texture)?;
+        }
+        writeln!(w)?;
+    }
+
+    w.flush()
+}
+
+/// Write a PNG one pixel high with the colour of each of `materials` in turn
+pub fn write_palette_png<W: Write>(materials: &[MaterialId], palette: &Palette, w: &mut W) -> io::Result<()> {
+    let pixels: Vec<u8> = materials.iter().flat_map(|m| palette.colour(*m)).collect();
+
+    let mut encoder = png::Encoder::new(w, materials.len().max(1) as u32, 1);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    if pixels.is_empty() {
+        writer.write_image_data(&[0; 4])?;
+    } else {
+        writer.write_image_data(&pixels)?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+/// Every material used by a mesh, in order
+pub fn mesh_materials(mesh: &VoxelMesh) -> Vec<MaterialId> {
+    let materials: BTreeSet<MaterialId> = mesh.inds.chunks_exact(3).map(|t| mesh.materials[t[0]]).collect();
+    materials.into_iter().collect()
+}
+
+fn material_name(material: MaterialId) -> String {
+    format!("material_{}", material)
+}
+
+/// Write each vertex's position, followed by its colour if there's a palette
+fn write_vertices<W: Write>(mesh: &VoxelMesh, palette: Option<&Palette>, w: &mut W) -> io::Result<()> {
+    match palette {
+        Some(palette) => {
+            for (v, m) in mesh.verts.iter().zip(&mesh.materials) {
+                let [r, g, b, _] = shade(palette.colour(*m), v.ao).map(|c| c as f32 / 255.0);
+                writeln!(w, "v {} {} {} {} {} {}", v.pos[0], v.pos[1], v.pos[2], r, g, b)?;
+            }
+        }
+        None => {
+            for v in &mesh.verts {
+                writeln!(w, "v {} {} {}", v.pos[0], v.pos[1], v.pos[2])?;
+            }
+        }
+    }
+    for v in &mesh.verts {
+        writeln!(w, "vn {} {} {}", v.norm[0], v.norm[1], v.norm[2])?;
+    }
+    Ok(())
+}
@@ -0,0 +1,154 @@
+//! PLY meshes and point clouds, with a position, normal and colour for every vertex
+
+use std::io::{self, Write};
+
+use glam::Vec3;
+
+use crate::{
+    grid::VoxelGrid,
+    mesher::{instances::voxel_instances, VoxelMesh},
+    palette::{shade, Palette},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlyFormat {
+    /// Readable text, several times bigger than binary
+    Ascii,
+    /// Little endian binary
+    Binary,
+}
+
+/// Write a mesh as PLY, with each vertex coloured by its material and darkened by its ambient
+/// occlusion
+pub fn write_ply_mesh<W: Write>(mesh: &VoxelMesh, palette: &Palette, format: PlyFormat, w: &mut W) -> io::Result<()> {
+    let triangles = mesh.inds.len() / 3;
+    if mesh.verts.len() > i32::MAX as usize {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Mesh has too many vertices for PLY"));
+    }
+
+    write_header(w, format, mesh.verts.len(), Some(triangles))?;
+    let points = mesh
+        .verts
+        .iter()
+        .zip(&mesh.materials)
+        .map(|(v, m)| (Vec3::from(v.pos), Vec3::from(v.norm), shade(palette.colour(*m), v.ao)));
+    write_points(w, format, points)?;
+
+    for t in mesh.inds.chunks_exact(3) {
+        match format {
+            PlyFormat::Ascii => writeln!(w, "3 {} {} {}", t[0], t[1], t[2])?,
+            PlyFormat::Binary => {
+                w.write_all(&[3])?;
+                for i in t {
+                    w.write_all(&(*i as i32).to_le_bytes())?;
+                }
+            }
+        }
+    }
+
+    w.flush()
+}
+
+/// Write the centre of every filled voxel as a point cloud, coloured by its material.
+///
+/// Each point's normal faces away from the voxel's empty neighbours, so points on the surface
+/// face out of it. Voxels with no empty neighbours are inside the model and get a normal of zero.
+pub fn write_ply_points<W: Write>(grid: &VoxelGrid, palette: &Palette, format: PlyFormat, w: &mut W) -> io::Result<()> {
+    let voxels = voxel_instances(grid, true).instances;
+
+    write_header(w, format, voxels.len(), None)?;
+    let points = voxels.iter().map(|v| {
+        let [x, y, z] = v.voxel.map(|c| c as i32);
+        let mut normal = Vec3::ZERO;
+        for dir in [Vec3::X, Vec3::Y, Vec3::Z, -Vec3::X, -Vec3::Y, -Vec3::Z] {
+            if !grid.is_filled(x + dir.x as i32, y + dir.y as i32, z + dir.z as i32) {
+                normal += dir;
+            }
+        }
+        (Vec3::from(v.offset), normal.normalize_or_zero(), palette.colour(v.material))
+    });
+    write_points(w, format, points)?;
+
+    w.flush()
+}
+
+fn write_header<W: Write>(w: &mut W, format: PlyFormat, vertices: usize, faces: Option<usize>) -> io::Result<()> {
+    writeln!(w, "ply")?;
+    match format {
+        PlyFormat::Ascii => writeln!(w, "format ascii 1.0")?,
+        PlyFormat::Binary => writeln!(w, "format binary_little_endian 1.0")?,
+    }
+    writeln!(w, "comment voxeliser")?;
+
+    writeln!(w, "element vertex {}", vertices)?;
+    for property in ["x", "y", "z", "nx", "ny", "nz"] {
+        writeln!(w, "property float {}", property)?;
+    }
+    for property in ["red", "green", "blue", "alpha"] {
+        writeln!(w, "property uchar {}", property)?;
+    }
+    if let Some(faces) = faces {
+        writeln!(w, "element face {}", faces)?;
+        writeln!(w, "property list uchar int vertex_indices")?;
+    }
+    writeln!(w, "end_header")
+}
+
+/// Write each point's position, normal and colour
+fn write_points<W: Write>(w: &mut W, format: PlyFormat, points: impl Iterator<Item = (Vec3, Vec3, [u8; 4])>) -> io::Result<()> {
+    for (pos, norm, colour) in points {
+        let [r, g, b, a] = colour;
+        match format {
+            PlyFormat::Ascii => writeln!(
+                w,
+                "{} {} {} {} {} {} {} {} {} {}",
+                pos.x, pos.y, pos.z, norm.x, norm.y, norm.z, r, g, b, a
+            )?,
+            PlyFormat::Binary => {
+                for f in pos.to_array().into_iter().chain(norm.to_array()) {
+                    w.write_all(&f.to_le_bytes())?;
+                }
+                w.write_all(&colour)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesher::block_mesh;
+
+    /// Colour of the upward facing vertex at `pos` in an ASCII PLY
+    fn colour_at(ply: &str, pos: [f32; 3]) -> [u8; 3] {
+        ply.lines()
+            .skip_while(|l| *l != "end_header")
+            .skip(1)
+            .map(|l| l.split(' ').collect::<Vec<_>>())
+            .filter(|p| p.len() == 10)
+            .find(|p| {
+                let f: Vec<f32> = p[..6].iter().map(|v| v.parse().unwrap()).collect();
+                f[..3] == pos && f[3..6] == [0.0, 1.0, 0.0]
+            })
+            .map(|p| [p[6], p[7], p[8]].map(|c| c.parse().unwrap()))
+            .unwrap()
+    }
+
+    #[test]
+    fn ambient_occlusion_darkens_concave_corners() {
+        // A floor two voxels long with a wall on one end, so the floor's top meets the wall in a
+        // concave corner
+        let mut grid = VoxelGrid::new(Vec3::ZERO, 1.0, [2, 2, 1]);
+        for [x, y] in [[0, 0], [1, 0], [0, 1]] {
+            grid.set(x, y, 0, Some(0));
+        }
+        let mut ply = Vec::new();
+        write_ply_mesh(&block_mesh(&grid), &Palette::new(), PlyFormat::Ascii, &mut ply).unwrap();
+        let ply = String::from_utf8(ply).unwrap();
+
+        let corner = colour_at(&ply, [1.0, 1.0, 0.0]);
+        let open = colour_at(&ply, [2.0, 1.0, 0.0]);
+        assert!(corner.iter().zip(open).all(|(c, o)| *c < o), "{:?} isn't darker than {:?}", corner, open);
+    }
+}
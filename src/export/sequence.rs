@@ -0,0 +1,183 @@
+//! A simple file format for voxelised animations.
+//!
+//! Everything is little endian:
+//!
+//! * `b"VXSQ"` followed by the format version as a `u32`, currently 1
+//! * The grid size as three `u32`s, its minimum corner as three `f32`s, then the voxel size,
+//!   frames per second and number of frames as an `f32`, `f32` and `u32`
+//! * Each frame's voxels in [`VoxelGrid::voxels`] order, run length encoded as pairs of a `u32`
+//!   count and a `u32` value, where 0 is empty and anything else is the material plus one
+
+use std::io::{self, Read, Write};
+
+use glam::Vec3;
+
+use crate::grid::{MaterialId, VoxelAnimation, VoxelGrid};
+
+const MAGIC: &[u8; 4] = b"VXSQ";
+const VERSION: u32 = 1;
+
+/// Write every frame of an animation
+pub fn write_sequence<W: Write>(anim: &VoxelAnimation, w: &mut W) -> io::Result<()> {
+    let (size, min, scale) = match anim.frames.first() {
+        Some(frame) => (frame.size, frame.min, frame.scale),
+        None => ([0; 3], Vec3::splat(0.0), 1.0),
+    };
+
+    w.write_all(MAGIC)?;
+    w.write_all(&VERSION.to_le_bytes())?;
+    for s in size {
+        w.write_all(&(s as u32).to_le_bytes())?;
+    }
+    for f in [min.x, min.y, min.z, scale, anim.fps] {
+        w.write_all(&f.to_le_bytes())?;
+    }
+    w.write_all(&(anim.frames.len() as u32).to_le_bytes())?;
+
+    for frame in &anim.frames {
+        if frame.size != size {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Frames must all be the same size"));
+        }
+
+        let mut run: Option<(u32, u32)> = None;
+        for v in frame.voxels() {
+            let value = v.map(|m| m as u32 + 1).unwrap_or(0);
+            run = match run {
+                Some((count, prev)) if prev == value => Some((count + 1, prev)),
+                Some((count, prev)) => {
+                    w.write_all(&count.to_le_bytes())?;
+                    w.write_all(&prev.to_le_bytes())?;
+                    Some((1, value))
+                }
+                None => Some((1, value)),
+            };
+        }
+        if let Some((count, value)) = run {
+            w.write_all(&count.to_le_bytes())?;
+            w.write_all(&value.to_le_bytes())?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Read an animation written by [`write_sequence`]
+pub fn read_sequence<R: Read>(r: &mut R) -> io::Result<VoxelAnimation> {
+    let mut magic = [0; 4];
+    r.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid("Not a voxel sequence"));
+    }
+    if read_u32(r)? != VERSION {
+        return Err(invalid("Unsupported voxel sequence version"));
+    }
+
+    let size = [read_u32(r)? as usize, read_u32(r)? as usize, read_u32(r)? as usize];
+    let min = Vec3::new(read_f32(r)?, read_f32(r)?, read_f32(r)?);
+    let scale = read_f32(r)?;
+    let fps = read_f32(r)?;
+    let frame_count = read_u32(r)?;
+
+    let len = size[0]
+        .checked_mul(size[1])
+        .and_then(|l| l.checked_mul(size[2]))
+        .ok_or_else(|| invalid("Voxel sequence grid is too big"))?;
+    let mut frames = Vec::new();
+    for _ in 0..frame_count {
+        // Grown as runs are read, so a corrupt size can't ask for more memory up front than
+        // the file has data for
+        let mut voxels: Vec<Option<MaterialId>> = Vec::with_capacity(len.min(1 << 20));
+        while voxels.len() < len {
+            let count = read_u32(r)? as usize;
+            let value = read_u32(r)?;
+            if count > len - voxels.len() || value > MaterialId::MAX as u32 + 1 {
+                return Err(invalid("Corrupt voxel data"));
+            }
+            let voxel = value.checked_sub(1).map(|m| m as MaterialId);
+            voxels.resize(voxels.len() + count, voxel);
+        }
+        frames.push(VoxelGrid::from_voxels(min, scale, size, voxels));
+    }
+
+    Ok(VoxelAnimation { fps, frames })
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn read_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_f32<R: Read>(r: &mut R) -> io::Result<f32> {
+    let mut buf = [0; 4];
+    r.read_exact(&mut buf)?;
+    Ok(f32::from_le_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn animation() -> VoxelAnimation {
+        let frames = (0..3)
+            .map(|f| {
+                let mut grid = VoxelGrid::new(Vec3::new(-1.0, 0.5, 2.0), 0.25, [4, 3, 5]);
+                for x in 0..4 {
+                    for z in 0..5 {
+                        if (x + z + f) % 3 != 0 {
+                            grid.set(x, f % 3, z, Some((x * 100 + f) as MaterialId));
+                        }
+                    }
+                }
+                grid
+            })
+            .collect();
+        VoxelAnimation { fps: 12.0, frames }
+    }
+
+    /// A header for a sequence of one frame of the given size, with no voxel data after it
+    fn header(size: [u32; 3]) -> Vec<u8> {
+        let mut data = MAGIC.to_vec();
+        data.extend(VERSION.to_le_bytes());
+        for s in size {
+            data.extend(s.to_le_bytes());
+        }
+        for f in [0.0f32, 0.0, 0.0, 1.0, 24.0] {
+            data.extend(f.to_le_bytes());
+        }
+        data.extend(1u32.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        let anim = animation();
+        let mut data = Vec::new();
+        write_sequence(&anim, &mut data).unwrap();
+        let read = read_sequence(&mut &data[..]).unwrap();
+        assert_eq!(read.fps, anim.fps);
+        assert_eq!(read.frames.len(), anim.frames.len());
+        for (a, b) in read.frames.iter().zip(&anim.frames) {
+            assert_eq!((a.min, a.scale, a.size), (b.min, b.scale, b.size));
+            assert_eq!(a.voxels(), b.voxels());
+        }
+    }
+
+    #[test]
+    fn bad_headers_fail() {
+        let mut data = Vec::new();
+        write_sequence(&animation(), &mut data).unwrap();
+        for len in [3, 20, 40, data.len() - 1] {
+            assert!(read_sequence(&mut &data[..len]).is_err(), "cut short at {}", len);
+        }
+
+        let kind = |data: Vec<u8>| read_sequence(&mut &data[..]).err().map(|e| e.kind());
+        assert_eq!(kind(header([u32::MAX; 3])), Some(io::ErrorKind::InvalidData));
+        // Far too big to allocate, but the data runs out long before that's needed
+        assert_eq!(kind(header([100_000; 3])), Some(io::ErrorKind::UnexpectedEof));
+    }
+}
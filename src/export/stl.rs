@@ -0,0 +1,227 @@
+//! Binary STL meshes for 3D printing.
+//!
+//! Slicers expect millimetres with z up, so positions are scaled by
+//! [`mm_per_unit`](StlSettings::mm_per_unit) and turned so the viewer's y axis points up.
+//! Meshes are checked before anything is written, so every file is closed with its triangles
+//! facing outwards.
+
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+};
+
+use glam::Vec3;
+
+use crate::mesher::VoxelMesh;
+
+#[derive(Debug, Clone, Copy)]
+pub struct StlSettings {
+    /// Millimetres in one unit of the mesh, so 1000 for meshes in metres
+    pub mm_per_unit: f32,
+}
+
+impl Default for StlSettings {
+    fn default() -> Self {
+        StlSettings { mm_per_unit: 1000.0 }
+    }
+}
+
+/// Write a mesh as a binary STL, failing without writing anything if it isn't fit to print
+/// (see [`check_printable`])
+pub fn write_stl<W: Write>(mesh: &VoxelMesh, settings: &StlSettings, w: &mut W) -> io::Result<()> {
+    check_printable(mesh).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let triangles = mesh.inds.len() / 3;
+    if triangles > u32::MAX as usize {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Too many triangles for STL"));
+    }
+
+    // Headers starting with "solid" get taken for text STL by some readers
+    let mut header = [0; 80];
+    let name = b"voxeliser binary STL, millimetres";
+    header[..name.len()].copy_from_slice(name);
+    w.write_all(&header)?;
+    w.write_all(&(triangles as u32).to_le_bytes())?;
+
+    let to_print = |p: [f32; 3]| Vec3::new(p[0], -p[2], p[1]) * settings.mm_per_unit;
+    for t in mesh.inds.chunks_exact(3) {
+        let [a, b, c] = [t[0], t[1], t[2]].map(|i| to_print(mesh.verts[i].pos));
+        let normal = (b - a).cross(c - a).normalize_or_zero();
+        for v in [normal, a, b, c] {
+            for f in v.to_array() {
+                w.write_all(&f.to_le_bytes())?;
+            }
+        }
+        // Attribute byte count, unused
+        w.write_all(&[0; 2])?;
+    }
+
+    w.flush()
+}
+
+/// Make sure a mesh is fit to print: closed, with every triangle facing the same way as those
+/// next to it, and facing outwards rather than inside out. Each separate piece is checked on its
+/// own, so one flipped piece can't be made up for by the others, and the walls of hollows inside
+/// a piece have to face into the hollow.
+///
+/// Vertices in the same place count as the same point, so meshes split up by normal or material
+/// are still closed as long as their pieces meet. Edges shared by more than two triangles, where
+/// voxels only touch along an edge, are fine as long as the same number of triangles cross them
+/// each way.
+pub fn check_printable(mesh: &VoxelMesh) -> Result<(), String> {
+    let mut points = HashMap::new();
+    let point: Vec<usize> = mesh
+        .verts
+        .iter()
+        .map(|v| {
+            let next = points.len();
+            *points.entry(v.pos.map(f32::to_bits)).or_insert(next)
+        })
+        .collect();
+
+    // Each edge counted once for every triangle going along it one way, less the other way
+    let mut edges: HashMap<(usize, usize), i32> = HashMap::new();
+    for t in mesh.inds.chunks_exact(3) {
+        for i in 0..3 {
+            let (a, b) = (point[t[i]], point[t[(i + 1) % 3]]);
+            if a == b {
+                continue;
+            }
+            let (key, step) = if a < b { ((a, b), 1) } else { ((b, a), -1) };
+            *edges.entry(key).or_default() += step;
+        }
+    }
+    let open = edges.values().filter(|c| **c != 0).count();
+    if open > 0 {
+        return Err(format!("Mesh has {} open or mismatched edges, so isn't closed", open));
+    }
+
+    // A closed piece facing outwards holds a positive volume, and the walls of a hollow inside
+    // another piece hold a negative one. So each piece should face outwards unless it's inside
+    // an odd number of others.
+    let components = mesh.components();
+    let pieces: Vec<Piece> = components.iter().map(Piece::new).collect();
+    for (i, piece) in pieces.iter().enumerate() {
+        if piece.volume.abs() <= (piece.max - piece.min).max_element().powi(3) * 1e-6 {
+            return Err(format!("Piece {} of the mesh has no volume", i + 1));
+        }
+        let point = Vec3::from(piece.mesh.verts[0].pos);
+        let depth = pieces.iter().enumerate().filter(|(j, other)| *j != i && other.contains(point)).count();
+        if (piece.volume > 0.0) != (depth % 2 == 0) {
+            return Err(format!("Piece {} of the mesh is inside out", i + 1));
+        }
+    }
+
+    Ok(())
+}
+
+/// A separate piece of a mesh, with what's needed to tell which way it faces
+struct Piece<'a> {
+    mesh: &'a VoxelMesh,
+    /// Six times the volume it holds, negative when it faces inwards
+    volume: f32,
+    min: Vec3,
+    max: Vec3,
+}
+
+impl<'a> Piece<'a> {
+    fn new(mesh: &'a VoxelMesh) -> Piece<'a> {
+        // Measured from a point on the piece so pieces far from the origin don't lose precision
+        let origin = Vec3::from(mesh.verts[0].pos);
+        let volume = mesh
+            .inds
+            .chunks_exact(3)
+            .map(|t| {
+                let [a, b, c] = [t[0], t[1], t[2]].map(|i| Vec3::from(mesh.verts[i].pos) - origin);
+                a.dot(b.cross(c))
+            })
+            .sum();
+        let min = mesh.verts.iter().fold(Vec3::splat(f32::MAX), |m, v| m.min(Vec3::from(v.pos)));
+        let max = mesh.verts.iter().fold(Vec3::splat(f32::MIN), |m, v| m.max(Vec3::from(v.pos)));
+        Piece { mesh, volume, min, max }
+    }
+
+    /// Whether a point not on the piece is inside it, by whether a ray from the point crosses it
+    /// an odd number of times. The ray isn't along an axis so it doesn't run along voxel faces.
+    fn contains(&self, point: Vec3) -> bool {
+        if point.cmplt(self.min).any() || point.cmpgt(self.max).any() {
+            return false;
+        }
+        let dir = Vec3::new(0.2113, 0.5774, 0.7887);
+        let crossings = self
+            .mesh
+            .inds
+            .chunks_exact(3)
+            .filter(|t| {
+                let [a, b, c] = [t[0], t[1], t[2]].map(|i| Vec3::from(self.mesh.verts[i].pos) - point);
+                ray_crosses(dir, a, b, c)
+            })
+            .count();
+        crossings % 2 == 1
+    }
+}
+
+/// Whether a ray from the origin crosses a triangle
+fn ray_crosses(dir: Vec3, a: Vec3, b: Vec3, c: Vec3) -> bool {
+    let (ab, ac) = (b - a, c - a);
+    let p = dir.cross(ac);
+    let det = ab.dot(p);
+    if det == 0.0 {
+        return false;
+    }
+    let u = -a.dot(p) / det;
+    let q = (-a).cross(ab);
+    let v = dir.dot(q) / det;
+    u >= 0.0 && v >= 0.0 && u + v <= 1.0 && ac.dot(q) / det > 0.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::vertex::Vertex;
+
+    /// Add a box facing outwards, or inwards if `flipped`
+    fn add_box(mesh: &mut VoxelMesh, min: Vec3, size: f32, flipped: bool) {
+        let first = mesh.verts.len();
+        for i in 0..8 {
+            let corner = Vec3::new((i & 1) as f32, (i >> 1 & 1) as f32, (i >> 2 & 1) as f32);
+            mesh.verts.push(Vertex::new((min + corner * size).to_array(), [0.0; 3]));
+            mesh.materials.push(0);
+        }
+        let faces = [[0, 4, 6, 2], [1, 3, 7, 5], [0, 1, 5, 4], [2, 6, 7, 3], [0, 2, 3, 1], [4, 5, 7, 6]];
+        for [a, b, c, d] in faces {
+            for mut t in [[a, b, c], [a, c, d]] {
+                if flipped {
+                    t.swap(1, 2);
+                }
+                mesh.inds.extend(t.map(|i| first + i));
+            }
+        }
+    }
+
+    #[test]
+    fn every_piece_faces_outwards() {
+        let mut mesh = VoxelMesh::default();
+        add_box(&mut mesh, Vec3::ZERO, 4.0, false);
+        add_box(&mut mesh, Vec3::new(10.0, 0.0, 0.0), 1.0, false);
+        assert_eq!(check_printable(&mesh), Ok(()));
+
+        // The big box outweighs the small one, so only checking the whole mesh would miss it
+        let mut flipped = VoxelMesh::default();
+        add_box(&mut flipped, Vec3::ZERO, 4.0, false);
+        add_box(&mut flipped, Vec3::new(10.0, 0.0, 0.0), 1.0, true);
+        assert_eq!(check_printable(&flipped), Err(String::from("Piece 2 of the mesh is inside out")));
+    }
+
+    #[test]
+    fn hollows_face_inwards() {
+        let mut hollow = VoxelMesh::default();
+        add_box(&mut hollow, Vec3::ZERO, 4.0, false);
+        add_box(&mut hollow, Vec3::ONE, 2.0, true);
+        assert_eq!(check_printable(&hollow), Ok(()));
+
+        let mut flipped = VoxelMesh::default();
+        add_box(&mut flipped, Vec3::ZERO, 4.0, false);
+        add_box(&mut flipped, Vec3::ONE, 2.0, false);
+        assert_eq!(check_printable(&flipped), Err(String::from("Piece 2 of the mesh is inside out")));
+    }
+}
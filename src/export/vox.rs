@@ -0,0 +1,501 @@
+//! MagicaVoxel `.vox` files.
+//!
+//! MagicaVoxel models can be at most 256 voxels along each side, so bigger grids are split into
+//! blocks of up to 256³, each its own model placed with a transform node under one group. Files
+//! have z up where grids have y up, so y and z are swapped (and one flipped) on the way through.
+//!
+//! Colours go in the file's palette, which only has room for 255. When every material is below
+//! 255 each is stored as itself so reading the file back gives the same materials, otherwise the
+//! materials used are numbered from 0 in order.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    io::{self, Read, Write},
+};
+
+use glam::Vec3;
+
+use crate::{
+    grid::{MaterialId, VoxelGrid},
+    palette::Palette,
+};
+
+/// Largest size of a model along each side
+const MAX_SIZE: usize = 256;
+const VERSION: u32 = 150;
+
+/// Write a grid and the colours of its materials. The grid's position is kept as best it can be
+/// in whole voxels, its voxel size isn't kept at all.
+pub fn write_vox<W: Write>(grid: &VoxelGrid, palette: &Palette, w: &mut W) -> io::Result<()> {
+    let materials = colour_indices(grid)?;
+
+    // Size of the grid along the file's axes, and where its lowest corner goes
+    let [sx, sy, sz] = grid.size;
+    let size = [sx, sz, sy];
+    let at = (grid.min / grid.scale).round();
+    let origin = [at.x as i32, -(at.z as i32) - sz as i32, at.y as i32];
+
+    let blocks = size.map(|s| s.div_ceil(MAX_SIZE).max(1));
+    let mut models = Vec::new();
+    let mut nodes = Vec::new();
+    let mut shapes = Vec::new();
+    for bx in 0..blocks[0] {
+        for by in 0..blocks[1] {
+            for bz in 0..blocks[2] {
+                let lo = [bx, by, bz].map(|b| b * MAX_SIZE);
+                let block_size = [0, 1, 2].map(|a| (size[a] - lo[a].min(size[a])).clamp(1, MAX_SIZE));
+
+                let mut voxels = Vec::new();
+                for x in lo[0]..(lo[0] + block_size[0]).min(sx) {
+                    for y in lo[2]..(lo[2] + block_size[2]).min(sy) {
+                        for z in sz.saturating_sub(lo[1] + block_size[1])..sz - lo[1].min(sz) {
+                            if let Some(material) = grid.get(x, y, z) {
+                                let file = [x, sz - 1 - z, y];
+                                voxels.extend([0, 1, 2].map(|a| (file[a] - lo[a]) as u8));
+                                voxels.push(materials[&material]);
+                            }
+                        }
+                    }
+                }
+
+                let mut size_chunk = Vec::new();
+                for s in block_size {
+                    size_chunk.extend((s as u32).to_le_bytes());
+                }
+                let mut xyzi = Vec::new();
+                xyzi.extend((voxels.len() as u32 / 4).to_le_bytes());
+                xyzi.extend(voxels);
+                write_chunk(&mut models, b"SIZE", &size_chunk, &[]);
+                write_chunk(&mut models, b"XYZI", &xyzi, &[]);
+
+                // Models are placed by their centre, rounded down
+                let translation = [0, 1, 2].map(|a| origin[a] + (lo[a] + block_size[a] / 2) as i32);
+                shapes.push(translation);
+            }
+        }
+    }
+
+    // A transform at the root holding a group of a transform and shape for each model
+    let shape_count = shapes.len() as i32;
+    write_chunk(&mut nodes, b"nTRN", &transform_node(0, 1, None), &[]);
+    let mut group = Vec::new();
+    group.extend(1i32.to_le_bytes());
+    write_dict(&mut group, &[]);
+    group.extend(shape_count.to_le_bytes());
+    for i in 0..shape_count {
+        group.extend((2 + i * 2).to_le_bytes());
+    }
+    write_chunk(&mut nodes, b"nGRP", &group, &[]);
+    for (i, translation) in shapes.iter().enumerate() {
+        let id = 2 + i as i32 * 2;
+        write_chunk(&mut nodes, b"nTRN", &transform_node(id, id + 1, Some(*translation)), &[]);
+        let mut shape = Vec::new();
+        shape.extend((id + 1).to_le_bytes());
+        write_dict(&mut shape, &[]);
+        shape.extend(1i32.to_le_bytes());
+        shape.extend((i as i32).to_le_bytes());
+        write_dict(&mut shape, &[]);
+        write_chunk(&mut nodes, b"nSHP", &shape, &[]);
+    }
+
+    // Entry i of the palette is the colour of index i + 1
+    let mut by_index = [None; 255];
+    for (material, index) in &materials {
+        by_index[*index as usize - 1] = Some(*material);
+    }
+    let mut rgba = Vec::with_capacity(1024);
+    for (i, material) in by_index.iter().enumerate() {
+        rgba.extend(palette.colour(material.unwrap_or(i as MaterialId)));
+    }
+    rgba.extend([0; 4]);
+
+    let mut children = models;
+    children.extend(nodes);
+    write_chunk(&mut children, b"RGBA", &rgba, &[]);
+
+    w.write_all(b"VOX ")?;
+    w.write_all(&VERSION.to_le_bytes())?;
+    let mut main = Vec::new();
+    write_chunk(&mut main, b"MAIN", &[], &children);
+    w.write_all(&main)
+}
+
+/// Palette index of each material in a grid
+fn colour_indices(grid: &VoxelGrid) -> io::Result<HashMap<MaterialId, u8>> {
+    let used: BTreeSet<MaterialId> = grid.voxels().iter().flatten().copied().collect();
+    if used.len() > 255 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("MagicaVoxel files can only hold 255 materials, the grid has {}", used.len()),
+        ));
+    }
+
+    let keep = used.iter().all(|m| *m < 255);
+    Ok(used
+        .iter()
+        .enumerate()
+        .map(|(i, m)| (*m, if keep { *m as u8 + 1 } else { i as u8 + 1 }))
+        .collect())
+}
+
+fn transform_node(id: i32, child: i32, translation: Option<[i32; 3]>) -> Vec<u8> {
+    let mut node = Vec::new();
+    node.extend(id.to_le_bytes());
+    write_dict(&mut node, &[]);
+    node.extend(child.to_le_bytes());
+    // Reserved, layer and frame count
+    node.extend((-1i32).to_le_bytes());
+    node.extend((if translation.is_some() { 0i32 } else { -1 }).to_le_bytes());
+    node.extend(1i32.to_le_bytes());
+    match translation {
+        Some([x, y, z]) => write_dict(&mut node, &[("_t", &format!("{} {} {}", x, y, z))]),
+        None => write_dict(&mut node, &[]),
+    }
+    node
+}
+
+fn write_chunk(out: &mut Vec<u8>, id: &[u8; 4], content: &[u8], children: &[u8]) {
+    out.extend(id);
+    out.extend((content.len() as u32).to_le_bytes());
+    out.extend((children.len() as u32).to_le_bytes());
+    out.extend(content);
+    out.extend(children);
+}
+
+fn write_dict(out: &mut Vec<u8>, entries: &[(&str, &str)]) {
+    out.extend((entries.len() as u32).to_le_bytes());
+    for (key, value) in entries {
+        for s in [key, value] {
+            out.extend((s.len() as u32).to_le_bytes());
+            out.extend(s.as_bytes());
+        }
+    }
+}
+
+/// One node of a file's scene graph
+enum Node {
+    Transform { child: i32, translation: [i32; 3] },
+    Group { children: Vec<i32> },
+    Shape { models: Vec<i32> },
+}
+
+/// Deepest the scene graph is followed, so files that loop back on themselves still end
+const MAX_DEPTH: usize = 64;
+
+/// Read a `.vox` file into one grid holding every model in the scene, along with the palette.
+///
+/// Material `m` is palette index `m + 1`, the same as [`write_vox`] uses when it can. Voxels are
+/// a size of 1 and the grid sits where the models were placed. Models turned by their transforms
+/// aren't supported. Files without a palette use MagicaVoxel's default one, which isn't included
+/// here, so their materials are given default colours.
+pub fn read_vox<R: Read>(r: &mut R) -> io::Result<(VoxelGrid, Palette)> {
+    let mut data = Vec::new();
+    r.read_to_end(&mut data)?;
+    let mut reader = Reader { data: &data, pos: 0 };
+
+    if reader.bytes(4)? != b"VOX " {
+        return Err(invalid("Not a MagicaVoxel file"));
+    }
+    reader.u32()?;
+    if reader.bytes(4)? != b"MAIN" {
+        return Err(invalid("Missing MAIN chunk"));
+    }
+    let content = reader.u32()? as usize;
+    reader.bytes(content)?;
+    let children = reader.u32()? as usize;
+    let mut reader = Reader {
+        data: reader.bytes(children)?,
+        pos: 0,
+    };
+
+    let mut sizes = Vec::new();
+    let mut models: Vec<(usize, &[u8])> = Vec::new();
+    let mut nodes = HashMap::new();
+    let mut palette = Palette::new();
+    while reader.pos < reader.data.len() {
+        let id: [u8; 4] = reader.bytes(4)?.try_into().unwrap();
+        let content_size = reader.u32()? as usize;
+        let children_size = reader.u32()? as usize;
+        let mut chunk = Reader {
+            data: reader.bytes(content_size)?,
+            pos: 0,
+        };
+        reader.bytes(children_size)?;
+
+        match &id {
+            b"SIZE" => sizes.push([chunk.u32()? as usize, chunk.u32()? as usize, chunk.u32()? as usize]),
+            b"XYZI" => {
+                let count = chunk.u32()? as usize;
+                let voxels = chunk.bytes(count.checked_mul(4).ok_or_else(|| invalid("Corrupt voxel data"))?)?;
+                models.push((sizes.len().checked_sub(1).ok_or_else(|| invalid("Voxels without a size"))?, voxels));
+            }
+            b"nTRN" => {
+                let id = chunk.i32()?;
+                chunk.dict()?;
+                let child = chunk.i32()?;
+                chunk.i32()?;
+                chunk.i32()?;
+                let frames = chunk.i32()?;
+                let mut translation = [0; 3];
+                for _ in 0..frames.max(0) {
+                    for (key, value) in chunk.dict()? {
+                        match key.as_str() {
+                            "_t" => translation = parse_translation(&value)?,
+                            "_r" if value != "4" => return Err(invalid("Rotated models aren't supported")),
+                            _ => {}
+                        }
+                    }
+                }
+                nodes.insert(id, Node::Transform { child, translation });
+            }
+            b"nGRP" => {
+                let id = chunk.i32()?;
+                chunk.dict()?;
+                let count = chunk.i32()?;
+                let children = (0..count.max(0)).map(|_| chunk.i32()).collect::<io::Result<_>>()?;
+                nodes.insert(id, Node::Group { children });
+            }
+            b"nSHP" => {
+                let id = chunk.i32()?;
+                chunk.dict()?;
+                let count = chunk.i32()?;
+                let mut shapes = Vec::new();
+                for _ in 0..count.max(0) {
+                    shapes.push(chunk.i32()?);
+                    chunk.dict()?;
+                }
+                nodes.insert(id, Node::Shape { models: shapes });
+            }
+            b"RGBA" => {
+                let colours = chunk.bytes(1024)?;
+                palette = Palette::from_colours(colours.chunks(4).take(255).map(|c| [c[0], c[1], c[2], c[3]]));
+            }
+            _ => {}
+        }
+    }
+
+    // The lowest corner of each model in the file's space
+    let mut placed = Vec::new();
+    if nodes.is_empty() {
+        placed.extend((0..models.len()).map(|i| (i, [0; 3])));
+    } else {
+        place(&nodes, 0, [0; 3], 0, &sizes, &models, &mut placed)?;
+    }
+
+    let mut lo = [i32::MAX; 3];
+    let mut hi = [i32::MIN; 3];
+    for (model, at) in &placed {
+        let size = sizes[models[*model].0];
+        for a in 0..3 {
+            lo[a] = lo[a].min(at[a]);
+            hi[a] = hi[a].max(at[a] + size[a] as i32);
+        }
+    }
+    if placed.is_empty() {
+        return Ok((VoxelGrid::new(Vec3::ZERO, 1.0, [0; 3]), palette));
+    }
+
+    let span = [0, 1, 2].map(|a| (hi[a] - lo[a]) as usize);
+    let size = [span[0], span[2], span[1]];
+    let min = Vec3::new(lo[0] as f32, lo[2] as f32, -(hi[1] as f32));
+    let mut grid = VoxelGrid::new(min, 1.0, size);
+    for (model, at) in &placed {
+        let (size_index, voxels) = models[*model];
+        let model_size = sizes[size_index];
+        for v in voxels.chunks(4) {
+            let local = [v[0] as usize, v[1] as usize, v[2] as usize];
+            if (0..3).any(|a| local[a] >= model_size[a]) {
+                return Err(invalid("Voxel outside of its model"));
+            }
+            if v[3] == 0 {
+                continue;
+            }
+            let file = [0, 1, 2].map(|a| (at[a] - lo[a]) as usize + local[a]);
+            grid.set(file[0], file[2], span[1] - 1 - file[1], Some(v[3] as MaterialId - 1));
+        }
+    }
+
+    Ok((grid, palette))
+}
+
+/// Follow the scene graph down from a node, giving the lowest corner of each model found
+fn place(
+    nodes: &HashMap<i32, Node>,
+    id: i32,
+    translation: [i32; 3],
+    depth: usize,
+    sizes: &[[usize; 3]],
+    models: &[(usize, &[u8])],
+    placed: &mut Vec<(usize, [i32; 3])>,
+) -> io::Result<()> {
+    if depth > MAX_DEPTH {
+        return Err(invalid("Scene graph is too deep"));
+    }
+    match nodes.get(&id) {
+        Some(Node::Transform { child, translation: t }) => {
+            let translation = [0, 1, 2].map(|a| translation[a] + t[a]);
+            place(nodes, *child, translation, depth + 1, sizes, models, placed)?;
+        }
+        Some(Node::Group { children }) => {
+            for child in children {
+                place(nodes, *child, translation, depth + 1, sizes, models, placed)?;
+            }
+        }
+        Some(Node::Shape { models: shapes }) => {
+            for model in shapes {
+                let (size_index, _) = models.get(*model as usize).ok_or_else(|| invalid("Shape of a missing model"))?;
+                let size = sizes[*size_index];
+                placed.push((*model as usize, [0, 1, 2].map(|a| translation[a] - (size[a] / 2) as i32)));
+            }
+        }
+        None => return Err(invalid("Missing scene graph node")),
+    }
+    Ok(())
+}
+
+fn parse_translation(value: &str) -> io::Result<[i32; 3]> {
+    let parts: Vec<i32> = value
+        .split_whitespace()
+        .map(|p| p.parse().map_err(|_| invalid("Corrupt translation")))
+        .collect::<io::Result<_>>()?;
+    parts.try_into().map_err(|_| invalid("Corrupt translation"))
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Reads little endian values out of a chunk
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        let end = self.pos.checked_add(len).filter(|end| *end <= self.data.len());
+        let end = end.ok_or_else(|| invalid("Unexpected end of file"))?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn i32(&mut self) -> io::Result<i32> {
+        Ok(i32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.bytes(len)?.to_vec()).map_err(|_| invalid("Corrupt string"))
+    }
+
+    fn dict(&mut self) -> io::Result<Vec<(String, String)>> {
+        let count = self.u32()?;
+        (0..count).map(|_| Ok((self.string()?, self.string()?))).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A grid with a spread of voxels of the given materials, filled the same way every time
+    fn grid(min: Vec3, size: [usize; 3], materials: &[MaterialId]) -> VoxelGrid {
+        let mut grid = VoxelGrid::new(min, 1.0, size);
+        let mut i = 0usize;
+        for x in 0..size[0] {
+            for y in 0..size[1] {
+                for z in 0..size[2] {
+                    i = i.wrapping_mul(2654435761).wrapping_add(x * 7 + y * 13 + z * 31 + 1);
+                    if i.is_multiple_of(3) {
+                        grid.set(x, y, z, Some(materials[i / 3 % materials.len()]));
+                    }
+                }
+            }
+        }
+        grid
+    }
+
+    fn round_trip(grid: &VoxelGrid, palette: &Palette) -> (VoxelGrid, Palette) {
+        let mut data = Vec::new();
+        write_vox(grid, palette, &mut data).unwrap();
+        read_vox(&mut &data[..]).unwrap()
+    }
+
+    /// The read grid only covers the models, so compare every voxel by where it is
+    fn assert_same_voxels(a: &VoxelGrid, b: &VoxelGrid) {
+        assert_eq!(a.count(), b.count());
+        let offset = ((a.min - b.min) / a.scale).round();
+        for x in 0..a.size[0] {
+            for y in 0..a.size[1] {
+                for z in 0..a.size[2] {
+                    if let Some(m) = a.get(x, y, z) {
+                        let [bx, by, bz] = [x as f32 + offset.x, y as f32 + offset.y, z as f32 + offset.z].map(|c| c as usize);
+                        assert_eq!(b.get(bx, by, bz), Some(m), "voxel {} {} {}", x, y, z);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn materials_and_palette_round_trip() {
+        let materials = [0, 3, 7, 254];
+        let original = grid(Vec3::new(-4.0, 2.0, 5.0), [9, 6, 11], &materials);
+        let mut palette = Palette::new();
+        for (i, m) in materials.iter().enumerate() {
+            palette.set(*m, [10 * i as u8, 200, 255 - i as u8, 255]);
+        }
+
+        let (read, read_palette) = round_trip(&original, &palette);
+        assert_eq!(read.size, original.size);
+        assert_eq!(read.min, original.min);
+        assert_eq!(read.voxels(), original.voxels());
+        for m in materials {
+            assert_eq!(read_palette.colour(m), palette.colour(m));
+        }
+    }
+
+    #[test]
+    fn big_grids_are_split_into_models() {
+        // Over 256 along x and along z, which is the file's flipped y
+        let original = grid(Vec3::new(1.0, 0.0, -3.0), [300, 4, 270], &[1, 2, 5]);
+        let mut data = Vec::new();
+        write_vox(&original, &Palette::new(), &mut data).unwrap();
+        let shapes = data.windows(4).filter(|w| w == b"nSHP").count();
+        assert_eq!(shapes, 4);
+
+        let (read, _) = read_vox(&mut &data[..]).unwrap();
+        assert_same_voxels(&original, &read);
+    }
+
+    #[test]
+    fn palette_holds_255_materials() {
+        let all: Vec<MaterialId> = (0..255).collect();
+        let mut original = VoxelGrid::new(Vec3::ZERO, 1.0, [255, 1, 1]);
+        for (x, m) in all.iter().enumerate() {
+            original.set(x, 0, 0, Some(*m));
+        }
+        let (read, _) = round_trip(&original, &Palette::new());
+        assert_eq!(read.voxels(), original.voxels());
+
+        // Materials past 254 don't fit as themselves, so they're renumbered in order
+        let mut high = VoxelGrid::new(Vec3::ZERO, 1.0, [3, 1, 1]);
+        for (x, m) in [300, 255, 1000].into_iter().enumerate() {
+            high.set(x, 0, 0, Some(m));
+        }
+        let (read, _) = round_trip(&high, &Palette::new());
+        assert_eq!(read.voxels(), &[Some(1), Some(0), Some(2)]);
+
+        let mut too_many = VoxelGrid::new(Vec3::ZERO, 1.0, [256, 1, 1]);
+        for x in 0..256 {
+            too_many.set(x, 0, 0, Some(x as MaterialId));
+        }
+        let e = write_vox(&too_many, &Palette::new(), &mut Vec::new()).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+    }
+}
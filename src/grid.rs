@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+
+use glam::Vec3;
+
+use crate::hash::Fnv64;
+
+/// Identifies which material (or source object) a voxel came from
+pub type MaterialId = u16;
+
+/// A dense grid of voxels, as produced by the voxeliser.
+///
+/// Each voxel is either empty or holds the material of the object it was made from.
+#[derive(Debug, Clone)]
+pub struct VoxelGrid {
+    /// World position of the minimum corner of the grid
+    pub min: Vec3,
+    /// Side length of each voxel
+    pub scale: f32,
+    /// Number of voxels along the x, y and z axes
+    pub size: [usize; 3],
+
+    voxels: Vec<Option<MaterialId>>,
+}
+
+impl VoxelGrid {
+    /// Create an empty grid
+    pub fn new(min: Vec3, scale: f32, size: [usize; 3]) -> VoxelGrid {
+        VoxelGrid {
+            min,
+            scale,
+            size,
+            voxels: vec![None; size[0] * size[1] * size[2]],
+        }
+    }
+
+    fn index(&self, x: usize, y: usize, z: usize) -> usize {
+        (x * self.size[1] + y) * self.size[2] + z
+    }
+
+    pub fn get(&self, x: usize, y: usize, z: usize) -> Option<MaterialId> {
+        self.voxels[self.index(x, y, z)]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, z: usize, voxel: Option<MaterialId>) {
+        let i = self.index(x, y, z);
+        self.voxels[i] = voxel;
+    }
+
+    /// Like `get`, but anything outside of the grid is treated as empty
+    pub fn material(&self, x: i32, y: i32, z: i32) -> Option<MaterialId> {
+        if x < 0 || y < 0 || z < 0 {
+            return None;
+        }
+        let (x, y, z) = (x as usize, y as usize, z as usize);
+        if x >= self.size[0] || y >= self.size[1] || z >= self.size[2] {
+            return None;
+        }
+        self.get(x, y, z)
+    }
+
+    /// If there is a voxel at the given position, anything outside of the grid is empty
+    pub fn is_filled(&self, x: i32, y: i32, z: i32) -> bool {
+        self.material(x, y, z).is_some()
+    }
+
+    /// World position of the minimum corner of a voxel
+    pub fn voxel_min(&self, x: usize, y: usize, z: usize) -> Vec3 {
+        self.min + Vec3::new(x as f32, y as f32, z as f32) * self.scale
+    }
+
+    /// All of the voxels, with z changing fastest, then y, then x
+    pub fn voxels(&self) -> &[Option<MaterialId>] {
+        &self.voxels
+    }
+
+    /// Create a grid from voxels laid out like [`VoxelGrid::voxels`]
+    pub fn from_voxels(min: Vec3, scale: f32, size: [usize; 3], voxels: Vec<Option<MaterialId>>) -> VoxelGrid {
+        assert_eq!(voxels.len(), size[0] * size[1] * size[2], "Wrong number of voxels for grid");
+        VoxelGrid {
+            min,
+            scale,
+            size,
+            voxels,
+        }
+    }
+
+    /// Number of filled voxels
+    pub fn count(&self) -> usize {
+        self.voxels.iter().filter(|v| v.is_some()).count()
+    }
+
+    /// A hash of the grid's size, placement and voxels.
+    ///
+    /// This is stable across builds and platforms, so it can be stored and compared later to catch
+    /// unexpected changes in the output.
+    pub fn fingerprint(&self) -> u64 {
+        let mut hash = Fnv64::new();
+        for s in self.size {
+            hash.write_u64(s as u64);
+        }
+        hash.write_f32(self.min.x);
+        hash.write_f32(self.min.y);
+        hash.write_f32(self.min.z);
+        hash.write_f32(self.scale);
+        for v in &self.voxels {
+            match v {
+                Some(mat) => {
+                    hash.write(&[1]);
+                    hash.write(&mat.to_le_bytes());
+                }
+                None => hash.write(&[0]),
+            }
+        }
+        hash.finish()
+    }
+}
+
+/// Voxelised frames of an animation, all sharing the same grid size and placement
+pub struct VoxelAnimation {
+    /// Frames per second the animation was sampled at
+    pub fps: f32,
+    pub frames: Vec<VoxelGrid>,
+}
+
+/// Where the surface crosses the line between the centres of two neighbouring voxels
+#[derive(Debug, Clone, Copy)]
+pub struct EdgeIntersection {
+    /// World position of the crossing
+    pub pos: Vec3,
+    /// Normal of the surface there, pointing towards the empty voxel
+    pub normal: Vec3,
+}
+
+/// Hermite data for a voxel grid, giving the exact crossing of the surface on the edges between
+/// each filled voxel and its empty neighbours.
+///
+/// Edges are named by the voxel at their lower end and the axis they run along, and may start
+/// just outside of the grid.
+#[derive(Debug, Clone, Default)]
+pub struct HermiteData {
+    edges: HashMap<([i32; 3], usize), EdgeIntersection>,
+}
+
+impl HermiteData {
+    pub fn new() -> HermiteData {
+        HermiteData::default()
+    }
+
+    /// Crossings halfway along every edge between a filled and an empty voxel, facing straight
+    /// out of the filled one. This is all that can be worked out from the voxels alone.
+    pub fn from_grid(grid: &VoxelGrid) -> HermiteData {
+        let mut hermite = HermiteData::new();
+        let [res_x, res_y, res_z] = grid.size.map(|s| s as i32);
+        for x in -1..res_x {
+            for y in -1..res_y {
+                for z in -1..res_z {
+                    let lower = [x, y, z];
+                    for axis in 0..3 {
+                        let mut upper = lower;
+                        upper[axis] += 1;
+                        let (a, b) = (grid.is_filled(x, y, z), grid.is_filled(upper[0], upper[1], upper[2]));
+                        if a == b {
+                            continue;
+                        }
+
+                        let mut normal = Vec3::ZERO;
+                        normal[axis] = if a { 1.0 } else { -1.0 };
+                        let centre = |p: [i32; 3]| grid.min + (Vec3::new(p[0] as f32, p[1] as f32, p[2] as f32) + 0.5) * grid.scale;
+                        hermite.insert(lower, axis, EdgeIntersection {
+                            pos: (centre(lower) + centre(upper)) * 0.5,
+                            normal,
+                        });
+                    }
+                }
+            }
+        }
+        hermite
+    }
+
+    /// The crossing on the edge going from `voxel` along `axis`, if there is one
+    pub fn get(&self, voxel: [i32; 3], axis: usize) -> Option<&EdgeIntersection> {
+        self.edges.get(&(voxel, axis))
+    }
+
+    pub fn insert(&mut self, voxel: [i32; 3], axis: usize, intersection: EdgeIntersection) {
+        self.edges.insert((voxel, axis), intersection);
+    }
+
+    pub fn remove(&mut self, voxel: [i32; 3], axis: usize) {
+        self.edges.remove(&(voxel, axis));
+    }
+
+    /// Number of edges with a crossing
+    pub fn len(&self) -> usize {
+        self.edges.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.edges.is_empty()
+    }
+}
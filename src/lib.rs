@@ -0,0 +1,17 @@
+//! Turns 3D models into voxels.
+//!
+//! The library half of the crate only depends on the maths and model loading crates, the viewer
+//! (and the [`renderer`] module it uses) sits behind the `gui` feature.
+
+pub mod export;
+pub mod grid;
+pub mod mesher;
+pub mod model;
+pub mod palette;
+pub mod remesh;
+pub mod voxeliser;
+
+mod hash;
+
+#[cfg(feature = "gui")]
+pub mod renderer;
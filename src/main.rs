@@ -1,4 +1,14 @@
-use std::sync::mpsc::{Receiver, TryRecvError};
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{Receiver, TryRecvError},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
 use egui::{Align2, Vec2};
 use egui_winit::winit::{
@@ -6,21 +16,50 @@ use egui_winit::winit::{
     event::{Event, VirtualKeyCode, WindowEvent},
     window::WindowBuilder,
 };
-use glam::Vec3;
+use glam::{Mat4, Vec3};
 use glium::{Display, Surface};
 use glium_app::{context::Context, Application};
-use model::{loader, Model, vertex::Vertex};
-use renderer::Renderer;
 use rfd::FileDialog;
-use voxeliser::generate_voxels;
-
-pub mod model;
-pub mod renderer;
-pub mod voxeliser;
+use voxeliser::{
+    export::{
+        binvox,
+        gltf::{self, GltfColours},
+        minecraft::{
+            anvil::{self, AnvilSettings},
+            litematic::{self, LitematicMetadata},
+            mcstructure,
+            schematic::{self, SchematicVersion},
+            BlockMapping,
+        },
+        obj,
+        ply::{self, PlyFormat},
+        sequence,
+        stl::{self, StlSettings},
+        vox,
+    },
+    grid::{HermiteData, MaterialId, VoxelAnimation},
+    mesher::{chunks::ChunkedMesh, instances::voxel_instances, simplify::SimplifySettings, MeshStyle, VoxelMesh},
+    model::{
+        animation::{self, AnimatedModel},
+        loader, Model,
+    },
+    palette::Palette,
+    remesh::{generate_remesh, RemeshSettings},
+    renderer::{
+        buffers::{InstanceBuffers, ModelBuffers},
+        Renderer,
+    },
+    voxeliser::{check_scale, generate_animation, generate_voxels},
+};
 
 const SENSITIVITY: f32 = 0.05;
 const VELOCITY: f32 = 5.0;
 
+/// Number of voxels along the longest side of the model for live previews
+const PREVIEW_RESOLUTION: f32 = 32.0;
+/// How long the model has to be left alone before the preview is refined to the full voxel size
+const PREVIEW_SETTLE: Duration = Duration::from_millis(400);
+
 fn main() {
     let app = App::new();
     let wb = WindowBuilder::new()
@@ -31,15 +70,102 @@ fn main() {
     glium_app::run(app, wb);
 }
 
+/// What started the voxel model that is currently being generated
+#[derive(PartialEq, Eq, Clone, Copy)]
+enum VoxelJob {
+    /// The "Generate voxel model" button
+    Manual,
+    /// A live preview while the model is being moved
+    Coarse,
+    /// A live preview at the full voxel size after the model stopped moving
+    Refine,
+}
+
+/// How the voxel mesh is split into nodes when exported
+#[derive(PartialEq, Eq, Clone, Copy)]
+enum NodeSplit {
+    /// Everything in one node
+    Single,
+    /// A node for each chunk with anything in it
+    Chunks,
+    /// A node for each separate piece
+    Pieces,
+}
+
+/// A model placed in the scene, along with what's needed to draw it
+struct SceneModel {
+    name: String,
+    model: Model,
+    buffers: ModelBuffers,
+    /// Everything needed to animate the model, if it was imported as an animated glTF.
+    /// `model` is then its rest pose.
+    animated: Option<AnimatedModel>,
+}
+
+/// A voxelised animation being played back in the viewer
+struct PlayingAnimation {
+    anim: VoxelAnimation,
+    /// Buffers of each chunk of each frame
+    frames: Vec<Vec<ModelBuffers>>,
+    playing: bool,
+    time: f32,
+}
+
+/// Placement, material and priority of a model, used to tell when the scene has changed
+type ModelState = (Vec3, Vec3, f32, MaterialId, i32);
+
 struct App {
     renderer: Option<Renderer>,
 
-    model: Option<Model>,
-    voxel_model: Option<Model>,
-    incoming_voxel_model: Option<Receiver<(Vec<Vertex>, Vec<usize>)>>,
+    models: Vec<SceneModel>,
+    /// Index of the model being edited in "Model Settings"
+    selected: usize,
+    voxel_mesh: Option<ChunkedMesh>,
+    voxel_fingerprint: u64,
+    /// Buffers of each chunk of the voxel mesh that has anything in it
+    voxel_model: Vec<ModelBuffers>,
+    /// The voxels as copies of one cube, in place of `voxel_model` when drawing instanced
+    voxel_instances: Option<InstanceBuffers>,
+    instanced: bool,
+    incoming_voxel_model: Option<Receiver<ChunkedMesh>>,
+    /// Set to stop generating `incoming_voxel_model` once it's been replaced
+    voxel_cancel: Arc<AtomicBool>,
+
+    /// Index of the animation to voxelise from the selected model
+    animation: usize,
+    animation_fps: f32,
+    voxel_animation: Option<PlayingAnimation>,
+    incoming_animation: Option<Receiver<(VoxelAnimation, Vec<Vec<VoxelMesh>>)>>,
+    incoming_kind: VoxelJob,
     voxel_scale: f32,
+    mesh_style: MeshStyle,
+    simplify: bool,
+    /// Furthest simplifying may move the surface, in voxels
+    simplify_error: f32,
+
+    gltf_colours: GltfColours,
+    gltf_split: NodeSplit,
+    stl_settings: StlSettings,
+    ply_format: PlyFormat,
+    /// Author and description of saved Litematica schematics, which are named after the file
+    litematic_metadata: LitematicMetadata,
+    anvil_settings: AnvilSettings,
+
+    remesh_settings: RemeshSettings,
+    /// Remesh being generated along with where it is to be saved
+    incoming_remesh: Option<(Receiver<VoxelMesh>, PathBuf)>,
+
+    live_preview: bool,
+    /// State of each model as of the last frame
+    preview_scene: Vec<ModelState>,
+    /// The scene has changed since the last coarse preview was started
+    preview_dirty: bool,
+    /// When the scene last changed, cleared once the refined preview has been started
+    last_edit: Option<Instant>,
 
     mouse_grabbed: bool,
+    /// Message of the last thing that went wrong, shown until it's dismissed
+    error: Option<String>,
 }
 
 impl Application for App {
@@ -59,8 +185,11 @@ impl Application for App {
 
         if let Some(rx) = &mut self.incoming_voxel_model {
             match rx.try_recv() {
-                Ok((verts, inds)) => {
-                    self.voxel_model = Some(Model::new(&ctx.dis, verts, inds));
+                Ok(mesh) => {
+                    self.voxel_fingerprint = mesh.grid().fingerprint();
+                    self.voxel_mesh = Some(mesh);
+                    self.upload_voxels(&ctx.dis);
+                    self.voxel_animation = None;
                     self.incoming_voxel_model = None;
                 },
                 Err(TryRecvError::Disconnected) => panic!("Failed to receive incoming voxel model"),
@@ -68,28 +197,109 @@ impl Application for App {
             }
         }
 
+        if let Some((rx, path)) = &mut self.incoming_remesh {
+            match rx.try_recv() {
+                Ok(mesh) => {
+                    let result = File::create(&path).and_then(|f| obj::write_obj(&mesh, &mut BufWriter::new(f)));
+                    if let Err(e) = result {
+                        report(&mut self.error, format!("Failed to save {} - {}", path.display(), e));
+                    }
+
+                    // Show the repaired surface in place of the voxels
+                    self.voxel_model = upload_chunks(&ctx.dis, &[mesh], &mut self.error);
+                    self.voxel_instances = None;
+                    self.voxel_mesh = None;
+                    self.voxel_animation = None;
+                    self.live_preview = false;
+                    self.cancel_voxels();
+                    self.incoming_remesh = None;
+                },
+                Err(TryRecvError::Disconnected) => panic!("Failed to receive incoming remesh"),
+                _ => {}
+            }
+        }
+
+        if let Some(rx) = &mut self.incoming_animation {
+            match rx.try_recv() {
+                Ok((anim, meshes)) => {
+                    let frames = meshes
+                        .iter()
+                        .map(|chunks| upload_chunks(&ctx.dis, chunks, &mut self.error))
+                        .collect();
+                    self.voxel_animation = Some(PlayingAnimation {
+                        anim,
+                        frames,
+                        playing: true,
+                        time: 0.0,
+                    });
+                    self.incoming_animation = None;
+                },
+                Err(TryRecvError::Disconnected) => panic!("Failed to receive incoming voxel animation"),
+                _ => {}
+            }
+        }
+        if let Some(playing) = &mut self.voxel_animation {
+            if playing.playing {
+                playing.time += t.delta();
+            }
+        }
+        self.update_preview();
+
         // Render gui
         let mut target = ctx.dis.draw();
         target.clear_color_and_depth((0.5, 0.7, 0.8, 1.0), 1.0);
 
         // Scene
-        if let Some(model) = &self.model {
+        for m in &self.models {
             self.renderer
                 .as_mut()
                 .unwrap()
-                .render_model(&mut target, model);
+                .render_model(&mut target, &m.buffers, m.model.transform());
         }
-        if let Some(model) = &self.voxel_model {
-            self.renderer
-                .as_mut()
-                .unwrap()
-                .render_model(&mut target, model);
+        if let Some(playing) = &self.voxel_animation {
+            if let Some(chunks) = playing.frames.get(playing.frame()) {
+                for buffers in chunks {
+                    self.renderer
+                        .as_mut()
+                        .unwrap()
+                        .render_model(&mut target, buffers, Mat4::IDENTITY);
+                }
+            }
+        } else {
+            for buffers in &self.voxel_model {
+                self.renderer
+                    .as_mut()
+                    .unwrap()
+                    .render_model(&mut target, buffers, Mat4::IDENTITY);
+            }
+            if let Some(buffers) = &self.voxel_instances {
+                self.renderer
+                    .as_mut()
+                    .unwrap()
+                    .render_instances(&mut target, buffers, Mat4::IDENTITY);
+            }
         }
 
         // Gui
         let _ = ctx.gui.run(&ctx.dis, |gui_ctx| {
+            // Error message
+            if let Some(error) = &self.error {
+                let mut dismissed = false;
+                egui::Window::new("Error")
+                    .anchor(Align2::CENTER_TOP, Vec2::new(0.0, 10.0))
+                    .resizable(false)
+                    .collapsible(false)
+                    .show(gui_ctx, |ui| {
+                        ui.label(error);
+                        dismissed = ui.button("OK").clicked();
+                    });
+                if dismissed {
+                    self.error = None;
+                }
+            }
+
             // Import model prompt
-            if self.model.is_none() {
+            if self.models.is_empty() {
                 egui::Window::new("Load a model")
                     .anchor(Align2::CENTER_CENTER, Vec2::new(0.0, 0.0))
                     .resizable(false)
@@ -101,6 +311,12 @@ impl Application for App {
                                 self.import_model(&ctx.dis);
                             }
                         });
+                        ui.horizontal(|ui| {
+                            ui.label("or ");
+                            if ui.selectable_label(false, "import an animated glTF").clicked() {
+                                self.add_animated_model(&ctx.dis);
+                            }
+                        });
                     });
 
                 return;
@@ -113,7 +329,7 @@ impl Application for App {
                     if resp.clicked() {
                         self.import_model(&ctx.dis);
                     }
-                    resp.on_hover_text("This will remove the current model");
+                    resp.on_hover_text("This will remove the current models");
 
                     ui.collapsing("Camera settings", |ui| {
                         let resp = ui.button("Move Camera");
@@ -136,8 +352,24 @@ impl Application for App {
                         }
                     });
 
+                    ui.collapsing("Scene", |ui| {
+                        for (i, m) in self.models.iter().enumerate() {
+                            ui.selectable_value(&mut self.selected, i, m.name.as_str());
+                        }
+                        if ui.button("Add model").clicked() {
+                            self.add_model(&ctx.dis);
+                        }
+                        if ui.button("Add animated model").clicked() {
+                            self.add_animated_model(&ctx.dis);
+                        }
+                        if self.models.len() > 1 && ui.button("Remove model").clicked() {
+                            self.models.remove(self.selected);
+                            self.selected = self.selected.min(self.models.len() - 1);
+                        }
+                    });
+
                     // Model settings
-                    let model = self.model.as_mut().unwrap();
+                    let model = &mut self.models[self.selected].model;
                     ui.collapsing("Model Settings", |ui| {
                         if ui.button("Reset model").clicked() {
                             model.pos = Vec3::splat(0.0);
@@ -172,21 +404,384 @@ impl Application for App {
                             ui.add(egui::DragValue::new(&mut model.rot.z).speed(0.01));
                             ui.label("Rot Z");
                         });
+                        ui.horizontal(|ui| {
+                            ui.add(egui::DragValue::new(&mut model.material));
+                            ui.label("Material");
+                        });
+                        ui.horizontal(|ui| {
+                            ui.add(egui::DragValue::new(&mut model.priority));
+                            ui.label("Priority")
+                                .on_hover_text("Overlapping voxels go to the model with the highest priority");
+                        });
                     });
 
+                    if let Some(animated) = &self.models[self.selected].animated {
+                        ui.collapsing("Animation", |ui| {
+                            for (i, anim) in animated.animations.iter().enumerate() {
+                                ui.selectable_value(
+                                    &mut self.animation,
+                                    i,
+                                    format!("{} ({:.2}s)", anim.name, anim.duration),
+                                );
+                            }
+                            ui.horizontal(|ui| {
+                                ui.add(egui::DragValue::new(&mut self.animation_fps).speed(0.1).clamp_range(1.0..=120.0));
+                                ui.label("Frames per second");
+                            });
+
+                            if ui.button("Voxelise animation").clicked() {
+                                // Voxelise the animation wherever the model has been placed
+                                let model = &self.models[self.selected].model;
+                                let mut animated = animated.clone();
+                                animated.pos = model.pos;
+                                animated.rot = model.rot;
+                                animated.scale = model.scale;
+                                animated.material = model.material;
+
+                                self.incoming_animation = Some(generate_animation(
+                                    Arc::new(animated),
+                                    self.animation,
+                                    self.animation_fps,
+                                    self.voxel_scale,
+                                    self.mesh_style,
+                                ));
+                            }
+                            if self.incoming_animation.is_some() {
+                                ui.label("Voxelising animation...");
+                            }
+
+                            if let Some(playing) = &mut self.voxel_animation {
+                                ui.separator();
+                                ui.checkbox(&mut playing.playing, "Play");
+
+                                let last = playing.frames.len().saturating_sub(1);
+                                let mut frame = playing.frame();
+                                if ui.add(egui::Slider::new(&mut frame, 0..=last).text("Frame")).changed() {
+                                    playing.time = frame as f32 / playing.anim.fps;
+                                    playing.playing = false;
+                                }
+
+                                if ui.button("Export sequence").clicked() {
+                                    if let Some(pb) = FileDialog::new()
+                                        .add_filter("Voxel sequence", &["vxs"])
+                                        .save_file()
+                                    {
+                                        let result = File::create(&pb).and_then(|f| {
+                                            sequence::write_sequence(&playing.anim, &mut BufWriter::new(f))
+                                        });
+                                        if let Err(e) = result {
+                                            report(&mut self.error, format!("Failed to save {} - {}", pb.display(), e));
+                                        }
+                                    }
+                                }
+                                if ui.button("Stop showing animation").clicked() {
+                                    self.voxel_animation = None;
+                                }
+                            }
+                        });
+                    }
+
                     ui.collapsing("Voxel settings", |ui| {
                         if ui.button("Generate voxel model").clicked() {
-                            self.incoming_voxel_model = Some(generate_voxels(&self.model.as_ref().unwrap(), self.voxel_scale));
+                            self.start_voxels(self.voxel_scale, VoxelJob::Manual);
+                        }
+                        if ui.button("Open binvox").on_hover_text("Show a .binvox grid in place of the voxelised models").clicked() {
+                            self.open_binvox(&ctx.dis);
                         }
+                        ui.checkbox(&mut self.live_preview, "Live preview")
+                            .on_hover_text("Re-voxelise at a coarse size while the models are moved");
                         ui.horizontal(|ui| {
-                            ui.add(egui::DragValue::new(&mut self.voxel_scale).speed(0.01));
+                            ui.add(egui::DragValue::new(&mut self.voxel_scale).speed(0.01).clamp_range(0.001..=f32::MAX));
                             ui.label("Voxel size");
                         });
+                        ui.horizontal(|ui| {
+                            ui.radio_value(&mut self.mesh_style, MeshStyle::Blocks, "Blocks");
+                            ui.radio_value(&mut self.mesh_style, MeshStyle::Greedy, "Greedy")
+                                .on_hover_text("Merge neighbouring faces into larger rectangles");
+                            let smooth = matches!(self.mesh_style, MeshStyle::MarchingCubes { .. });
+                            if ui.radio(smooth, "Smooth").on_hover_text("Marching cubes surface through the voxels").clicked() && !smooth {
+                                self.mesh_style = MeshStyle::MarchingCubes { smoothing: 1 };
+                            }
+                            ui.radio_value(&mut self.mesh_style, MeshStyle::SurfaceNets, "Surface nets");
+                            ui.radio_value(&mut self.mesh_style, MeshStyle::DualContouring, "Sharp")
+                                .on_hover_text("Dual contouring, keeps the hard edges of the models");
+                            let bevelled = matches!(self.mesh_style, MeshStyle::Bevelled { .. });
+                            if ui.radio(bevelled, "Bevelled").on_hover_text("Blocks with their outer edges cut off or rounded").clicked() && !bevelled {
+                                self.mesh_style = MeshStyle::Bevelled { size: 0.2, rounding: 2 };
+                            }
+                        });
+                        if let MeshStyle::MarchingCubes { smoothing } = &mut self.mesh_style {
+                            ui.horizontal(|ui| {
+                                ui.add(egui::DragValue::new(smoothing).clamp_range(0..=8));
+                                ui.label("Smoothing");
+                            });
+                        }
+                        if let MeshStyle::Bevelled { size, rounding } = &mut self.mesh_style {
+                            ui.horizontal(|ui| {
+                                ui.add(egui::DragValue::new(size).speed(0.01).clamp_range(0.01..=0.5));
+                                ui.label("Bevel size (voxels)");
+                            });
+                            ui.horizontal(|ui| {
+                                ui.add(egui::DragValue::new(rounding).clamp_range(0..=8));
+                                ui.label("Rounding")
+                                    .on_hover_text("Steps around each rounded edge, 0 for flat chamfers");
+                            });
+                        }
+                        ui.horizontal(|ui| {
+                            ui.checkbox(&mut self.simplify, "Simplify")
+                                .on_hover_text("Merge flat areas and thin out the mesh, keeping its outline");
+                            if self.simplify {
+                                ui.add(egui::DragValue::new(&mut self.simplify_error).speed(0.01).clamp_range(0.0..=4.0));
+                                ui.label("Max error (voxels)");
+                            }
+                        });
+                        let instanced = ui
+                            .checkbox(&mut self.instanced, "Instanced")
+                            .on_hover_text("Draw each voxel as a copy of one cube rather than meshing them");
+                        if instanced.changed() {
+                            self.upload_voxels(&ctx.dis);
+                        }
+                        if let Some(mesh) = &self.voxel_mesh {
+                            let [x, y, z] = mesh.grid().size;
+                            ui.label(format!("Grid: {} x {} x {}", x, y, z));
+                            ui.label(format!("Fingerprint: {:016x}", self.voxel_fingerprint));
+                            let triangles: usize = mesh.meshes().iter().map(|m| m.inds.len() / 3).sum();
+                            ui.label(format!("Triangles: {}", triangles));
+                        }
+                        if self.incoming_kind != VoxelJob::Manual && self.incoming_voxel_model.is_some() {
+                            ui.label("Updating preview...");
+                        }
+                    });
+
+                    if let Some(mesh) = &self.voxel_mesh {
+                        ui.collapsing("Export", |ui| {
+                            if ui.button("MagicaVoxel (.vox)").clicked() {
+                                if let Some(pb) = FileDialog::new().add_filter("MagicaVoxel", &["vox"]).save_file() {
+                                    let result = File::create(&pb).and_then(|f| {
+                                        vox::write_vox(mesh.grid(), &Palette::new(), &mut BufWriter::new(f))
+                                    });
+                                    if let Err(e) = result {
+                                        report(&mut self.error, format!("Failed to save {} - {}", pb.display(), e));
+                                    }
+                                }
+                            }
+                            if ui.button("binvox (.binvox)").on_hover_text("Only which voxels are filled is saved").clicked() {
+                                if let Some(pb) = FileDialog::new().add_filter("binvox", &["binvox"]).save_file() {
+                                    let result = File::create(&pb).and_then(|f| binvox::write_binvox(mesh.grid(), &mut BufWriter::new(f)));
+                                    if let Err(e) = result {
+                                        report(&mut self.error, format!("Failed to save {} - {}", pb.display(), e));
+                                    }
+                                }
+                            }
+                            if ui.button("Wavefront OBJ (.obj)").on_hover_text("Saves a .mtl and palette .png next to it").clicked() {
+                                if let Some(pb) = FileDialog::new().add_filter("Wavefront OBJ", &["obj"]).save_file() {
+                                    if let Err(e) = obj::export_obj(&mesh.merged(), Some(&Palette::new()), &pb) {
+                                        report(&mut self.error, format!("Failed to save {} - {}", pb.display(), e));
+                                    }
+                                }
+                            }
+
+                            ui.separator();
+                            ui.horizontal(|ui| {
+                                ui.radio_value(&mut self.gltf_colours, GltfColours::Materials, "Materials");
+                                ui.radio_value(&mut self.gltf_colours, GltfColours::Vertex, "Vertex colours");
+                            });
+                            ui.horizontal(|ui| {
+                                ui.radio_value(&mut self.gltf_split, NodeSplit::Single, "One node");
+                                ui.radio_value(&mut self.gltf_split, NodeSplit::Chunks, "Node per chunk");
+                                ui.radio_value(&mut self.gltf_split, NodeSplit::Pieces, "Node per piece");
+                            });
+                            if ui.button("glTF (.gltf / .glb)").clicked() {
+                                if let Some(pb) = FileDialog::new().add_filter("glTF", &["glb", "gltf"]).save_file() {
+                                    let meshes = match self.gltf_split {
+                                        NodeSplit::Single => vec![mesh.merged()],
+                                        NodeSplit::Chunks => mesh.meshes().to_vec(),
+                                        NodeSplit::Pieces => mesh.merged().components(),
+                                    };
+                                    let palette = Palette::new();
+                                    let result = if pb.extension().is_some_and(|e| e == "gltf") {
+                                        gltf::export_gltf(&meshes, &palette, self.gltf_colours, &pb)
+                                    } else {
+                                        File::create(&pb).and_then(|f| {
+                                            gltf::write_glb(&meshes, &palette, self.gltf_colours, &mut BufWriter::new(f))
+                                        })
+                                    };
+                                    if let Err(e) = result {
+                                        report(&mut self.error, format!("Failed to save {} - {}", pb.display(), e));
+                                    }
+                                }
+                            }
+
+                            ui.separator();
+                            ui.horizontal(|ui| {
+                                ui.add(egui::DragValue::new(&mut self.stl_settings.mm_per_unit).speed(1.0).clamp_range(0.001..=100000.0));
+                                ui.label("mm per unit").on_hover_text("1000 for models made in metres");
+                            });
+                            if ui.button("STL for printing (.stl)").clicked() {
+                                let merged = mesh.merged();
+                                match stl::check_printable(&merged) {
+                                    Ok(()) => {
+                                        if let Some(pb) = FileDialog::new().add_filter("STL", &["stl"]).save_file() {
+                                            let result = File::create(&pb).and_then(|f| {
+                                                stl::write_stl(&merged, &self.stl_settings, &mut BufWriter::new(f))
+                                            });
+                                            if let Err(e) = result {
+                                                report(&mut self.error, format!("Failed to save {} - {}", pb.display(), e));
+                                            }
+                                        }
+                                    }
+                                    Err(e) => report(&mut self.error, format!("Can't print the voxel mesh - {}", e)),
+                                }
+                            }
+
+                            ui.separator();
+                            ui.horizontal(|ui| {
+                                ui.radio_value(&mut self.ply_format, PlyFormat::Binary, "Binary");
+                                ui.radio_value(&mut self.ply_format, PlyFormat::Ascii, "ASCII");
+                            });
+                            ui.horizontal(|ui| {
+                                let mesh_clicked = ui.button("PLY mesh (.ply)").clicked();
+                                let points_clicked = ui
+                                    .button("PLY voxel centres (.ply)")
+                                    .on_hover_text("A point for every filled voxel, for point cloud tools")
+                                    .clicked();
+                                if mesh_clicked || points_clicked {
+                                    if let Some(pb) = FileDialog::new().add_filter("PLY", &["ply"]).save_file() {
+                                        let palette = Palette::new();
+                                        let result = File::create(&pb).and_then(|f| {
+                                            let mut w = BufWriter::new(f);
+                                            if mesh_clicked {
+                                                ply::write_ply_mesh(&mesh.merged(), &palette, self.ply_format, &mut w)
+                                            } else {
+                                                ply::write_ply_points(mesh.grid(), &palette, self.ply_format, &mut w)
+                                            }
+                                        });
+                                        if let Err(e) = result {
+                                            report(&mut self.error, format!("Failed to save {} - {}", pb.display(), e));
+                                        }
+                                    }
+                                }
+                            });
+
+                            ui.separator();
+                            if ui
+                                .button("Sponge schematic (.schem)")
+                                .on_hover_text("Every voxel is placed as stone, for WorldEdit and other Minecraft tools")
+                                .clicked()
+                            {
+                                if let Some(pb) = FileDialog::new().add_filter("Sponge schematic", &["schem"]).save_file() {
+                                    let result = File::create(&pb).and_then(|f| {
+                                        schematic::write_schematic(
+                                            mesh.grid(),
+                                            &BlockMapping::default(),
+                                            SchematicVersion::V2,
+                                            &mut BufWriter::new(f),
+                                        )
+                                    });
+                                    if let Err(e) = result {
+                                        report(&mut self.error, format!("Failed to save {} - {}", pb.display(), e));
+                                    }
+                                }
+                            }
+                            ui.horizontal(|ui| {
+                                ui.text_edit_singleline(&mut self.litematic_metadata.author);
+                                ui.label("Author");
+                            });
+                            ui.horizontal(|ui| {
+                                ui.text_edit_singleline(&mut self.litematic_metadata.description);
+                                ui.label("Description");
+                            });
+                            if ui
+                                .button("Litematica (.litematic)")
+                                .on_hover_text("Every voxel is placed as stone, for building from with Litematica")
+                                .clicked()
+                            {
+                                if let Some(pb) = FileDialog::new().add_filter("Litematica", &["litematic"]).save_file() {
+                                    let metadata = LitematicMetadata {
+                                        name: pb.file_stem().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default(),
+                                        ..self.litematic_metadata.clone()
+                                    };
+                                    let result = File::create(&pb).and_then(|f| {
+                                        litematic::write_litematic(mesh.grid(), &BlockMapping::default(), &metadata, &mut BufWriter::new(f))
+                                    });
+                                    if let Err(e) = result {
+                                        report(&mut self.error, format!("Failed to save {} - {}", pb.display(), e));
+                                    }
+                                }
+                            }
+                            if ui
+                                .button("Bedrock structure (.mcstructure)")
+                                .on_hover_text("Every voxel is placed as stone, for loading with a structure block")
+                                .clicked()
+                            {
+                                if let Some(pb) = FileDialog::new().add_filter("Bedrock structure", &["mcstructure"]).save_file() {
+                                    let result = File::create(&pb).and_then(|f| {
+                                        mcstructure::write_mcstructure(mesh.grid(), &BlockMapping::default(), &mut BufWriter::new(f))
+                                    });
+                                    if let Err(e) = result {
+                                        report(&mut self.error, format!("Failed to save {} - {}", pb.display(), e));
+                                    }
+                                }
+                            }
+
+                            ui.separator();
+                            ui.horizontal(|ui| {
+                                for c in &mut self.anvil_settings.origin {
+                                    ui.add(egui::DragValue::new(c));
+                                }
+                                ui.label("World position")
+                                    .on_hover_text("Where the lowest corner of the voxels goes in the world");
+                            });
+                            ui.checkbox(&mut self.anvil_settings.replace_with_air, "Replace with air")
+                                .on_hover_text("Clear blocks where there aren't voxels, rather than leaving them");
+                            if ui
+                                .button("Into Minecraft world")
+                                .on_hover_text("Pick the region folder of a Java Edition world that isn't open in the game")
+                                .clicked()
+                            {
+                                if let Some(pb) = FileDialog::new().pick_folder() {
+                                    let result = anvil::write_region_files(mesh.grid(), &BlockMapping::default(), &self.anvil_settings, &pb);
+                                    if let Err(e) = result {
+                                        report(&mut self.error, format!("Failed to save into {} - {}", pb.display(), e));
+                                    }
+                                }
+                            }
+                        });
+                    }
+
+                    ui.collapsing("Remesh", |ui| {
+                        ui.label("Repair the models into one closed surface and save it");
+                        let simplify = self.simplify_settings(self.voxel_scale);
+                        let settings = &mut self.remesh_settings;
+                        ui.horizontal(|ui| {
+                            ui.add(egui::DragValue::new(&mut settings.closing).clamp_range(0..=8));
+                            ui.label("Close holes")
+                                .on_hover_text("Fill in holes and cracks up to about twice this many voxels across");
+                        });
+                        ui.horizontal(|ui| {
+                            ui.add(egui::DragValue::new(&mut settings.smoothing).clamp_range(0..=32));
+                            ui.label("Smoothing");
+                        });
+                        let remesh_clicked = ui.button("Remesh").on_hover_text("Uses the voxel size and style from the voxel settings").clicked();
+                        if remesh_clicked {
+                            if let Err(e) = check_scale(self.voxel_scale) {
+                                report(&mut self.error, format!("Can't remesh - {}", e));
+                            } else if let Some(pb) = FileDialog::new().add_filter("Wavefront OBJ", &["obj"]).save_file() {
+                                settings.voxel_size = self.voxel_scale;
+                                settings.style = self.mesh_style;
+                                settings.simplify = simplify;
+                                let rx = generate_remesh(self.models.iter().map(|m| &m.model), *settings);
+                                self.incoming_remesh = Some((rx, pb));
+                            }
+                        }
+                        if self.incoming_remesh.is_some() {
+                            ui.label("Remeshing...");
+                        }
                     });
                 });
 
                 // Generating voxel window
-                if self.incoming_voxel_model.is_some() {
+                if self.incoming_voxel_model.is_some() && self.incoming_kind == VoxelJob::Manual {
                     egui::Window::new("Generating voxels")
                     .anchor(Align2::CENTER_CENTER, Vec2::new(0.0, 0.0))
                     .collapsible(false)
@@ -227,30 +822,289 @@ impl Application for App {
     }
 }
 
+/// Log an error and keep it to show in the ui
+fn report(error: &mut Option<String>, msg: String) {
+    log::error!("{}", msg);
+    *error = Some(msg);
+}
+
+/// Upload the chunks of a voxel mesh that have anything in them. Chunks that can't be uploaded
+/// are reported and left out so the rest can still be shown.
+fn upload_chunks(dis: &Display, chunks: &[VoxelMesh], error: &mut Option<String>) -> Vec<ModelBuffers> {
+    chunks
+        .iter()
+        .filter(|mesh| !mesh.inds.is_empty())
+        .filter_map(|mesh| match ModelBuffers::new(dis, &mesh.verts, &mesh.inds) {
+            Ok(buffers) => Some(buffers),
+            Err(e) => {
+                report(error, format!("Failed to upload voxel chunk - {}", e));
+                None
+            }
+        })
+        .collect()
+}
+
+impl PlayingAnimation {
+    fn frame(&self) -> usize {
+        if self.frames.is_empty() {
+            return 0;
+        }
+        (self.time * self.anim.fps) as usize % self.frames.len()
+    }
+}
+
 impl App {
     pub fn new() -> App {
         App {
             renderer: None,
-            model: None,
-            voxel_model: None,
+            models: Vec::new(),
+            selected: 0,
+            voxel_mesh: None,
+            voxel_fingerprint: 0,
+            voxel_model: Vec::new(),
+            voxel_instances: None,
+            instanced: false,
             incoming_voxel_model: None,
+            voxel_cancel: Arc::default(),
+
+            animation: 0,
+            animation_fps: 12.0,
+            voxel_animation: None,
+            incoming_animation: None,
+            incoming_kind: VoxelJob::Manual,
             voxel_scale: 0.05,
+            mesh_style: MeshStyle::Blocks,
+            simplify: false,
+            simplify_error: 0.25,
+
+            gltf_colours: GltfColours::Materials,
+            gltf_split: NodeSplit::Single,
+            stl_settings: StlSettings::default(),
+            ply_format: PlyFormat::Binary,
+            litematic_metadata: LitematicMetadata::default(),
+            anvil_settings: AnvilSettings::default(),
+
+            remesh_settings: RemeshSettings::default(),
+            incoming_remesh: None,
+
+            live_preview: true,
+            preview_scene: Vec::new(),
+            preview_dirty: false,
+            last_edit: None,
             mouse_grabbed: false,
+            error: None,
+        }
+    }
+
+    /// Upload the voxel mesh for drawing, either by chunk or as instances of one cube
+    fn upload_voxels(&mut self, dis: &Display) {
+        let mesh = match &self.voxel_mesh {
+            Some(mesh) => mesh,
+            None => return,
+        };
+
+        if self.instanced {
+            self.voxel_model = Vec::new();
+            self.voxel_instances = match InstanceBuffers::new(dis, &voxel_instances(mesh.grid(), false)) {
+                Ok(buffers) => Some(buffers),
+                Err(e) => {
+                    report(&mut self.error, format!("Failed to upload voxel instances - {}", e));
+                    None
+                }
+            };
+        } else {
+            self.voxel_model = upload_chunks(dis, mesh.meshes(), &mut self.error);
+            self.voxel_instances = None;
         }
     }
 
+    /// Replace the scene with a newly picked model
     fn import_model(&mut self, dis: &Display) {
-        if let Some(pb) = FileDialog::new()
+        if let Some(model) = self.pick_model(dis) {
+            self.models = vec![model];
+            self.selected = 0;
+            self.preview_scene = self.scene_state();
+            self.preview_dirty = false;
+            self.last_edit = None;
+        }
+    }
+
+    /// Add a newly picked model to the scene
+    fn add_model(&mut self, dis: &Display) {
+        if let Some(model) = self.pick_model(dis) {
+            self.models.push(model);
+            self.selected = self.models.len() - 1;
+        }
+    }
+
+    /// Show a picked binvox grid as the voxel model, meshed with the current settings
+    fn open_binvox(&mut self, dis: &Display) {
+        let pb = match FileDialog::new().add_filter("binvox", &["binvox"]).pick_file() {
+            Some(pb) => pb,
+            None => return,
+        };
+        let grid = match File::open(&pb).and_then(|f| binvox::read_binvox(&mut BufReader::new(f), 0)) {
+            Ok(grid) => grid,
+            Err(e) => {
+                report(&mut self.error, format!("Failed to load {} - {}", pb.display(), e));
+                return;
+            }
+        };
+
+        let simplify = self.simplify_settings(grid.scale);
+        let mesh = ChunkedMesh::with_simplify(grid, HermiteData::new(), self.mesh_style, simplify);
+        self.voxel_fingerprint = mesh.grid().fingerprint();
+        self.voxel_mesh = Some(mesh);
+        self.upload_voxels(dis);
+        self.voxel_animation = None;
+        self.live_preview = false;
+        self.cancel_voxels();
+    }
+
+    /// Load a picked model, reporting why if it can't be
+    fn pick_model(&mut self, dis: &Display) -> Option<SceneModel> {
+        let pb = FileDialog::new()
             .add_filter("gltf/obj", &["gltf", "glb", "obj"])
-            .pick_file()
-        {
-            match loader::load_model(pb) {
-                Ok((verts, inds)) => self.model = Some(Model::new(dis, verts, inds)),
-                Err(e) => todo!("{}", e),
+            .pick_file()?;
+
+        let name = pb
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        match loader::load_model(pb.clone()) {
+            Ok((verts, inds)) => match ModelBuffers::new(dis, &verts, &inds) {
+                Ok(buffers) => Some(SceneModel {
+                    name,
+                    buffers,
+                    model: Model::new(verts, inds),
+                    animated: None,
+                }),
+                Err(e) => {
+                    report(&mut self.error, format!("Failed to upload {} - {}", name, e));
+                    None
+                }
+            },
+            Err(e) => {
+                report(&mut self.error, format!("Failed to load {} - {}", pb.display(), e));
+                None
             }
         }
     }
 
+    /// Add a newly picked animated glTF to the scene, shown in its rest pose
+    fn add_animated_model(&mut self, dis: &Display) {
+        let pb = match FileDialog::new().add_filter("gltf", &["gltf", "glb"]).pick_file() {
+            Some(pb) => pb,
+            None => return,
+        };
+
+        match animation::load_animated(&pb) {
+            Ok(animated) => {
+                let model = animated.rest_pose();
+                let buffers = match ModelBuffers::new(dis, &model.verts, &model.inds) {
+                    Ok(buffers) => buffers,
+                    Err(e) => {
+                        report(&mut self.error, format!("Failed to upload {} - {}", pb.display(), e));
+                        return;
+                    }
+                };
+                self.models.push(SceneModel {
+                    name: pb
+                        .file_name()
+                        .map(|n| n.to_string_lossy().into_owned())
+                        .unwrap_or_default(),
+                    buffers,
+                    model,
+                    animated: Some(animated),
+                });
+                self.selected = self.models.len() - 1;
+                self.animation = 0;
+            }
+            Err(e) => report(&mut self.error, format!("Failed to load {} - {}", pb.display(), e)),
+        }
+    }
+
+    fn scene_state(&self) -> Vec<ModelState> {
+        self.models
+            .iter()
+            .map(|m| (m.model.pos, m.model.rot, m.model.scale, m.model.material, m.model.priority))
+            .collect()
+    }
+
+    /// Keep a live preview of the voxels up to date while the models are being moved around.
+    ///
+    /// Coarse previews are generated back to back while the scene keeps changing, then once it
+    /// has been left alone for a moment the voxels are generated again at the full voxel size.
+    fn update_preview(&mut self) {
+        if !self.live_preview || self.models.is_empty() {
+            return;
+        }
+
+        let state = self.scene_state();
+        if state != self.preview_scene {
+            self.preview_scene = state;
+            self.preview_dirty = true;
+            self.last_edit = Some(Instant::now());
+        }
+
+        let last_edit = match self.last_edit {
+            Some(last_edit) => last_edit,
+            None => return,
+        };
+
+        if self.incoming_voxel_model.is_some() {
+            // Anything other than a coarse preview is out of date now, coarse ones are quick
+            // enough to let them finish
+            if self.preview_dirty && self.incoming_kind != VoxelJob::Coarse {
+                self.cancel_voxels();
+            } else {
+                return;
+            }
+        }
+
+        if self.preview_dirty {
+            let mut min = Vec3::splat(f32::INFINITY);
+            let mut max = Vec3::splat(f32::NEG_INFINITY);
+            for m in &self.models {
+                let bb = m.model.bounding_box();
+                min = min.min(bb.min);
+                max = max.max(bb.max);
+            }
+            let extent = (max - min).max_element();
+            let scale = (extent / PREVIEW_RESOLUTION).max(self.voxel_scale);
+
+            self.start_voxels(scale, VoxelJob::Coarse);
+            self.preview_dirty = false;
+        } else if last_edit.elapsed() >= PREVIEW_SETTLE {
+            self.start_voxels(self.voxel_scale, VoxelJob::Refine);
+            self.last_edit = None;
+        }
+    }
+
+    /// Start voxelising the models, stopping any voxels still being generated
+    fn start_voxels(&mut self, scale: f32, kind: VoxelJob) {
+        self.cancel_voxels();
+        let models = self.models.iter().map(|m| &m.model);
+        let cancel = Arc::new(AtomicBool::new(false));
+        self.incoming_voxel_model = Some(generate_voxels(models, scale, self.mesh_style, self.simplify_settings(scale), cancel.clone()));
+        self.voxel_cancel = cancel;
+        self.incoming_kind = kind;
+    }
+
+    /// Stop generating voxels, if they are
+    fn cancel_voxels(&mut self) {
+        self.voxel_cancel.store(true, Ordering::Relaxed);
+        self.incoming_voxel_model = None;
+    }
+
+    /// How the voxel meshes are simplified for voxels of the given size, if they are
+    fn simplify_settings(&self, scale: f32) -> Option<SimplifySettings> {
+        self.simplify.then(|| SimplifySettings {
+            ratio: 0.0,
+            max_error: self.simplify_error * scale,
+        })
+    }
+
     fn move_camera(&mut self, ctx: &Context, delta: f32) {
         if self.renderer.is_none() {
             panic!("Dum")
@@ -0,0 +1,378 @@
+use std::collections::HashMap;
+
+use glam::Vec3;
+
+use crate::{grid::{HermiteData, MaterialId, VoxelGrid}, hash::Fnv64, model::vertex::Vertex};
+
+use self::{bevel::Bevel, builder::MeshBuilder, marching_cubes::DensityField};
+
+pub mod bevel;
+mod builder;
+pub mod chunks;
+pub mod dual_contouring;
+pub mod greedy;
+pub mod instances;
+pub mod marching_cubes;
+pub mod simplify;
+
+/// How voxels are turned into a mesh
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MeshStyle {
+    /// A cube for every voxel
+    Blocks,
+    /// Neighbouring faces merged into larger rectangles, see [`greedy::greedy_mesh`]
+    Greedy,
+    /// A smooth surface through the voxels, see [`marching_cubes::marching_cubes`]
+    MarchingCubes {
+        /// Number of times the voxels are blurred before the surface is found, rounding it off
+        smoothing: usize,
+    },
+    /// A smooth surface with a vertex for each cell of voxel centres it passes through, see
+    /// [`dual_contouring::surface_nets`]
+    SurfaceNets,
+    /// Like surface nets, but keeping sharp edges and corners, see
+    /// [`dual_contouring::dual_contour`]
+    DualContouring,
+    /// A cube for every voxel with the edges and corners sticking out cut off or rounded, see
+    /// [`bevel::bevel_mesh`]
+    Bevelled {
+        /// How far the bevel reaches into each face, as a fraction of a voxel up to a half
+        size: f32,
+        /// Number of steps across each face's side of a rounded bevel, 0 for flat chamfers
+        rounding: usize,
+    },
+}
+
+impl MeshStyle {
+    /// If the style can make use of Hermite data from the source models, see
+    /// [`mesh_with_hermite`]
+    pub fn uses_hermite(&self) -> bool {
+        matches!(self, MeshStyle::SurfaceNets | MeshStyle::DualContouring)
+    }
+
+    /// How many voxels away changing a voxel can change the mesh
+    fn reach(&self) -> usize {
+        match self {
+            MeshStyle::Blocks | MeshStyle::Greedy => 1,
+            MeshStyle::MarchingCubes { smoothing } => smoothing + 2,
+            MeshStyle::SurfaceNets | MeshStyle::DualContouring | MeshStyle::Bevelled { .. } => 2,
+        }
+    }
+}
+
+/// Density a smooth surface is put at when meshing a grid. Just under half, as smoothing leaves
+/// lone voxels at exactly 0.5 and surface vertices landing right on samples give flat triangles.
+const SURFACE_LEVEL: f32 = 0.45;
+
+/// Mesh the grid in the given style
+pub fn mesh(grid: &VoxelGrid, style: MeshStyle) -> VoxelMesh {
+    mesh_region(grid, &HermiteData::new(), style, Region::whole(grid))
+}
+
+/// Mesh the grid in the given style, using Hermite data from
+/// [`voxelise_scene_hermite`](crate::voxeliser::voxelise_scene_hermite) to place the surface
+/// where it really was on the source models. Styles that don't use it ignore it.
+pub fn mesh_with_hermite(grid: &VoxelGrid, hermite: &HermiteData, style: MeshStyle) -> VoxelMesh {
+    mesh_region(grid, hermite, style, Region::whole(grid))
+}
+
+/// Mesh only the part of the grid in `region`, looking outside of it only to see what covers or
+/// shades its faces. Meshes of regions next to each other meet up without gaps or overlaps.
+///
+/// Edges missing from the Hermite data are crossed halfway, facing straight out of the filled
+/// voxel.
+fn mesh_region(grid: &VoxelGrid, hermite: &HermiteData, style: MeshStyle, region: Region) -> VoxelMesh {
+    let cells = region.cells(grid.size);
+    match style {
+        MeshStyle::Blocks => block_mesh_region(grid, region, None),
+        MeshStyle::Greedy => greedy::greedy_mesh_region(grid, region),
+        MeshStyle::MarchingCubes { smoothing } => {
+            // Each pass of smoothing reaches one voxel further, and the cubes and gradients reach
+            // another two past that
+            let mut field = DensityField::from_grid_region(grid, region, smoothing + 2);
+            for _ in 0..smoothing {
+                field = field.smoothed();
+            }
+            marching_cubes::marching_cubes_cells(&field, SURFACE_LEVEL, cells)
+        }
+        MeshStyle::SurfaceNets => dual_contouring::surface_nets_cells(grid, hermite, cells),
+        MeshStyle::DualContouring => dual_contouring::dual_contour_cells(grid, hermite, cells),
+        MeshStyle::Bevelled { size, rounding } => block_mesh_region(grid, region, Bevel::new(size, rounding)),
+    }
+}
+
+/// A box of voxels in a grid, from `min` up to but not including `max`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Region {
+    min: [usize; 3],
+    max: [usize; 3],
+}
+
+impl Region {
+    fn whole(grid: &VoxelGrid) -> Region {
+        Region {
+            min: [0; 3],
+            max: grid.size,
+        }
+    }
+
+    /// The cells between voxel centres that belong to the region, named by their lowest voxel,
+    /// from the first up to but not including the second.
+    ///
+    /// Each cell goes with the voxel at its highest corner, except that cells hanging off the
+    /// top of the grid go with the region at the top. That way regions covering the grid cover
+    /// every cell exactly once, including those one past the grid on each side.
+    fn cells(&self, size: [usize; 3]) -> ([i32; 3], [i32; 3]) {
+        let min = self.min.map(|m| m as i32 - 1);
+        let max = [0, 1, 2].map(|a| if self.max[a] == size[a] { size[a] as i32 } else { self.max[a] as i32 - 1 });
+        (min, max)
+    }
+
+    /// If a grid point lies on a side of the region that's shared with another region
+    fn on_seam(&self, point: [i32; 3], size: [usize; 3]) -> bool {
+        (0..3).any(|a| {
+            (point[a] == self.min[a] as i32 && self.min[a] > 0) || (point[a] == self.max[a] as i32 && self.max[a] < size[a])
+        })
+    }
+}
+
+/// Triangle mesh built from a voxel grid, in world space.
+///
+/// Meshes are built by walking the grid in x, y then z order, so the same grid always gives the
+/// same vertices in the same order.
+#[derive(Default, Clone)]
+pub struct VoxelMesh {
+    pub verts: Vec<Vertex>,
+    pub inds: Vec<usize>,
+    /// Material of the voxel each vertex came from
+    pub materials: Vec<MaterialId>,
+}
+
+impl VoxelMesh {
+    /// A hash of the vertices and indices, stable across builds and platforms
+    pub fn fingerprint(&self) -> u64 {
+        let mut hash = Fnv64::new();
+        hash.write_u64(self.verts.len() as u64);
+        for v in &self.verts {
+            for f in v.pos.iter().chain(v.norm.iter()) {
+                hash.write_f32(*f);
+            }
+            hash.write_f32(v.ao);
+        }
+        hash.write_u64(self.inds.len() as u64);
+        for i in &self.inds {
+            hash.write_u64(*i as u64);
+        }
+        for m in &self.materials {
+            hash.write(&m.to_le_bytes());
+        }
+        hash.finish()
+    }
+
+    /// Work out smooth vertex normals from the triangles around each vertex, weighted by their
+    /// area
+    pub fn recompute_normals(&mut self) {
+        let mut normals = vec![Vec3::ZERO; self.verts.len()];
+        for t in self.inds.chunks_exact(3) {
+            let [a, b, c] = [t[0], t[1], t[2]].map(|i| self.verts[i].pos_vec());
+            // Twice the area, so bigger triangles count for more
+            let normal = (b - a).cross(c - a);
+            for i in t {
+                normals[*i] += normal;
+            }
+        }
+        for (v, n) in self.verts.iter_mut().zip(normals) {
+            if let Some(n) = n.try_normalize() {
+                v.norm = n.into();
+            }
+        }
+    }
+
+    /// Split the mesh into the separate pieces it is made of, in the order of their first
+    /// triangles. Triangles that touch, even at a single point, are in the same piece.
+    pub fn components(&self) -> Vec<VoxelMesh> {
+        fn root(parents: &mut [usize], mut i: usize) -> usize {
+            while parents[i] != i {
+                parents[i] = parents[parents[i]];
+                i = parents[i];
+            }
+            i
+        }
+
+        // Vertices in the same place are joined even if they don't share an index
+        let mut parents: Vec<usize> = (0..self.verts.len()).collect();
+        let mut at = HashMap::new();
+        for (i, v) in self.verts.iter().enumerate() {
+            let first = *at.entry(v.pos.map(f32::to_bits)).or_insert(i);
+            let (a, b) = (root(&mut parents, first), root(&mut parents, i));
+            parents[b] = a;
+        }
+        for t in self.inds.chunks_exact(3) {
+            for i in &t[1..] {
+                let (a, b) = (root(&mut parents, t[0]), root(&mut parents, *i));
+                parents[b] = a;
+            }
+        }
+
+        let mut pieces: Vec<VoxelMesh> = Vec::new();
+        let mut piece_of = HashMap::new();
+        let mut remap = vec![usize::MAX; self.verts.len()];
+        for t in self.inds.chunks_exact(3) {
+            let piece = *piece_of.entry(root(&mut parents, t[0])).or_insert_with(|| {
+                pieces.push(VoxelMesh::default());
+                pieces.len() - 1
+            });
+            let mesh = &mut pieces[piece];
+            for i in t {
+                if remap[*i] == usize::MAX {
+                    remap[*i] = mesh.verts.len();
+                    mesh.verts.push(self.verts[*i]);
+                    mesh.materials.push(self.materials[*i]);
+                }
+                mesh.inds.push(remap[*i]);
+            }
+        }
+        pieces
+    }
+}
+
+/// Create a mesh with a cube for each voxel, leaving out faces that are hidden by a neighbour.
+///
+/// Faces facing the same way with the same material share the vertices at their corners.
+pub fn block_mesh(grid: &VoxelGrid) -> VoxelMesh {
+    block_mesh_region(grid, Region::whole(grid), None)
+}
+
+/// Block mesh only the voxels in `region`, bevelling their faces if a bevel is given
+fn block_mesh_region(grid: &VoxelGrid, region: Region, bevel: Option<Bevel>) -> VoxelMesh {
+    let (min, max) = (region.min, region.max);
+
+    let mut builder = MeshBuilder::new(grid);
+    for x in min[0]..max[0] {
+        for y in min[1]..max[1] {
+            for z in min[2]..max[2] {
+                let material = match grid.get(x, y, z) {
+                    Some(material) => material,
+                    None => continue,
+                };
+
+                let (ix, iy, iz) = (x as i32, y as i32, z as i32);
+                let px = grid.is_filled(ix + 1, iy, iz);
+                let py = grid.is_filled(ix, iy + 1, iz);
+                let pz = grid.is_filled(ix, iy, iz + 1);
+                let nx = grid.is_filled(ix - 1, iy, iz);
+                let ny = grid.is_filled(ix, iy - 1, iz);
+                let nz = grid.is_filled(ix, iy, iz - 1);
+
+                generate_block_mesh(&mut builder, grid, [ix, iy, iz], material, bevel, px, py, pz, nx, ny, nz);
+            }
+        }
+    }
+
+    builder.finish()
+}
+
+#[allow(clippy::too_many_arguments)]
+fn generate_block_mesh(
+    builder: &mut MeshBuilder,
+    grid: &VoxelGrid,
+    pos: [i32; 3],
+    material: MaterialId,
+    bevel: Option<Bevel>,
+    px: bool,
+    py: bool,
+    pz: bool,
+    nx: bool,
+    ny: bool,
+    nz: bool,
+) {
+    // Axis and direction of each face, along with whether it's covered
+    let faces = [
+        (1, 1, py),
+        (1, -1, ny),
+        (2, -1, nz),
+        (0, 1, px),
+        (2, 1, pz),
+        (0, -1, nx),
+    ];
+
+    for (d, dir, covered) in faces {
+        if covered {
+            continue;
+        }
+        let (u, v) = ((d + 1) % 3, (d + 2) % 3);
+        let plane = if dir > 0 { pos[d] + 1 } else { pos[d] };
+        let corner = |a: i32, b: i32| {
+            let mut c = [0; 3];
+            c[d] = plane;
+            c[u] = pos[u] + a;
+            c[v] = pos[v] + b;
+            c
+        };
+        let mut front = pos;
+        front[d] += dir;
+
+        // u cross v is d, so this goes anti-clockwise looking back along +d
+        let mut corners = [corner(0, 0), corner(1, 0), corner(1, 1), corner(0, 1)];
+        if dir < 0 {
+            corners.reverse();
+        }
+        let ao = corners.map(|c| ao_brightness(vertex_ao(grid, c, d, front)));
+        let mut normal = [0.0; 3];
+        normal[d] = dir as f32;
+
+        match bevel {
+            Some(bevel) => bevel.face(builder, grid, corners, normal, material, ao),
+            None => {
+                let corners = corners.map(|c| Vec3::new(c[0] as f32, c[1] as f32, c[2] as f32));
+                builder.quad(corners, normal, material, ao);
+            }
+        }
+    }
+}
+
+/// Ambient occlusion at a grid point on a face pointing along axis `d`, from 0 for the darkest
+/// to 3 for nothing in the way.
+///
+/// `front` is the empty voxel the face looks into, and the point must be one of its corners on
+/// the face. The voxels next to `front` on either side of the point, and the one diagonally
+/// across from it, are what block the light. Two sides being filled closes off the corner
+/// completely, whatever is in the diagonal.
+fn vertex_ao(grid: &VoxelGrid, point: [i32; 3], d: usize, front: [i32; 3]) -> u8 {
+    let (u, v) = ((d + 1) % 3, (d + 2) % 3);
+    // The other voxel touching the point along an axis
+    let across = |mut voxel: [i32; 3], axis: usize| {
+        voxel[axis] = if voxel[axis] == point[axis] { point[axis] - 1 } else { point[axis] };
+        voxel
+    };
+    let filled = |voxel: [i32; 3]| grid.is_filled(voxel[0], voxel[1], voxel[2]);
+
+    let side_u = filled(across(front, u));
+    let side_v = filled(across(front, v));
+    let diagonal = filled(across(across(front, u), v));
+    if side_u && side_v {
+        0
+    } else {
+        3 - side_u as u8 - side_v as u8 - diagonal as u8
+    }
+}
+
+/// How bright a vertex with the given ambient occlusion level is
+fn ao_brightness(level: u8) -> f32 {
+    0.4 + 0.2 * level as f32
+}
+
+/// Number of edges that don't have as many triangles going along them each way, with vertices
+/// in the same place counting as one, so 0 for a closed mesh
+#[cfg(test)]
+pub(crate) fn open_edges(mesh: &VoxelMesh) -> usize {
+    let mut edges: HashMap<([u32; 3], [u32; 3]), i32> = HashMap::new();
+    for t in mesh.inds.chunks_exact(3) {
+        for i in 0..3 {
+            let (a, b) = (mesh.verts[t[i]].pos.map(f32::to_bits), mesh.verts[t[(i + 1) % 3]].pos.map(f32::to_bits));
+            let (key, step) = if a < b { ((a, b), 1) } else { ((b, a), -1) };
+            *edges.entry(key).or_default() += step;
+        }
+    }
+    edges.values().filter(|c| **c != 0).count()
+}
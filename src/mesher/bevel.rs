@@ -0,0 +1,218 @@
+use glam::Vec3;
+
+use crate::grid::{MaterialId, VoxelGrid};
+
+use super::{block_mesh_region, builder::MeshBuilder, Region, VoxelMesh};
+
+/// Leeway given when working out which voxels a point's surroundings touch, so points that land
+/// a rounding error away from a voxel's side don't count as touching it
+const EPSILON: f32 = 1e-4;
+
+/// Create a mesh of cubes with their exposed edges and corners cut off by `size` (as a fraction
+/// of a voxel, up to a half), or rounded off with `rounding` steps across each face's side of
+/// the bevel. A `rounding` of 0 gives flat chamfers.
+///
+/// Only edges sticking out of the model are bevelled. Edges between faces that carry on flat
+/// across voxels, and edges in the corners between voxels, are left sharp. The shape is what's
+/// left of the voxels after rolling a ball (or for chamfers an octahedron) of radius `size`
+/// around everywhere inside of them.
+///
+/// Every face is split into `(2 * rounding + 1)²` quads (3 by 3 for chamfers) whether it's next
+/// to a bevel or not, so it's worth [simplifying](super::simplify::simplify) the mesh afterwards.
+pub fn bevel_mesh(grid: &VoxelGrid, size: f32, rounding: usize) -> VoxelMesh {
+    block_mesh_region(grid, Region::whole(grid), Bevel::new(size, rounding))
+}
+
+/// Most steps across each face's side of a rounded bevel
+const MAX_ROUNDING: usize = 16;
+
+/// How the faces of a block mesh are bevelled
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Bevel {
+    size: f32,
+    rounding: usize,
+}
+
+impl Bevel {
+    /// A bevel of the given size, as long as there's anything to bevel
+    pub fn new(size: f32, rounding: usize) -> Option<Bevel> {
+        if size.is_nan() || size <= 0.0 {
+            return None;
+        }
+        Some(Bevel {
+            size: size.min(0.5),
+            rounding: rounding.min(MAX_ROUNDING),
+        })
+    }
+
+    /// Lines across one side of a face, each as the end of the side it is measured from and how
+    /// far it is from there
+    fn lines(&self) -> Vec<(i32, f32)> {
+        let count = self.steps();
+        let from_start = (0..=count).map(|i| (0, self.step(i)));
+        let from_end = (0..=count).rev().map(|i| (1, -self.step(i)));
+        from_start.chain(from_end).collect()
+    }
+
+    /// Number of steps across each face's side of the bevel
+    fn steps(&self) -> usize {
+        self.rounding.max(1)
+    }
+
+    /// How far the `i`th line across a face's side of the bevel is from the end of the side
+    fn step(&self, i: usize) -> f32 {
+        self.size * i as f32 / self.steps() as f32
+    }
+
+    /// Move each part of a point that is a rounding error away from one of the lines faces are
+    /// split along exactly onto it. Points moved onto the surface can land on a line that other
+    /// points started on, and have to come out exactly the same for the faces to meet up.
+    fn snap(&self, p: Vec3) -> Vec3 {
+        p.to_array()
+            .map(|x| {
+                let mut best = (EPSILON, x);
+                let whole = x.round() as i32;
+                for k in whole - 1..=whole + 1 {
+                    for i in 0..=self.steps() {
+                        for offset in [self.step(i), -self.step(i)] {
+                            // Built the same way as the points of a face
+                            let line = k as f32 + offset;
+                            if (line - x).abs() < best.0 {
+                                best = ((line - x).abs(), line);
+                            }
+                        }
+                    }
+                }
+                best.1
+            })
+            .into()
+    }
+
+    /// Add a face of a voxel, with its corners going anti-clockwise when looked at from the front
+    /// and the ambient occlusion at each of them.
+    ///
+    /// The face is split into a grid finer near its edges, then each point of the grid is moved
+    /// onto the bevelled surface. Where each point ends up only depends on where it started and
+    /// the voxels around it, so the faces of neighbouring voxels still meet up.
+    pub fn face(&self, builder: &mut MeshBuilder, grid: &VoxelGrid, corners: [[i32; 3]; 4], normal: [f32; 3], material: MaterialId, ao: [f32; 4]) {
+        let facing = Vec3::from(normal);
+        let along_a = [0, 1, 2].map(|i| corners[1][i] - corners[0][i]);
+        let along_b = [0, 1, 2].map(|i| corners[3][i] - corners[0][i]);
+        let lines = self.lines();
+
+        // Built from whole numbers and the steps so the same point always comes out the same
+        let point = |(end_a, off_a): (i32, f32), (end_b, off_b): (i32, f32)| {
+            let mut p = Vec3::ZERO;
+            for i in 0..3 {
+                let whole = corners[0][i] + along_a[i] * end_a + along_b[i] * end_b;
+                p[i] = whole as f32 + (along_a[i] as f32 * off_a + along_b[i] as f32 * off_b);
+            }
+            let (a, b) = (end_a as f32 + off_a, end_b as f32 + off_b);
+            let occlusion = (1.0 - a) * (1.0 - b) * ao[0] + a * (1.0 - b) * ao[1] + a * b * ao[2] + (1.0 - a) * b * ao[3];
+            let (pos, norm) = self.surface_point(grid, p, facing);
+            (pos, norm, occlusion)
+        };
+
+        let grid_points: Vec<Vec<(Vec3, Vec3, f32)>> = lines
+            .iter()
+            .map(|b| lines.iter().map(|a| point(*a, *b)).collect())
+            .collect();
+
+        for j in 0..lines.len() - 1 {
+            for i in 0..lines.len() - 1 {
+                let quad = [grid_points[j][i], grid_points[j][i + 1], grid_points[j + 1][i + 1], grid_points[j + 1][i]];
+                for [x, y, z] in [[0, 1, 2], [0, 2, 3]] {
+                    self.triangle(builder, [quad[x], quad[y], quad[z]], material);
+                }
+            }
+        }
+    }
+
+    fn triangle(&self, builder: &mut MeshBuilder, points: [(Vec3, Vec3, f32); 3], material: MaterialId) {
+        let [(a, _, _), (b, _, _), (c, _, _)] = points;
+        // Squashed to nothing where a face's side of the bevel has no width. Triangles squashed
+        // into a line still join up the triangles around them so they're kept.
+        if a == b || b == c || c == a {
+            return;
+        }
+        let flat = (b - a).cross(c - a).try_normalize().unwrap_or(points[0].1);
+
+        let [a, b, c] = points.map(|(pos, norm, ao)| {
+            // Chamfers are flat, so they are shaded flat too
+            let norm = if self.rounding == 0 { flat } else { norm };
+            builder.vertex(pos, norm.into(), material, ao)
+        });
+        builder.triangle(a, b, c);
+    }
+
+    /// Where a point on the side of a voxel ends up, along with the surface normal there.
+    ///
+    /// The insides of the voxels are first shrunk by the bevel size, leaving the points whose
+    /// surroundings (out to the bevel size along each axis) are all filled. The point is then moved
+    /// to the bevel size away from the nearest of those, straight towards it for rounded bevels or
+    /// counting the distance along each axis for chamfers.
+    fn surface_point(&self, grid: &VoxelGrid, p: Vec3, normal: Vec3) -> (Vec3, Vec3) {
+        let size = self.size;
+
+        // Nothing can be closer than straight in, so flat parts of faces stay where they are
+        if self.is_inside(grid, p - normal * size) {
+            return (p, normal);
+        }
+
+        let candidates = |x: f32| -> Vec<f32> {
+            let base = x.floor() as i32;
+            let mut values = vec![x];
+            for k in base - 1..=base + 2 {
+                for v in [k as f32 - size, k as f32 + size] {
+                    if (v - x).abs() <= 1.0 {
+                        values.push(v);
+                    }
+                }
+            }
+            values
+        };
+        let distance = |d: Vec3| if self.rounding == 0 { d.x.abs() + d.y.abs() + d.z.abs() } else { d.length() };
+
+        let mut nearest: Option<(f32, Vec3)> = None;
+        for x in candidates(p.x) {
+            for y in candidates(p.y) {
+                for z in candidates(p.z) {
+                    let e = Vec3::new(x, y, z);
+                    let dist = distance(p - e);
+                    let closer = match nearest {
+                        Some((best, _)) => dist < best,
+                        None => true,
+                    };
+                    if closer && self.is_inside(grid, e) {
+                        nearest = Some((dist, e));
+                    }
+                }
+            }
+        }
+
+        match nearest {
+            Some((dist, e)) if dist > 0.0 => {
+                let out = p - e;
+                (self.snap(e + out * (size / dist)), out.normalize())
+            }
+            _ => (p, normal),
+        }
+    }
+
+    /// If every voxel within the bevel size of a point along each axis is filled
+    fn is_inside(&self, grid: &VoxelGrid, p: Vec3) -> bool {
+        let reach = self.size - EPSILON;
+        let lo = (p - reach).floor();
+        let hi = (p + reach).ceil() - 1.0;
+        for x in lo.x as i32..=hi.x as i32 {
+            for y in lo.y as i32..=hi.y as i32 {
+                for z in lo.z as i32..=hi.z as i32 {
+                    if !grid.is_filled(x, y, z) {
+                        return false;
+                    }
+                }
+            }
+        }
+        true
+    }
+}
@@ -0,0 +1,81 @@
+use std::collections::HashMap;
+
+use glam::Vec3;
+
+use crate::{grid::{MaterialId, VoxelGrid}, model::vertex::Vertex};
+
+use super::VoxelMesh;
+
+/// Builds up an indexed mesh from faces given in grid coordinates.
+///
+/// Faces facing the same way with the same material and ambient occlusion share their vertices,
+/// while faces facing different ways keep their own so the normals stay flat. Vertices come out in the order they
+/// are first used.
+pub(crate) struct MeshBuilder {
+    min: Vec3,
+    scale: f32,
+
+    verts: Vec<Vertex>,
+    inds: Vec<usize>,
+    materials: Vec<MaterialId>,
+
+    lookup: HashMap<([u32; 3], [u32; 3], MaterialId, u32), usize>,
+}
+
+impl MeshBuilder {
+    pub fn new(grid: &VoxelGrid) -> MeshBuilder {
+        MeshBuilder {
+            min: grid.min,
+            scale: grid.scale,
+
+            verts: Vec::new(),
+            inds: Vec::new(),
+            materials: Vec::new(),
+
+            lookup: HashMap::new(),
+        }
+    }
+
+    /// Index of the vertex at a point in grid coordinates, creating it if no face facing the same
+    /// way with the same material and ambient occlusion has used it yet
+    pub fn vertex(&mut self, pos: Vec3, normal: [f32; 3], material: MaterialId, ao: f32) -> usize {
+        let key = (pos.to_array().map(f32::to_bits), normal.map(f32::to_bits), material, ao.to_bits());
+        if let Some(i) = self.lookup.get(&key) {
+            return *i;
+        }
+
+        let i = self.verts.len();
+        self.verts.push(Vertex::new((self.min + pos * self.scale).into(), normal).with_ao(ao));
+        self.materials.push(material);
+        self.lookup.insert(key, i);
+        i
+    }
+
+    pub fn triangle(&mut self, a: usize, b: usize, c: usize) {
+        self.inds.extend([a, b, c]);
+    }
+
+    /// Add a quad with its corners going anti-clockwise when looked at from the front.
+    ///
+    /// The quad is split along the diagonal between its brighter corners, otherwise the
+    /// occlusion is stretched across the wrong diagonal and faces look different depending on
+    /// which way they point.
+    pub fn quad(&mut self, corners: [Vec3; 4], normal: [f32; 3], material: MaterialId, ao: [f32; 4]) {
+        let [a, b, c, d] = [0, 1, 2, 3].map(|i| self.vertex(corners[i], normal, material, ao[i]));
+        if ao[0] + ao[2] >= ao[1] + ao[3] {
+            self.triangle(a, b, c);
+            self.triangle(a, c, d);
+        } else {
+            self.triangle(b, c, d);
+            self.triangle(b, d, a);
+        }
+    }
+
+    pub fn finish(self) -> VoxelMesh {
+        VoxelMesh {
+            verts: self.verts,
+            inds: self.inds,
+            materials: self.materials,
+        }
+    }
+}
@@ -0,0 +1,232 @@
+use crate::grid::{HermiteData, MaterialId, VoxelGrid};
+
+use super::{mesh_region, simplify::{simplify, SimplifySettings}, MeshStyle, Region, VoxelMesh};
+
+/// Number of voxels along each side of a chunk
+pub const CHUNK_SIZE: usize = 32;
+
+/// A voxel grid meshed in chunks of [`CHUNK_SIZE`] voxels a side, each with a mesh of its own.
+///
+/// However big the grid gets, each chunk's mesh stays small enough to index with 32 bits and to
+/// upload on its own. Changing a voxel only means meshing the chunks around it again.
+pub struct ChunkedMesh {
+    grid: VoxelGrid,
+    hermite: HermiteData,
+    style: MeshStyle,
+    simplify: Option<SimplifySettings>,
+    /// Number of chunks along the x, y and z axes
+    pub chunks: [usize; 3],
+
+    meshes: Vec<VoxelMesh>,
+    dirty: Vec<bool>,
+}
+
+impl ChunkedMesh {
+    /// Mesh every chunk of the grid
+    pub fn new(grid: VoxelGrid, style: MeshStyle) -> ChunkedMesh {
+        ChunkedMesh::with_hermite(grid, HermiteData::new(), style)
+    }
+
+    /// Mesh every chunk of the grid, using Hermite data for the styles that can, see
+    /// [`mesh_with_hermite`](super::mesh_with_hermite)
+    pub fn with_hermite(grid: VoxelGrid, hermite: HermiteData, style: MeshStyle) -> ChunkedMesh {
+        ChunkedMesh::with_simplify(grid, hermite, style, None)
+    }
+
+    /// Mesh every chunk of the grid like [`with_hermite`](ChunkedMesh::with_hermite), simplifying
+    /// each chunk's mesh once it is built. Chunks are simplified on their own, but the sides they
+    /// share are left alone so they still meet up.
+    pub fn with_simplify(grid: VoxelGrid, hermite: HermiteData, style: MeshStyle, simplify: Option<SimplifySettings>) -> ChunkedMesh {
+        let chunks = chunk_count(grid.size);
+        let count = chunks[0] * chunks[1] * chunks[2];
+        let mut chunked = ChunkedMesh {
+            grid,
+            hermite,
+            style,
+            simplify,
+            chunks,
+
+            meshes: (0..count).map(|_| VoxelMesh::default()).collect(),
+            dirty: vec![true; count],
+        };
+        chunked.rebuild();
+        chunked
+    }
+
+    pub fn grid(&self) -> &VoxelGrid {
+        &self.grid
+    }
+
+    pub fn style(&self) -> MeshStyle {
+        self.style
+    }
+
+    pub fn simplify(&self) -> Option<SimplifySettings> {
+        self.simplify
+    }
+
+    /// Change how each chunk is simplified, marking every chunk as needing a rebuild
+    pub fn set_simplify(&mut self, simplify: Option<SimplifySettings>) {
+        self.simplify = simplify;
+        self.dirty.fill(true);
+    }
+
+    /// The mesh of each chunk, with z changing fastest, then y, then x. Chunks without anything
+    /// in them have empty meshes.
+    pub fn meshes(&self) -> &[VoxelMesh] {
+        &self.meshes
+    }
+
+    /// Index of the chunk at a chunk position
+    pub fn index(&self, x: usize, y: usize, z: usize) -> usize {
+        (x * self.chunks[1] + y) * self.chunks[2] + z
+    }
+
+    fn region(&self, i: usize) -> Region {
+        let pos = [
+            i / (self.chunks[1] * self.chunks[2]),
+            i / self.chunks[2] % self.chunks[1],
+            i % self.chunks[2],
+        ];
+        chunk_region(self.grid.size, pos)
+    }
+
+    /// Change a voxel, marking every chunk whose mesh that could change as needing a rebuild.
+    ///
+    /// Any Hermite data on the edges around the voxel no longer applies, so those edges go back
+    /// to being crossed halfway. Voxels outside of the grid are ignored.
+    pub fn set(&mut self, x: usize, y: usize, z: usize, voxel: Option<MaterialId>) {
+        if [x, y, z].into_iter().zip(self.grid.size).any(|(p, s)| p >= s) {
+            return;
+        }
+        self.grid.set(x, y, z, voxel);
+
+        let pos = [x as i32, y as i32, z as i32];
+        for axis in 0..3 {
+            let mut below = pos;
+            below[axis] -= 1;
+            self.hermite.remove(pos, axis);
+            self.hermite.remove(below, axis);
+        }
+
+        let reach = self.style.reach();
+        let [min_x, min_y, min_z] = [x, y, z].map(|p| p.saturating_sub(reach) / CHUNK_SIZE);
+        let [max_x, max_y, max_z] = [0, 1, 2].map(|a| ((pos[a] as usize + reach) / CHUNK_SIZE).min(self.chunks[a] - 1));
+        for cx in min_x..=max_x {
+            for cy in min_y..=max_y {
+                for cz in min_z..=max_z {
+                    let i = self.index(cx, cy, cz);
+                    self.dirty[i] = true;
+                }
+            }
+        }
+    }
+
+    /// Mesh the chunks that changed since they were last meshed, giving the index of each chunk
+    /// that was
+    pub fn rebuild(&mut self) -> Vec<usize> {
+        let rebuilt: Vec<usize> = (0..self.meshes.len()).filter(|i| self.dirty[*i]).collect();
+        for i in &rebuilt {
+            let mesh = mesh_region(&self.grid, &self.hermite, self.style, self.region(*i));
+            self.meshes[*i] = match &self.simplify {
+                Some(settings) => simplify(&mesh, settings),
+                None => mesh,
+            };
+            self.dirty[*i] = false;
+        }
+        rebuilt
+    }
+
+    /// All of the chunks joined into one mesh
+    pub fn merged(&self) -> VoxelMesh {
+        let mut merged = VoxelMesh::default();
+        for mesh in &self.meshes {
+            let base = merged.verts.len();
+            merged.verts.extend_from_slice(&mesh.verts);
+            merged.inds.extend(mesh.inds.iter().map(|i| i + base));
+            merged.materials.extend_from_slice(&mesh.materials);
+        }
+        merged
+    }
+}
+
+/// Mesh each chunk of a grid, with z changing fastest, then y, then x
+pub fn mesh_chunks(grid: &VoxelGrid, style: MeshStyle) -> Vec<VoxelMesh> {
+    let chunks = chunk_count(grid.size);
+    let hermite = HermiteData::new();
+
+    let mut meshes = Vec::with_capacity(chunks[0] * chunks[1] * chunks[2]);
+    for x in 0..chunks[0] {
+        for y in 0..chunks[1] {
+            for z in 0..chunks[2] {
+                meshes.push(mesh_region(grid, &hermite, style, chunk_region(grid.size, [x, y, z])));
+            }
+        }
+    }
+    meshes
+}
+
+/// Number of chunks needed along each axis to cover a grid
+fn chunk_count(size: [usize; 3]) -> [usize; 3] {
+    size.map(|s| s.div_ceil(CHUNK_SIZE))
+}
+
+/// The voxels in the chunk at a chunk position, chunks at the top of the grid may be cut short
+fn chunk_region(size: [usize; 3], chunk: [usize; 3]) -> Region {
+    let min = chunk.map(|c| c * CHUNK_SIZE);
+    let max = [0, 1, 2].map(|a| (min[a] + CHUNK_SIZE).min(size[a]));
+    Region { min, max }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+
+    use super::*;
+
+    /// A grid over several chunks with a scattering of voxels
+    fn grid() -> VoxelGrid {
+        let mut grid = VoxelGrid::new(Vec3::ZERO, 1.0, [CHUNK_SIZE + 4, 5, CHUNK_SIZE + 2]);
+        let [sx, sy, sz] = grid.size;
+        for x in 0..sx {
+            for y in 0..sy {
+                for z in 0..sz {
+                    if (x / 3 + y / 2 + z / 3) % 3 != 0 {
+                        grid.set(x, y, z, Some((x % 3) as MaterialId));
+                    }
+                }
+            }
+        }
+        grid
+    }
+
+    #[test]
+    fn rebuilding_matches_meshing_again() {
+        let styles = [MeshStyle::Blocks, MeshStyle::Greedy, MeshStyle::MarchingCubes { smoothing: 1 }, MeshStyle::SurfaceNets, MeshStyle::DualContouring];
+        for style in styles {
+            // On the edge of a chunk, so its neighbours change too
+            for (pos, voxel) in [([CHUNK_SIZE - 1, 0, CHUNK_SIZE], None), ([3, 1, 4], Some(7))] {
+                assert_ne!(grid().get(pos[0], pos[1], pos[2]), voxel);
+                let mut chunked = ChunkedMesh::new(grid(), style);
+                chunked.set(pos[0], pos[1], pos[2], voxel);
+                assert!(!chunked.rebuild().is_empty());
+
+                let mut edited = grid();
+                edited.set(pos[0], pos[1], pos[2], voxel);
+                let fresh = ChunkedMesh::new(edited, style);
+                assert_eq!(chunked.merged().fingerprint(), fresh.merged().fingerprint(), "{:?} at {:?}", style, pos);
+            }
+        }
+    }
+
+    #[test]
+    fn voxels_outside_are_ignored() {
+        let mut chunked = ChunkedMesh::new(grid(), MeshStyle::Blocks);
+        let [sx, sy, sz] = chunked.grid().size;
+        chunked.set(sx, 0, 0, Some(1));
+        chunked.set(0, sy + 5, 0, Some(1));
+        chunked.set(0, 0, sz, None);
+        assert!(chunked.rebuild().is_empty());
+        assert_eq!(chunked.grid().voxels(), grid().voxels());
+    }
+}
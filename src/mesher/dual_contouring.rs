@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+
+use glam::{Mat3, Vec3};
+
+use crate::{grid::{EdgeIntersection, HermiteData, MaterialId, VoxelGrid}, model::vertex::Vertex};
+
+use super::{marching_cubes::{corner_offset, cube_edges}, Region, VoxelMesh};
+
+/// How far the dual contouring vertex is pulled towards the average of the crossings, which
+/// keeps it steady where the surface is flat and the planes don't pin down a single point
+const MASS_POINT_BIAS: f32 = 0.05;
+
+/// Create a smooth mesh with a vertex in each cell of voxel centres the surface passes through,
+/// placed at the average of where the surface crosses the cell's edges.
+pub fn surface_nets(grid: &VoxelGrid, hermite: &HermiteData) -> VoxelMesh {
+    surface_nets_cells(grid, hermite, Region::whole(grid).cells(grid.size))
+}
+
+pub(super) fn surface_nets_cells(grid: &VoxelGrid, hermite: &HermiteData, cells: ([i32; 3], [i32; 3])) -> VoxelMesh {
+    dual_mesh(grid, hermite, cells, |crossings, _, _| mass_point(crossings))
+}
+
+/// Create a mesh with a vertex in each cell of voxel centres the surface passes through, placed
+/// where it best fits the planes of the surface at each of the crossings on the cell's edges.
+///
+/// Unlike [`surface_nets`] or marching cubes this puts vertices right on the corners and edges
+/// where flat parts of the surface meet, so hard edges stay hard. Vertices are kept inside of
+/// their cell so the surface can't fold over itself.
+pub fn dual_contour(grid: &VoxelGrid, hermite: &HermiteData) -> VoxelMesh {
+    dual_contour_cells(grid, hermite, Region::whole(grid).cells(grid.size))
+}
+
+pub(super) fn dual_contour_cells(grid: &VoxelGrid, hermite: &HermiteData, cells: ([i32; 3], [i32; 3])) -> VoxelMesh {
+    dual_mesh(grid, hermite, cells, |crossings, min, max| {
+        let mass = mass_point(crossings);
+
+        // Minimise the squared distance to every plane, measured from the mass point
+        let mut ata = Mat3::ZERO;
+        let mut atb = Vec3::ZERO;
+        for c in crossings {
+            let n = c.normal;
+            ata += Mat3::from_cols(n * n.x, n * n.y, n * n.z);
+            atb += n * n.dot(c.pos - mass);
+        }
+        ata += Mat3::from_diagonal(Vec3::splat(MASS_POINT_BIAS));
+
+        (mass + ata.inverse() * atb).clamp(min, max)
+    })
+}
+
+fn mass_point(crossings: &[EdgeIntersection]) -> Vec3 {
+    crossings.iter().fold(Vec3::ZERO, |acc, c| acc + c.pos) / crossings.len() as f32
+}
+
+/// Build a mesh with one vertex per cell the surface passes through, and a quad joining the four
+/// cells around each edge it crosses.
+///
+/// Cells are the cubes between 8 neighbouring voxel centres, named by their lowest voxel and
+/// going one past the grid on each side so the surface is always closed. Only the edges starting
+/// in the cells from `cells.0` up to but not including `cells.1` get quads. `place` is given the
+/// crossings on a cell's edges along with the cell's bounds, and decides where its vertex goes.
+fn dual_mesh(
+    grid: &VoxelGrid,
+    hermite: &HermiteData,
+    cells: ([i32; 3], [i32; 3]),
+    place: impl Fn(&[EdgeIntersection], Vec3, Vec3) -> Vec3,
+) -> VoxelMesh {
+    let edges = cube_edges();
+    let (min, max) = cells;
+    let centre = |p: [i32; 3]| grid.min + (Vec3::new(p[0] as f32, p[1] as f32, p[2] as f32) + 0.5) * grid.scale;
+
+    // Crossing on the edge from a voxel along an axis, if the surface crosses it at all
+    let crossing = |lower: [i32; 3], axis: usize| -> Option<EdgeIntersection> {
+        let mut upper = lower;
+        upper[axis] += 1;
+        let filled = grid.is_filled(lower[0], lower[1], lower[2]);
+        if filled == grid.is_filled(upper[0], upper[1], upper[2]) {
+            return None;
+        }
+        Some(match hermite.get(lower, axis) {
+            Some(c) => *c,
+            None => {
+                let mut normal = Vec3::ZERO;
+                normal[axis] = if filled { 1.0 } else { -1.0 };
+                EdgeIntersection {
+                    pos: (centre(lower) + centre(upper)) * 0.5,
+                    normal,
+                }
+            }
+        })
+    };
+
+    let mut verts: Vec<Vertex> = Vec::new();
+    let mut materials: Vec<MaterialId> = Vec::new();
+    let mut cell_verts: HashMap<[i32; 3], usize> = HashMap::new();
+
+    let mut cell_vertex = |cell: [i32; 3]| -> usize {
+        *cell_verts.entry(cell).or_insert_with(|| {
+            let corners: Vec<[i32; 3]> = (0..8)
+                .map(|c| {
+                    let o = corner_offset(c);
+                    [cell[0] + o[0], cell[1] + o[1], cell[2] + o[2]]
+                })
+                .collect();
+            let crossings: Vec<EdgeIntersection> = edges
+                .iter()
+                .filter_map(|(a, b)| crossing(corners[*a], (a ^ b).trailing_zeros() as usize))
+                .collect();
+
+            let cell_min = centre(cell);
+            let pos = place(&crossings, cell_min, cell_min + Vec3::splat(grid.scale));
+            let norm = crossings
+                .iter()
+                .fold(Vec3::ZERO, |acc, c| acc + c.normal)
+                .try_normalize()
+                .unwrap_or(Vec3::Y);
+            let material = corners.iter().find_map(|c| grid.material(c[0], c[1], c[2])).unwrap_or(0);
+
+            verts.push(Vertex::new(pos.into(), norm.into()));
+            materials.push(material);
+            verts.len() - 1
+        })
+    };
+
+    let mut quads: Vec<[usize; 4]> = Vec::new();
+    for x in min[0]..max[0] {
+        for y in min[1]..max[1] {
+            for z in min[2]..max[2] {
+                let lower = [x, y, z];
+                for axis in 0..3 {
+                    if crossing(lower, axis).is_none() {
+                        continue;
+                    }
+
+                    // The four cells around the edge, anti-clockwise looking back along the axis
+                    let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+                    let cell = |i: i32, j: i32| {
+                        let mut c = lower;
+                        c[u] -= i;
+                        c[v] -= j;
+                        c
+                    };
+                    let mut quad = [cell(0, 0), cell(1, 0), cell(1, 1), cell(0, 1)].map(&mut cell_vertex);
+                    // Face towards the empty side
+                    if !grid.is_filled(x, y, z) {
+                        quad.reverse();
+                    }
+                    quads.push(quad);
+                }
+            }
+        }
+    }
+
+    // Split each quad along its shorter diagonal, which follows creases better
+    let mut inds: Vec<usize> = Vec::with_capacity(quads.len() * 6);
+    for [a, b, c, d] in quads {
+        let pos = |i: usize| verts[i].pos_vec();
+        if pos(a).distance_squared(pos(c)) <= pos(b).distance_squared(pos(d)) {
+            inds.extend([a, b, c, a, c, d]);
+        } else {
+            inds.extend([a, b, d, b, c, d]);
+        }
+    }
+
+    VoxelMesh { verts, inds, materials }
+}
@@ -0,0 +1,275 @@
+use std::collections::HashSet;
+
+use glam::Vec3;
+
+use crate::grid::{MaterialId, VoxelGrid};
+
+use super::{ao_brightness, builder::MeshBuilder, vertex_ao, Region, VoxelMesh};
+
+/// A merged face, with corners in grid coordinates going anti-clockwise when looked at from the
+/// front
+struct Quad {
+    corners: [[i32; 3]; 4],
+    normal: [f32; 3],
+    material: MaterialId,
+    /// Ambient occlusion level at each corner
+    ao: [u8; 4],
+    /// Axis the quad faces along
+    axis: usize,
+    /// Smallest and largest of the empty voxels the quad looks into
+    front: ([i32; 3], [i32; 3]),
+}
+
+/// Create a mesh of the voxels where neighbouring faces facing the same way with the same
+/// material are merged into as few rectangles as possible.
+///
+/// Merging faces of different sizes leaves corners of one quad lying along the edge of another
+/// (T-junctions), which open up cracks once the mesh is transformed or exported. Any quad with
+/// another's corner on its edges is split into a fan around its centre that includes those
+/// corners, so the mesh stays watertight.
+///
+/// Faces are only merged when their ambient occlusion matches too, so the shading comes out the
+/// same as [`block_mesh`](super::block_mesh).
+pub fn greedy_mesh(grid: &VoxelGrid) -> VoxelMesh {
+    greedy_mesh_region(grid, Region::whole(grid))
+}
+
+/// Greedy mesh only the voxels in `region`.
+///
+/// Faces are never merged across the sides of the region, but the quads of the region next door
+/// can still end on the edges of this one's. Quads touching a side shared with another region
+/// get a vertex at every grid point along it, so the two meshes meet without cracks.
+pub(super) fn greedy_mesh_region(grid: &VoxelGrid, region: Region) -> VoxelMesh {
+    let quads = greedy_quads(grid, region);
+    let corners: HashSet<[i32; 3]> = quads.iter().flat_map(|q| q.corners).collect();
+
+    let mut builder = MeshBuilder::new(grid);
+    for quad in &quads {
+        let mut points: Vec<[i32; 3]> = Vec::with_capacity(4);
+        for e in 0..4 {
+            let (a, b) = (quad.corners[e], quad.corners[(e + 1) % 4]);
+            points.push(a);
+            points.extend(points_between(a, b).filter(|p| corners.contains(p) || region.on_seam(*p, grid.size)));
+        }
+        let outline: Vec<Vec3> = points
+            .iter()
+            .map(|c| Vec3::new(c[0] as f32, c[1] as f32, c[2] as f32))
+            .collect();
+
+        if outline.len() == 4 {
+            let ao = quad.ao.map(ao_brightness);
+            builder.quad([outline[0], outline[1], outline[2], outline[3]], quad.normal, quad.material, ao);
+        } else {
+            let ao: Vec<f32> = points.iter().map(|p| ao_brightness(quad.point_ao(grid, *p))).collect();
+            let centre = quad.corners.iter().fold(Vec3::ZERO, |acc, c| {
+                acc + Vec3::new(c[0] as f32, c[1] as f32, c[2] as f32)
+            }) / 4.0;
+            let centre_ao = quad.ao.map(ao_brightness).iter().sum::<f32>() / 4.0;
+            let centre = builder.vertex(centre, quad.normal, quad.material, centre_ao);
+            let ring: Vec<usize> = outline
+                .iter()
+                .zip(&ao)
+                .map(|(p, ao)| builder.vertex(*p, quad.normal, quad.material, *ao))
+                .collect();
+            for i in 0..ring.len() {
+                builder.triangle(centre, ring[i], ring[(i + 1) % ring.len()]);
+            }
+        }
+    }
+
+    builder.finish()
+}
+
+impl Quad {
+    /// Ambient occlusion level at a grid point on the edge of the quad
+    fn point_ao(&self, grid: &VoxelGrid, point: [i32; 3]) -> u8 {
+        // Any of the quad's voxels touching the point will do, the sides they see are the same
+        let (min, max) = self.front;
+        let mut front = min;
+        for a in 0..3 {
+            if a != self.axis {
+                front[a] = point[a].clamp(min[a], max[a]);
+            }
+        }
+        vertex_ao(grid, point, self.axis, front)
+    }
+}
+
+/// Grid points strictly between two corners along an axis aligned edge
+fn points_between(a: [i32; 3], b: [i32; 3]) -> impl Iterator<Item = [i32; 3]> {
+    let step = [(b[0] - a[0]).signum(), (b[1] - a[1]).signum(), (b[2] - a[2]).signum()];
+    let len = (b[0] - a[0]).abs() + (b[1] - a[1]).abs() + (b[2] - a[2]).abs();
+    (1..len).map(move |t| [a[0] + step[0] * t, a[1] + step[1] * t, a[2] + step[2] * t])
+}
+
+/// Sweep through each layer of the grid in each direction, merging the faces that are showing
+fn greedy_quads(grid: &VoxelGrid, region: Region) -> Vec<Quad> {
+    let (min, max) = (region.min.map(|m| m as i32), region.max.map(|m| m as i32));
+    let size = [0, 1, 2].map(|a| max[a] - min[a]);
+    let mut quads = Vec::new();
+
+    for d in 0..3 {
+        let (u, v) = ((d + 1) % 3, (d + 2) % 3);
+        let idx = |i: i32, j: i32| ((j - min[v]) * size[u] + i - min[u]) as usize;
+
+        for dir in [1, -1] {
+            let mut mask: Vec<Option<(MaterialId, [u8; 4])>> = vec![None; (size[u] * size[v]) as usize];
+
+            for k in min[d]..max[d] {
+                // Faces in this layer that aren't covered by a neighbour
+                for j in min[v]..max[v] {
+                    for i in min[u]..max[u] {
+                        let mut pos = [0; 3];
+                        pos[d] = k;
+                        pos[u] = i;
+                        pos[v] = j;
+                        let mut next = pos;
+                        next[d] += dir;
+
+                        mask[idx(i, j)] = match grid.material(pos[0], pos[1], pos[2]) {
+                            Some(m) if !grid.is_filled(next[0], next[1], next[2]) => {
+                                let plane = if dir > 0 { k + 1 } else { k };
+                                let ao = [(0, 0), (1, 0), (1, 1), (0, 1)].map(|(a, b)| {
+                                    let mut c = [0; 3];
+                                    c[d] = plane;
+                                    c[u] = i + a;
+                                    c[v] = j + b;
+                                    vertex_ao(grid, c, d, next)
+                                });
+                                Some((m, ao))
+                            }
+                            _ => None,
+                        };
+                    }
+                }
+
+                // Grow each face as far as it can go along u, then along v
+                for j in min[v]..max[v] {
+                    let mut i = min[u];
+                    while i < max[u] {
+                        let face = match mask[idx(i, j)] {
+                            Some(face) => face,
+                            None => {
+                                i += 1;
+                                continue;
+                            }
+                        };
+
+                        let mut w = 1;
+                        while i + w < max[u] && mask[idx(i + w, j)] == Some(face) {
+                            w += 1;
+                        }
+                        let mut h = 1;
+                        while j + h < max[v] && (i..i + w).all(|x| mask[idx(x, j + h)] == Some(face)) {
+                            h += 1;
+                        }
+                        for y in j..j + h {
+                            for x in i..i + w {
+                                mask[idx(x, y)] = None;
+                            }
+                        }
+
+                        let plane = if dir > 0 { k + 1 } else { k };
+                        let corner = |a: i32, b: i32| {
+                            let mut c = [0; 3];
+                            c[d] = plane;
+                            c[u] = a;
+                            c[v] = b;
+                            c
+                        };
+                        // Every merged face has the same occlusion, so the first one's corners are
+                        // the quad's corners
+                        let (material, mut ao) = face;
+                        // u cross v is d, so this goes anti-clockwise looking back along +d
+                        let mut corners = [corner(i, j), corner(i + w, j), corner(i + w, j + h), corner(i, j + h)];
+                        if dir < 0 {
+                            corners.reverse();
+                            ao.reverse();
+                        }
+                        let mut normal = [0.0; 3];
+                        normal[d] = dir as f32;
+
+                        let layer = k + dir;
+                        let mut front_min = [0; 3];
+                        let mut front_max = [0; 3];
+                        front_min[d] = layer;
+                        front_max[d] = layer;
+                        front_min[u] = i;
+                        front_max[u] = i + w - 1;
+                        front_min[v] = j;
+                        front_max[v] = j + h - 1;
+
+                        quads.push(Quad {
+                            corners,
+                            normal,
+                            material,
+                            ao,
+                            axis: d,
+                            front: (front_min, front_max),
+                        });
+                        i += w;
+                    }
+                }
+            }
+        }
+    }
+
+    quads
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    /// An L of two materials with a one voxel step beside it, so quads of different sizes meet
+    fn l_shape() -> VoxelGrid {
+        let mut grid = VoxelGrid::new(Vec3::ZERO, 1.0, [6, 4, 5]);
+        for x in 0..5 {
+            for z in 0..4 {
+                grid.set(x, 0, z, Some(if x < 2 { 0 } else { 1 }));
+            }
+        }
+        for y in 1..4 {
+            for z in 0..4 {
+                grid.set(0, y, z, Some(0));
+            }
+        }
+        grid.set(3, 1, 2, Some(1));
+        grid
+    }
+
+    #[test]
+    fn every_edge_has_two_opposite_triangles() {
+        let mesh = greedy_mesh(&l_shape());
+        let key = |i: usize| mesh.verts[i].pos.map(f32::to_bits);
+        let mut edges: HashMap<([u32; 3], [u32; 3]), usize> = HashMap::new();
+        for t in mesh.inds.chunks_exact(3) {
+            for i in 0..3 {
+                *edges.entry((key(t[i]), key(t[(i + 1) % 3]))).or_default() += 1;
+            }
+        }
+        for ((a, b), count) in &edges {
+            assert_eq!(*count, 1, "edge {:?} {:?} is used more than once the same way", a, b);
+            assert_eq!(edges.get(&(*b, *a)), Some(&1), "edge {:?} {:?} has no triangle going back", a, b);
+        }
+    }
+
+    #[test]
+    fn no_vertex_lies_inside_an_edge() {
+        let mesh = greedy_mesh(&l_shape());
+        let points: Vec<Vec3> = mesh.verts.iter().map(|v| Vec3::from(v.pos)).collect();
+        for t in mesh.inds.chunks_exact(3) {
+            for i in 0..3 {
+                let (a, b) = (points[t[i]], points[t[(i + 1) % 3]]);
+                for p in &points {
+                    let (ap, ab) = (*p - a, b - a);
+                    let along = ap.dot(ab) / ab.length_squared();
+                    let on_line = ap.cross(ab).length_squared() < 1e-8;
+                    assert!(!(on_line && along > 1e-4 && along < 1.0 - 1e-4), "{:?} lies inside {:?} {:?}", p, a, b);
+                }
+            }
+        }
+    }
+}
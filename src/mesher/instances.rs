@@ -0,0 +1,73 @@
+use glam::{Mat4, Vec3};
+
+use crate::grid::{MaterialId, VoxelGrid};
+
+use super::{block_mesh, VoxelMesh};
+
+/// One voxel, drawn as a copy of the shared cube moved to its place
+#[derive(Debug, Clone, Copy)]
+pub struct VoxelInstance {
+    /// World position of the centre of the voxel
+    pub offset: [f32; 3],
+    pub material: MaterialId,
+    /// Position of the voxel in the grid
+    pub voxel: [usize; 3],
+}
+
+#[cfg(feature = "gui")]
+glium::implement_vertex!(VoxelInstance, offset);
+
+impl VoxelInstance {
+    /// The transform placing the shared cube at this voxel
+    pub fn transform(&self) -> Mat4 {
+        Mat4::from_translation(Vec3::from(self.offset))
+    }
+}
+
+/// The voxels of a grid as one cube mesh and a list of where to put copies of it, for drawing
+/// with instancing rather than baking every cube into one mesh
+pub struct VoxelInstances {
+    /// A cube the size of one voxel, centred on the origin
+    pub cube: VoxelMesh,
+    pub instances: Vec<VoxelInstance>,
+}
+
+/// An instance for each voxel of the grid, in x, y then z order.
+///
+/// Voxels with all six sides covered by neighbours can't be seen, and are left out unless
+/// `hidden` is set. Keep them for things like destructible objects, where knocking out a voxel
+/// uncovers the ones behind it.
+pub fn voxel_instances(grid: &VoxelGrid, hidden: bool) -> VoxelInstances {
+    let mut single = VoxelGrid::new(Vec3::splat(-grid.scale / 2.0), grid.scale, [1, 1, 1]);
+    single.set(0, 0, 0, Some(0));
+    let cube = block_mesh(&single);
+
+    let mut instances = Vec::new();
+    for x in 0..grid.size[0] {
+        for y in 0..grid.size[1] {
+            for z in 0..grid.size[2] {
+                let material = match grid.get(x, y, z) {
+                    Some(material) => material,
+                    None => continue,
+                };
+
+                let (ix, iy, iz) = (x as i32, y as i32, z as i32);
+                let covered = [(1, 0, 0), (-1, 0, 0), (0, 1, 0), (0, -1, 0), (0, 0, 1), (0, 0, -1)]
+                    .iter()
+                    .all(|(dx, dy, dz)| grid.is_filled(ix + dx, iy + dy, iz + dz));
+                if covered && !hidden {
+                    continue;
+                }
+
+                let centre = grid.voxel_min(x, y, z) + Vec3::splat(grid.scale / 2.0);
+                instances.push(VoxelInstance {
+                    offset: centre.into(),
+                    material,
+                    voxel: [x, y, z],
+                });
+            }
+        }
+    }
+
+    VoxelInstances { cube, instances }
+}
@@ -0,0 +1,382 @@
+use std::{collections::HashMap, sync::OnceLock};
+
+use glam::Vec3;
+
+use crate::{grid::{MaterialId, VoxelGrid}, model::vertex::Vertex};
+
+use super::{Region, VoxelMesh};
+
+/// Density values sampled at the centre of each voxel of a grid.
+///
+/// Anything outside of the field has a density of 0, so surfaces extracted from it are always
+/// closed. Each sample can also carry the material that surface passing near it should get.
+#[derive(Debug, Clone)]
+pub struct DensityField {
+    /// World position of the minimum corner of the voxel at sample (0, 0, 0)
+    pub min: Vec3,
+    /// Distance between samples
+    pub scale: f32,
+    /// Number of samples along the x, y and z axes
+    pub size: [usize; 3],
+
+    /// Position of the first sample, for fields covering only part of a grid
+    offset: [i32; 3],
+    values: Vec<f32>,
+    materials: Vec<Option<MaterialId>>,
+}
+
+impl DensityField {
+    /// Create a field that is empty everywhere
+    pub fn new(min: Vec3, scale: f32, size: [usize; 3]) -> DensityField {
+        let len = size[0] * size[1] * size[2];
+        DensityField {
+            min,
+            scale,
+            size,
+            offset: [0; 3],
+            values: vec![0.0; len],
+            materials: vec![None; len],
+        }
+    }
+
+    /// A density of 1 inside of filled voxels and 0 everywhere else
+    pub fn from_grid(grid: &VoxelGrid) -> DensityField {
+        DensityField {
+            min: grid.min,
+            scale: grid.scale,
+            size: grid.size,
+            offset: [0; 3],
+            values: grid.voxels().iter().map(|v| if v.is_some() { 1.0 } else { 0.0 }).collect(),
+            materials: grid.voxels().to_vec(),
+        }
+    }
+
+    /// Like [`DensityField::from_grid`], but only covering a region of the grid and `pad` voxels
+    /// around it
+    pub(super) fn from_grid_region(grid: &VoxelGrid, region: Region, pad: usize) -> DensityField {
+        let pad = pad as i32;
+        let offset = region.min.map(|m| m as i32 - pad);
+        let size = [0, 1, 2].map(|a| region.max[a] - region.min[a] + 2 * pad as usize);
+
+        let mut field = DensityField::new(grid.min, grid.scale, size);
+        field.offset = offset;
+        for x in 0..size[0] as i32 {
+            for y in 0..size[1] as i32 {
+                for z in 0..size[2] as i32 {
+                    let (gx, gy, gz) = (x + offset[0], y + offset[1], z + offset[2]);
+                    if let Some(material) = grid.material(gx, gy, gz) {
+                        let i = field.index(gx, gy, gz).unwrap();
+                        field.values[i] = 1.0;
+                        field.materials[i] = Some(material);
+                    }
+                }
+            }
+        }
+        field
+    }
+
+    /// Where a sample is kept, if it's in the field
+    fn index(&self, x: i32, y: i32, z: i32) -> Option<usize> {
+        let (x, y, z) = (x - self.offset[0], y - self.offset[1], z - self.offset[2]);
+        if x < 0 || y < 0 || z < 0 {
+            return None;
+        }
+        let (x, y, z) = (x as usize, y as usize, z as usize);
+        if x >= self.size[0] || y >= self.size[1] || z >= self.size[2] {
+            return None;
+        }
+        Some((x * self.size[1] + y) * self.size[2] + z)
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, z: usize, density: f32, material: Option<MaterialId>) {
+        let i = self
+            .index(x as i32, y as i32, z as i32)
+            .expect("Sample outside of the density field");
+        self.values[i] = density;
+        self.materials[i] = material;
+    }
+
+    /// Density at a sample, anything outside of the field is 0
+    pub fn density(&self, x: i32, y: i32, z: i32) -> f32 {
+        self.index(x, y, z).map(|i| self.values[i]).unwrap_or(0.0)
+    }
+
+    /// Material at a sample, anything outside of the field has none
+    pub fn material(&self, x: i32, y: i32, z: i32) -> Option<MaterialId> {
+        self.index(x, y, z).and_then(|i| self.materials[i])
+    }
+
+    /// World position of a sample
+    pub fn sample_pos(&self, x: i32, y: i32, z: i32) -> Vec3 {
+        self.min + (Vec3::new(x as f32, y as f32, z as f32) + 0.5) * self.scale
+    }
+
+    /// Gradient of the density at a sample, by central differences
+    fn gradient(&self, x: i32, y: i32, z: i32) -> Vec3 {
+        Vec3::new(
+            self.density(x + 1, y, z) - self.density(x - 1, y, z),
+            self.density(x, y + 1, z) - self.density(x, y - 1, z),
+            self.density(x, y, z + 1) - self.density(x, y, z - 1),
+        ) * 0.5
+    }
+
+    /// Blend each sample halfway towards the average of its six neighbours.
+    ///
+    /// This rounds off the corners of the blocky density from [`DensityField::from_grid`] while
+    /// keeping single voxel thick walls and lines above a density of 0.5. Samples that had no
+    /// material take the one of their densest neighbour that does.
+    pub fn smoothed(&self) -> DensityField {
+        const NEIGHBOURS: [[i32; 3]; 6] = [[1, 0, 0], [-1, 0, 0], [0, 1, 0], [0, -1, 0], [0, 0, 1], [0, 0, -1]];
+
+        let mut out = self.clone();
+        let [res_x, res_y, res_z] = self.size.map(|s| s as i32);
+        let [off_x, off_y, off_z] = self.offset;
+        for x in off_x..off_x + res_x {
+            for y in off_y..off_y + res_y {
+                for z in off_z..off_z + res_z {
+                    let sum: f32 = NEIGHBOURS.iter().map(|n| self.density(x + n[0], y + n[1], z + n[2])).sum();
+                    let density = self.density(x, y, z) * 0.5 + sum / 12.0;
+
+                    let material = self.material(x, y, z).or_else(|| {
+                        NEIGHBOURS
+                            .iter()
+                            .map(|n| [x + n[0], y + n[1], z + n[2]])
+                            .filter(|[x, y, z]| self.material(*x, *y, *z).is_some())
+                            .fold(None, |best: Option<[i32; 3]>, n| match best {
+                                Some(b) if self.density(b[0], b[1], b[2]) >= self.density(n[0], n[1], n[2]) => Some(b),
+                                _ => Some(n),
+                            })
+                            .and_then(|[x, y, z]| self.material(x, y, z))
+                    });
+
+                    let i = out.index(x, y, z).unwrap();
+                    out.values[i] = density;
+                    out.materials[i] = material;
+                }
+            }
+        }
+        out
+    }
+}
+
+/// Corner offset of each of the 8 corners of a cube, corner `i` is offset by bit 0 in x, bit 1
+/// in y and bit 2 in z
+pub(super) fn corner_offset(i: usize) -> [i32; 3] {
+    [(i & 1) as i32, ((i >> 1) & 1) as i32, ((i >> 2) & 1) as i32]
+}
+
+/// The 12 edges of a cube, as the two corners they join
+pub(super) fn cube_edges() -> Vec<(usize, usize)> {
+    let mut edges = Vec::with_capacity(12);
+    for a in 0..8 {
+        for axis in 0..3 {
+            if a & (1 << axis) == 0 {
+                edges.push((a, a | (1 << axis)));
+            }
+        }
+    }
+    edges
+}
+
+/// Work out the surface loops through a cube for each of the 256 ways its corners can be inside
+/// or outside, as lists of the edges they cross.
+///
+/// Each face of the cube is walked anti-clockwise from outside. Where that walk goes from
+/// outside to inside it starts a piece of surface, which runs to the next crossing along the
+/// face. When all four corners of a face alternate, this keeps the inside corners apart, and as
+/// the cube on the other side of the face sees the same corners it makes the same choice, so the
+/// surfaces of neighbouring cubes always meet up.
+///
+/// The pieces from all six faces join up into loops going anti-clockwise when looked at from the
+/// outside of the surface.
+fn case_table() -> &'static [Vec<Vec<usize>>] {
+    static TABLE: OnceLock<Vec<Vec<Vec<usize>>>> = OnceLock::new();
+    TABLE.get_or_init(build_case_table)
+}
+
+/// Work out [`case_table`] from scratch
+fn build_case_table() -> Vec<Vec<Vec<usize>>> {
+    let edges = cube_edges();
+    let edge_between = |a: usize, b: usize| {
+        edges
+            .iter()
+            .position(|e| *e == (a.min(b), a.max(b)))
+            .expect("Corners don't share an edge")
+    };
+
+    // Corners of each face, anti-clockwise from outside
+    let mut faces = Vec::with_capacity(6);
+    for d in 0..3 {
+        let (u, v) = ((d + 1) % 3, (d + 2) % 3);
+        for side in 0..2 {
+            let corner = |a: usize, b: usize| (side << d) | (a << u) | (b << v);
+            let mut corners = [corner(0, 0), corner(1, 0), corner(1, 1), corner(0, 1)];
+            if side == 0 {
+                corners.reverse();
+            }
+            faces.push(corners);
+        }
+    }
+
+    (0..256)
+        .map(|case: usize| {
+            let inside = |c: usize| case & (1 << c) != 0;
+
+            let mut next: HashMap<usize, usize> = HashMap::new();
+            for face in &faces {
+                let crossings: Vec<(usize, bool)> = (0..4)
+                    .filter(|k| inside(face[*k]) != inside(face[(k + 1) % 4]))
+                    .map(|k| (edge_between(face[k], face[(k + 1) % 4]), inside(face[(k + 1) % 4])))
+                    .collect();
+                for (i, (edge, entering)) in crossings.iter().enumerate() {
+                    if *entering {
+                        next.insert(*edge, crossings[(i + 1) % crossings.len()].0);
+                    }
+                }
+            }
+
+            let mut loops = Vec::new();
+            let mut starts: Vec<usize> = next.keys().copied().collect();
+            starts.sort_unstable();
+            for start in starts {
+                if !next.contains_key(&start) {
+                    continue;
+                }
+                let mut edge_loop = vec![start];
+                let mut edge = next.remove(&start).unwrap();
+                while edge != start {
+                    edge_loop.push(edge);
+                    edge = next.remove(&edge).expect("Surface doesn't form a loop");
+                }
+                loops.push(edge_loop);
+            }
+            loops
+        })
+        .collect()
+}
+
+/// Extract the surface where the field crosses `iso` as a smooth mesh.
+///
+/// Vertices are shared between neighbouring cubes, with normals taken from the gradient of the
+/// field and the material from the denser end of the edge they lie on.
+pub fn marching_cubes(field: &DensityField, iso: f32) -> VoxelMesh {
+    // Cubes join sample centres, so go one past the field on each side to close the surface
+    let min = field.offset.map(|o| o - 1);
+    let max = [0, 1, 2].map(|a| field.offset[a] + field.size[a] as i32);
+    marching_cubes_cells(field, iso, (min, max))
+}
+
+/// Marching cubes over only the cubes from `cells.0` up to but not including `cells.1`, each
+/// named by its lowest sample
+pub(super) fn marching_cubes_cells(field: &DensityField, iso: f32, cells: ([i32; 3], [i32; 3])) -> VoxelMesh {
+    let table = case_table();
+    let edges = cube_edges();
+    let (min, max) = cells;
+
+    let mut verts: Vec<Vertex> = Vec::new();
+    let mut inds: Vec<usize> = Vec::new();
+    let mut materials: Vec<MaterialId> = Vec::new();
+    // Vertex on each crossed edge between samples, keyed by its lower sample and axis
+    let mut edge_verts: HashMap<([i32; 3], usize), usize> = HashMap::new();
+
+    for x in min[0]..max[0] {
+        for y in min[1]..max[1] {
+            for z in min[2]..max[2] {
+                let corners: Vec<[i32; 3]> = (0..8)
+                    .map(|c| {
+                        let o = corner_offset(c);
+                        [x + o[0], y + o[1], z + o[2]]
+                    })
+                    .collect();
+                let case = (0..8)
+                    .filter(|c| {
+                        let [x, y, z] = corners[*c];
+                        field.density(x, y, z) >= iso
+                    })
+                    .fold(0, |case, c| case | (1 << c));
+
+                for edge_loop in &table[case] {
+                    let ring: Vec<usize> = edge_loop
+                        .iter()
+                        .map(|e| {
+                            let (a, b) = edges[*e];
+                            let axis = (a ^ b).trailing_zeros() as usize;
+                            *edge_verts
+                                .entry((corners[a], axis))
+                                .or_insert_with(|| {
+                                    let (v, m) = edge_vertex(field, iso, corners[a], corners[b]);
+                                    verts.push(v);
+                                    materials.push(m);
+                                    verts.len() - 1
+                                })
+                        })
+                        .collect();
+
+                    for i in 1..ring.len() - 1 {
+                        inds.extend([ring[0], ring[i], ring[i + 1]]);
+                    }
+                }
+            }
+        }
+    }
+
+    VoxelMesh { verts, inds, materials }
+}
+
+/// The vertex where the field crosses `iso` between two neighbouring samples
+fn edge_vertex(field: &DensityField, iso: f32, a: [i32; 3], b: [i32; 3]) -> (Vertex, MaterialId) {
+    let (da, db) = (field.density(a[0], a[1], a[2]), field.density(b[0], b[1], b[2]));
+    let t = if da != db { ((iso - da) / (db - da)).clamp(0.0, 1.0) } else { 0.5 };
+
+    let (pa, pb) = (field.sample_pos(a[0], a[1], a[2]), field.sample_pos(b[0], b[1], b[2]));
+    let pos = pa.lerp(pb, t);
+
+    // Density goes up going inside, so the normal points down the gradient
+    let gradient = field.gradient(a[0], a[1], a[2]).lerp(field.gradient(b[0], b[1], b[2]), t);
+    let (inside, outside) = if da >= db { (a, b) } else { (b, a) };
+    let norm = match (-gradient).try_normalize() {
+        Some(norm) => norm,
+        None => Vec3::new(
+            (outside[0] - inside[0]) as f32,
+            (outside[1] - inside[1]) as f32,
+            (outside[2] - inside[2]) as f32,
+        ),
+    };
+
+    let material = field.material(inside[0], inside[1], inside[2]).unwrap_or(0);
+    (Vertex::new(pos.into(), norm.into()), material)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesher::open_edges;
+
+    #[test]
+    fn every_crossed_edge_is_used_once() {
+        let edges = cube_edges();
+        for (case, loops) in case_table().iter().enumerate() {
+            let inside = |c: usize| case & (1 << c) != 0;
+            let crossed: Vec<usize> = (0..12).filter(|e| inside(edges[*e].0) != inside(edges[*e].1)).collect();
+            let mut used: Vec<usize> = loops.iter().flatten().copied().collect();
+            used.sort_unstable();
+            assert_eq!(used, crossed, "case {}", case);
+            assert!(loops.iter().all(|l| l.len() >= 3), "case {}", case);
+        }
+    }
+
+    #[test]
+    fn solid_block_is_closed() {
+        let mut grid = VoxelGrid::new(Vec3::ZERO, 1.0, [3, 3, 3]);
+        for x in 0..3 {
+            for y in 0..3 {
+                for z in 0..3 {
+                    grid.set(x, y, z, Some(0));
+                }
+            }
+        }
+        let mesh = marching_cubes(&DensityField::from_grid(&grid), 0.5);
+        assert!(!mesh.inds.is_empty());
+        assert_eq!(open_edges(&mesh), 0);
+    }
+}
@@ -0,0 +1,549 @@
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap, HashSet},
+};
+
+use glam::{DMat3, DVec3, Vec3};
+
+use crate::{grid::MaterialId, model::vertex::Vertex};
+
+use super::VoxelMesh;
+
+/// Cosine of the largest angle between two faces that are shaded smoothly across
+const CREASE: f32 = 0.7;
+/// Cosine of the largest angle a triangle may turn through in one collapse
+const MAX_TURN: f64 = 0.5;
+/// How much more moving a point off a line between materials costs than moving it off the
+/// surface, which keeps the lines where they are
+const SEAM_WEIGHT: f64 = 10.0;
+
+/// How far a mesh is simplified, stopping at whichever limit is reached first
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SimplifySettings {
+    /// Fraction of the triangles to keep, 0 to go as far as `max_error` allows
+    pub ratio: f32,
+    /// How far the surface may move from where it was, in world units. Flat areas can always be
+    /// merged, so even 0 takes blocky meshes down to a few triangles per face.
+    pub max_error: f32,
+}
+
+impl Default for SimplifySettings {
+    fn default() -> SimplifySettings {
+        SimplifySettings {
+            ratio: 0.0,
+            max_error: 0.01,
+        }
+    }
+}
+
+/// Simplify a mesh by collapsing edges, cheapest first, for as long as the settings allow.
+///
+/// Each collapse is costed by how far it moves the surface away from the planes of the triangles
+/// that were merged into it, so flat areas go first and the silhouette, creases and corners are
+/// kept. Vertices in the same place are treated as one, whatever their normals are.
+///
+/// Edges on the open boundary of the mesh (like the sides of a chunk) never move, so meshes of
+/// regions next to each other still meet up after they are simplified. Lines between materials
+/// are only moved along themselves. Normals are worked out again from the simplified triangles,
+/// smooth between faces that meet at a shallow angle, and ambient occlusion is kept from the
+/// points that are left.
+pub fn simplify(mesh: &VoxelMesh, settings: &SimplifySettings) -> VoxelMesh {
+    let triangles = (mesh.inds.len() / 3) as f32 * settings.ratio.clamp(0.0, 1.0);
+    simplify_to(mesh, triangles.ceil() as usize, settings.max_error)
+}
+
+/// Simplify a mesh down to `triangles` triangles, or as far as it can go without moving the
+/// surface more than `max_error`, see [`simplify`]
+pub fn simplify_to(mesh: &VoxelMesh, triangles: usize, max_error: f32) -> VoxelMesh {
+    let mut decimator = Decimator::new(mesh);
+    decimator.run(triangles, (max_error as f64).powi(2));
+    decimator.finish(mesh)
+}
+
+/// Sum of squared distances to a set of planes, as `p·Ap + 2b·p + c`
+#[derive(Debug, Clone, Copy)]
+struct Quadric {
+    a: DMat3,
+    b: DVec3,
+    c: f64,
+}
+
+impl Quadric {
+    const ZERO: Quadric = Quadric {
+        a: DMat3::ZERO,
+        b: DVec3::ZERO,
+        c: 0.0,
+    };
+
+    /// Squared distance to the plane through `point` facing along the unit `normal`
+    fn plane(point: DVec3, normal: DVec3, weight: f64) -> Quadric {
+        let d = -normal.dot(point);
+        Quadric {
+            a: DMat3::from_cols(normal * normal.x, normal * normal.y, normal * normal.z) * weight,
+            b: normal * d * weight,
+            c: d * d * weight,
+        }
+    }
+
+    fn add(&self, other: &Quadric) -> Quadric {
+        Quadric {
+            a: self.a + other.a,
+            b: self.b + other.b,
+            c: self.c + other.c,
+        }
+    }
+
+    fn error(&self, p: DVec3) -> f64 {
+        (p.dot(self.a * p) + 2.0 * self.b.dot(p) + self.c).max(0.0)
+    }
+
+    /// The point with the least error, if there is just one
+    fn minimum(&self) -> Option<DVec3> {
+        if self.a.determinant().abs() < 1e-12 {
+            return None;
+        }
+        Some(-(self.a.inverse() * self.b))
+    }
+}
+
+/// How free a point is to move
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Kind {
+    Free,
+    /// On a line between materials
+    Seam,
+    /// On an open or non-manifold edge
+    Locked,
+}
+
+/// Merging `remove` into `keep`, moving `keep` to `pos`
+struct Collapse {
+    cost: f64,
+    keep: usize,
+    remove: usize,
+    pos: DVec3,
+    /// Versions of the two points when this was worked out
+    versions: (u32, u32),
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Collapse) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Collapse {}
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Collapse) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Collapse {
+    /// Cheapest first out of a max-heap, with ties broken by the points so the order is always
+    /// the same
+    fn cmp(&self, other: &Collapse) -> Ordering {
+        other
+            .cost
+            .total_cmp(&self.cost)
+            .then_with(|| (other.keep, other.remove).cmp(&(self.keep, self.remove)))
+    }
+}
+
+struct Decimator {
+    points: Vec<DVec3>,
+    quadrics: Vec<Quadric>,
+    kinds: Vec<Kind>,
+    /// Bumped whenever a point changes, so out of date collapses can be skipped
+    versions: Vec<u32>,
+    /// Vertices of the source mesh at each point
+    point_verts: Vec<Vec<usize>>,
+    point_tris: Vec<Vec<usize>>,
+
+    tris: Vec<[usize; 3]>,
+    tri_materials: Vec<MaterialId>,
+    alive: Vec<bool>,
+    alive_count: usize,
+
+    /// Edges between triangles of different materials, smallest point first
+    seams: HashSet<(usize, usize)>,
+    heap: BinaryHeap<Collapse>,
+}
+
+fn edge(a: usize, b: usize) -> (usize, usize) {
+    (a.min(b), a.max(b))
+}
+
+impl Decimator {
+    fn new(mesh: &VoxelMesh) -> Decimator {
+        // Join up vertices in the same place
+        let mut lookup: HashMap<[u32; 3], usize> = HashMap::new();
+        let mut points = Vec::new();
+        let mut point_verts: Vec<Vec<usize>> = Vec::new();
+        let point_of: Vec<usize> = mesh
+            .verts
+            .iter()
+            .enumerate()
+            .map(|(i, v)| {
+                let p = *lookup.entry(v.pos.map(f32::to_bits)).or_insert_with(|| {
+                    points.push(v.pos_vec().as_dvec3());
+                    point_verts.push(Vec::new());
+                    points.len() - 1
+                });
+                point_verts[p].push(i);
+                p
+            })
+            .collect();
+
+        let mut tris = Vec::new();
+        let mut tri_materials = Vec::new();
+        for t in mesh.inds.chunks_exact(3) {
+            let tri = [point_of[t[0]], point_of[t[1]], point_of[t[2]]];
+            if tri[0] == tri[1] || tri[1] == tri[2] || tri[2] == tri[0] {
+                continue;
+            }
+            tris.push(tri);
+            tri_materials.push(mesh.materials.get(t[0]).copied().unwrap_or(0));
+        }
+
+        let mut decimator = Decimator {
+            quadrics: vec![Quadric::ZERO; points.len()],
+            kinds: vec![Kind::Free; points.len()],
+            versions: vec![0; points.len()],
+            point_verts,
+            point_tris: vec![Vec::new(); points.len()],
+            points,
+
+            alive: vec![true; tris.len()],
+            alive_count: tris.len(),
+            tris,
+            tri_materials,
+
+            seams: HashSet::new(),
+            heap: BinaryHeap::new(),
+        };
+
+        let mut edge_tris: HashMap<(usize, usize), Vec<usize>> = HashMap::new();
+        for (i, tri) in decimator.tris.iter().enumerate() {
+            if let Some(normal) = decimator.normal(tri).try_normalize() {
+                let plane = Quadric::plane(decimator.points[tri[0]], normal, 1.0);
+                for p in tri {
+                    decimator.quadrics[*p] = decimator.quadrics[*p].add(&plane);
+                }
+            }
+            for e in 0..3 {
+                decimator.point_tris[tri[e]].push(i);
+                edge_tris.entry(edge(tri[e], tri[(e + 1) % 3])).or_default().push(i);
+            }
+        }
+
+        let mut edges: Vec<_> = edge_tris.into_iter().collect();
+        edges.sort_unstable_by_key(|(e, _)| *e);
+        for ((a, b), around) in &edges {
+            let (a, b) = (*a, *b);
+            if around.len() != 2 {
+                decimator.kinds[a] = Kind::Locked;
+                decimator.kinds[b] = Kind::Locked;
+            } else if decimator.tri_materials[around[0]] != decimator.tri_materials[around[1]] {
+                decimator.seams.insert((a, b));
+                for p in [a, b] {
+                    decimator.kinds[p] = decimator.kinds[p].max(Kind::Seam);
+                }
+
+                // Planes through the edge at right angles to each side, so moving off the line
+                // costs more than moving along it
+                let along = decimator.points[b] - decimator.points[a];
+                for t in around {
+                    let normal = decimator.normal(&decimator.tris[*t]);
+                    if let Some(side) = along.cross(normal).try_normalize() {
+                        let plane = Quadric::plane(decimator.points[a], side, SEAM_WEIGHT);
+                        decimator.quadrics[a] = decimator.quadrics[a].add(&plane);
+                        decimator.quadrics[b] = decimator.quadrics[b].add(&plane);
+                    }
+                }
+            }
+        }
+
+        for ((a, b), _) in edges {
+            decimator.push(a, b);
+        }
+        decimator
+    }
+
+    /// Normal of a triangle, as long as twice its area
+    fn normal(&self, tri: &[usize; 3]) -> DVec3 {
+        let [a, b, c] = tri.map(|p| self.points[p]);
+        (b - a).cross(c - a)
+    }
+
+    fn run(&mut self, triangles: usize, max_cost: f64) {
+        while self.alive_count > triangles {
+            let collapse = match self.heap.pop() {
+                Some(collapse) => collapse,
+                None => break,
+            };
+            if collapse.versions != (self.versions[collapse.keep], self.versions[collapse.remove]) {
+                continue;
+            }
+            if collapse.cost > max_cost {
+                break;
+            }
+            // Collapses that would break the mesh are tried again when the points around them
+            // change
+            if self.keeps_manifold(collapse.keep, collapse.remove) && !self.folds(collapse.keep, collapse.remove, collapse.pos) {
+                self.collapse(collapse.keep, collapse.remove, collapse.pos);
+            }
+        }
+    }
+
+    /// Queue up the cheapest way of collapsing an edge, if it can be collapsed at all
+    fn push(&mut self, a: usize, b: usize) {
+        let quadric = self.quadrics[a].add(&self.quadrics[b]);
+        let best = [(a, b), (b, a)]
+            .into_iter()
+            .filter_map(|(keep, remove)| {
+                let pos = self.collapse_pos(keep, remove, &quadric)?;
+                Some((quadric.error(pos), keep, remove, pos))
+            })
+            .min_by(|x, y| x.0.total_cmp(&y.0));
+
+        if let Some((cost, keep, remove, pos)) = best {
+            self.heap.push(Collapse {
+                cost,
+                keep,
+                remove,
+                pos,
+                versions: (self.versions[keep], self.versions[remove]),
+            });
+        }
+    }
+
+    /// Where `keep` ends up if `remove` is merged into it, if it can be
+    fn collapse_pos(&self, keep: usize, remove: usize, quadric: &Quadric) -> Option<DVec3> {
+        let (k, r) = (self.kinds[keep], self.kinds[remove]);
+        if r == Kind::Locked || k < r || (r == Kind::Seam && !self.seams.contains(&edge(keep, remove))) {
+            return None;
+        }
+        if k > r {
+            return Some(self.points[keep]);
+        }
+
+        let (a, b) = (self.points[keep], self.points[remove]);
+        let mid = (a + b) * 0.5;
+        // The best point can end up far away where the planes are close to parallel
+        let best = quadric.minimum().filter(|p| p.distance(mid) <= a.distance(b));
+        best.into_iter()
+            .chain([a, b, mid])
+            .min_by(|x, y| quadric.error(*x).total_cmp(&quadric.error(*y)))
+    }
+
+    fn neighbours(&self, p: usize) -> Vec<usize> {
+        let mut around: Vec<usize> = self.point_tris[p]
+            .iter()
+            .flat_map(|t| self.tris[*t])
+            .filter(|q| *q != p)
+            .collect();
+        around.sort_unstable();
+        around.dedup();
+        around
+    }
+
+    /// If the points next to both ends of the edge are only those across the two triangles on it,
+    /// otherwise collapsing it would pinch the surface. Closed pieces are never taken down past
+    /// a tetrahedron.
+    fn keeps_manifold(&self, a: usize, b: usize) -> bool {
+        let shared = self.point_tris[a].iter().filter(|t| self.tris[**t].contains(&b)).count();
+        let (around_a, around_b) = (self.neighbours(a), self.neighbours(b));
+        let common = around_a.iter().filter(|p| around_b.contains(p)).count();
+        let left = around_a.len() + around_b.len() - common - 2;
+        shared == 2 && common == 2 && left >= 3
+    }
+
+    /// If moving the triangles around the edge would turn any of them too far or squash it flat
+    fn folds(&self, keep: usize, remove: usize, pos: DVec3) -> bool {
+        [keep, remove].iter().any(|p| {
+            self.point_tris[*p].iter().any(|t| {
+                let tri = self.tris[*t];
+                if tri.contains(&keep) && tri.contains(&remove) {
+                    return false;
+                }
+                let before = self.normal(&tri);
+                let [a, b, c] = tri.map(|q| if q == *p { pos } else { self.points[q] });
+                let after = (b - a).cross(c - a);
+                match (before.try_normalize(), after.try_normalize()) {
+                    (Some(before), Some(after)) => before.dot(after) < MAX_TURN,
+                    _ => true,
+                }
+            })
+        })
+    }
+
+    fn collapse(&mut self, keep: usize, remove: usize, pos: DVec3) {
+        for t in std::mem::take(&mut self.point_tris[remove]) {
+            if self.tris[t].contains(&keep) {
+                self.alive[t] = false;
+                self.alive_count -= 1;
+                for p in self.tris[t] {
+                    self.point_tris[p].retain(|other| *other != t);
+                }
+            } else {
+                for p in &mut self.tris[t] {
+                    if *p == remove {
+                        *p = keep;
+                    }
+                }
+                self.point_tris[keep].push(t);
+            }
+        }
+
+        for p in self.neighbours(keep) {
+            if self.seams.remove(&edge(remove, p)) {
+                self.seams.insert(edge(keep, p));
+            }
+        }
+        self.seams.remove(&edge(keep, remove));
+
+        self.points[keep] = pos;
+        self.quadrics[keep] = self.quadrics[keep].add(&self.quadrics[remove]);
+        self.versions[keep] += 1;
+        self.versions[remove] += 1;
+
+        for p in self.neighbours(keep) {
+            self.push(keep, p);
+        }
+    }
+
+    /// Build the simplified mesh, with a vertex for each point, material and smooth group of faces
+    fn finish(&self, source: &VoxelMesh) -> VoxelMesh {
+        let normals: Vec<Vec3> = self.tris.iter().map(|t| self.normal(t).as_vec3()).collect();
+
+        let mut mesh = VoxelMesh::default();
+        let mut lookup: HashMap<(usize, MaterialId, [u32; 3], u32), usize> = HashMap::new();
+        for (t, tri) in self.tris.iter().enumerate() {
+            if !self.alive[t] {
+                continue;
+            }
+            let material = self.tri_materials[t];
+            let facing = normals[t].normalize_or_zero();
+
+            for p in tri {
+                let normal = self.point_tris[*p]
+                    .iter()
+                    .filter(|other| normals[**other].normalize_or_zero().dot(facing) >= CREASE)
+                    .fold(Vec3::ZERO, |acc, other| acc + normals[*other])
+                    .try_normalize()
+                    .unwrap_or(facing);
+
+                // Occlusion of the source vertices here facing about the same way
+                let source_verts = &self.point_verts[*p];
+                let matching: Vec<f32> = source_verts
+                    .iter()
+                    .filter(|v| source.materials.get(**v) == Some(&material) && source.verts[**v].norm_vec().dot(normal) >= CREASE)
+                    .map(|v| source.verts[*v].ao)
+                    .collect();
+                let ao = if matching.is_empty() {
+                    source_verts.iter().map(|v| source.verts[*v].ao).sum::<f32>() / source_verts.len() as f32
+                } else {
+                    matching.iter().sum::<f32>() / matching.len() as f32
+                };
+
+                let key = (*p, material, normal.to_array().map(f32::to_bits), ao.to_bits());
+                let i = *lookup.entry(key).or_insert_with(|| {
+                    mesh.verts.push(Vertex::new(self.points[*p].as_vec3().into(), normal.into()).with_ao(ao));
+                    mesh.materials.push(material);
+                    mesh.verts.len() - 1
+                });
+                mesh.inds.push(i);
+            }
+        }
+        mesh
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{grid::VoxelGrid, mesher::{block_mesh, open_edges}};
+
+    /// Block mesh of the voxels `inside` says are filled, in a grid of the given size
+    fn blocks(size: [usize; 3], inside: impl Fn([f32; 3]) -> bool) -> VoxelMesh {
+        let mut grid = VoxelGrid::new(Vec3::ZERO, 1.0, size);
+        for x in 0..size[0] {
+            for y in 0..size[1] {
+                for z in 0..size[2] {
+                    if inside([x as f32 + 0.5, y as f32 + 0.5, z as f32 + 0.5]) {
+                        grid.set(x, y, z, Some(0));
+                    }
+                }
+            }
+        }
+        block_mesh(&grid)
+    }
+
+    /// A ball of voxels, so the surface is all steps
+    fn ball() -> VoxelMesh {
+        blocks([10, 10, 10], |p| Vec3::from(p).distance(Vec3::splat(5.0)) < 4.5)
+    }
+
+    fn bounds(mesh: &VoxelMesh) -> (Vec3, Vec3) {
+        let points = mesh.verts.iter().map(|v| Vec3::from(v.pos));
+        (points.clone().fold(Vec3::splat(f32::MAX), Vec3::min), points.fold(Vec3::splat(f32::MIN), Vec3::max))
+    }
+
+    /// Distance from a point to the closest triangle of a mesh
+    fn distance_to(mesh: &VoxelMesh, p: Vec3) -> f32 {
+        let segment = |a: Vec3, b: Vec3| {
+            let t = ((p - a).dot(b - a) / (b - a).length_squared()).clamp(0.0, 1.0);
+            p.distance(a + (b - a) * t)
+        };
+        mesh.inds
+            .chunks_exact(3)
+            .map(|t| {
+                let [a, b, c] = [t[0], t[1], t[2]].map(|i| Vec3::from(mesh.verts[i].pos));
+                let normal = (b - a).cross(c - a).normalize();
+                let height = (p - a).dot(normal);
+                let q = p - normal * height;
+                let inside = [(a, b), (b, c), (c, a)].iter().all(|(s, e)| (*e - *s).cross(q - *s).dot(normal) >= 0.0);
+                if inside {
+                    height.abs()
+                } else {
+                    segment(a, b).min(segment(b, c)).min(segment(c, a))
+                }
+            })
+            .fold(f32::MAX, f32::min)
+    }
+
+    #[test]
+    fn box_keeps_its_shape() {
+        let mesh = blocks([6, 4, 5], |p| p[0] > 1.0 && p[1] > 1.0 && p[2] > 1.0);
+        let simple = simplify(&mesh, &SimplifySettings::default());
+        assert!(simple.inds.len() < mesh.inds.len() / 4);
+        assert_eq!(bounds(&simple), bounds(&mesh));
+        assert_eq!(open_edges(&simple), 0);
+    }
+
+    #[test]
+    fn ratio_is_kept_to() {
+        let mesh = ball();
+        let settings = SimplifySettings { ratio: 0.25, max_error: 100.0 };
+        let simple = simplify(&mesh, &settings);
+        let wanted = (mesh.inds.len() / 3) as f32 * settings.ratio;
+        assert!((simple.inds.len() / 3) as f32 <= wanted.ceil());
+        assert_eq!(open_edges(&simple), 0);
+    }
+
+    #[test]
+    fn surface_moves_no_further_than_max_error() {
+        let mesh = ball();
+        let mut last = usize::MAX;
+        for max_error in [0.0, 1.0, 2.0] {
+            let simple = simplify(&mesh, &SimplifySettings { ratio: 0.0, max_error });
+            let furthest = simple.verts.iter().map(|v| distance_to(&mesh, Vec3::from(v.pos))).fold(0.0, f32::max);
+            assert!(furthest <= max_error + 1e-4, "moved {} with a max error of {}", furthest, max_error);
+            assert!(simple.inds.len() < last, "{} didn't simplify further", max_error);
+            assert_eq!(open_edges(&simple), 0);
+            last = simple.inds.len();
+        }
+    }
+}
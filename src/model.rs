@@ -1,15 +1,14 @@
-use glam::Vec3;
-use glium::{index::PrimitiveType, Display, IndexBuffer, VertexBuffer};
+use glam::{EulerRot, Mat4, Vec3};
+
+use crate::grid::MaterialId;
 
 use self::vertex::Vertex;
 
+pub mod animation;
 pub mod loader;
 pub mod vertex;
 
 pub struct Model {
-    pub vbo: VertexBuffer<Vertex>,
-    pub ind_buf: IndexBuffer<u32>,
-
     pub verts: Vec<Vertex>,
     pub inds: Vec<usize>,
 
@@ -18,16 +17,16 @@ pub struct Model {
     pub pos: Vec3,
     pub rot: Vec3,
     pub scale: f32,
+
+    /// Material given to the voxels made from this model
+    pub material: MaterialId,
+    /// Decides who gets a voxel when models overlap, the highest priority wins
+    pub priority: i32,
 }
 
 impl Model {
-    pub fn new(dis: &Display, verts: Vec<Vertex>, inds: Vec<usize>) -> Model {
-        let ind_buf: Vec<u32> = inds.iter().map(|v| *v as u32).collect();
-        let ind_buf = IndexBuffer::new(dis, PrimitiveType::TrianglesList, &ind_buf).unwrap();
-
+    pub fn new(verts: Vec<Vertex>, inds: Vec<usize>) -> Model {
         Model {
-            vbo: glium::VertexBuffer::new(dis, &verts).unwrap(),
-            ind_buf,
             verts,
             inds,
 
@@ -36,10 +35,34 @@ impl Model {
             pos: Vec3::splat(0.0),
             rot: Vec3::splat(0.0),
             scale: 1.0,
+
+            material: 0,
+            priority: 0,
+        }
+    }
+
+    /// The matrix placing this model in the world according to its position, scale and rotation
+    pub fn transform(&self) -> Mat4 {
+        let mut tmat: Mat4 = Mat4::from_translation(self.pos);
+        tmat *= Mat4::from_scale(Vec3::splat(self.scale));
+        tmat *= Mat4::from_euler(EulerRot::XYZ, self.rot.x, self.rot.y, self.rot.z);
+        tmat
+    }
+
+    /// The world space bounds of the model once it has been transformed
+    pub fn bounding_box(&self) -> BoundingBox {
+        let tmat = self.transform();
+        let mut min = Vec3::splat(f32::INFINITY);
+        let mut max = Vec3::splat(f32::NEG_INFINITY);
+        for v in &self.verts {
+            let pos = tmat.transform_point3(v.pos_vec());
+            min = min.min(pos);
+            max = max.max(pos);
         }
+        BoundingBox { min, max }
     }
 
-    pub fn triangles(&self) -> Triangles {
+    pub fn triangles(&self) -> Triangles<'_> {
         Triangles {
             verts: &self.verts,
             inds: &self.inds,
@@ -49,8 +72,8 @@ impl Model {
 }
 
 pub struct BoundingBox {
-    min: Vec3,
-    max: Vec3,
+    pub min: Vec3,
+    pub max: Vec3,
 }
 
 impl BoundingBox {
@@ -0,0 +1,375 @@
+use std::path::Path;
+
+use glam::{Mat4, Quat, Vec3};
+use gltf::animation::{util::ReadOutputs, Interpolation, Property};
+
+use crate::grid::MaterialId;
+
+use super::{vertex::Vertex, Model};
+
+/// A node of the glTF scene graph, in its rest pose
+#[derive(Clone)]
+struct Node {
+    translation: Vec3,
+    rotation: Quat,
+    scale: Vec3,
+    /// Morph target weights
+    weights: Vec<f32>,
+
+    children: Vec<usize>,
+    mesh: Option<usize>,
+    skin: Option<usize>,
+}
+
+#[derive(Clone)]
+struct Primitive {
+    positions: Vec<Vec3>,
+    normals: Vec<Vec3>,
+    joints: Vec<[u16; 4]>,
+    weights: Vec<[f32; 4]>,
+    /// Position and normal offsets of each morph target
+    targets: Vec<(Vec<Vec3>, Vec<Vec3>)>,
+    inds: Vec<usize>,
+}
+
+#[derive(Clone)]
+struct Skin {
+    joints: Vec<usize>,
+    inverse_bind: Vec<Mat4>,
+}
+
+/// Keyframes animating one property of a node
+#[derive(Clone)]
+struct Channel {
+    node: usize,
+    property: Property,
+    interpolation: Interpolation,
+    times: Vec<f32>,
+    /// `width` floats per keyframe, cubic splines have an in-tangent, value and out-tangent each
+    values: Vec<f32>,
+    width: usize,
+}
+
+#[derive(Clone)]
+pub struct Animation {
+    pub name: String,
+    /// Length of the animation in seconds
+    pub duration: f32,
+    channels: Vec<Channel>,
+}
+
+/// A glTF model with skins, morph targets and animations, which can be posed at any point of
+/// one of its animations
+#[derive(Clone)]
+pub struct AnimatedModel {
+    nodes: Vec<Node>,
+    roots: Vec<usize>,
+    meshes: Vec<Vec<Primitive>>,
+    skins: Vec<Skin>,
+    pub animations: Vec<Animation>,
+
+    pub pos: Vec3,
+    pub rot: Vec3,
+    pub scale: f32,
+    /// Material given to the voxels made from this model
+    pub material: MaterialId,
+}
+
+impl AnimatedModel {
+    /// The model as it is without any animation applied
+    pub fn rest_pose(&self) -> Model {
+        self.pose(usize::MAX, 0.0)
+    }
+
+    /// Pose the model at `time` seconds into an animation, giving a model with all of its meshes
+    /// skinned and morphed into place. An animation that doesn't exist leaves it in its rest pose.
+    pub fn pose(&self, animation: usize, time: f32) -> Model {
+        let mut nodes = self.nodes.clone();
+        if let Some(animation) = self.animations.get(animation) {
+            for channel in &animation.channels {
+                let value = channel.sample(time);
+                let node = &mut nodes[channel.node];
+                match channel.property {
+                    Property::Translation => node.translation = Vec3::from_slice(&value),
+                    Property::Rotation => node.rotation = Quat::from_slice(&value).normalize(),
+                    Property::Scale => node.scale = Vec3::from_slice(&value),
+                    Property::MorphTargetWeights => node.weights = value,
+                }
+            }
+        }
+
+        // Work out where every node ends up
+        let mut global = vec![Mat4::IDENTITY; nodes.len()];
+        let mut visited = Vec::new();
+        let mut stack: Vec<(usize, Mat4)> = self.roots.iter().map(|r| (*r, Mat4::IDENTITY)).collect();
+        while let Some((i, parent)) = stack.pop() {
+            let node = &nodes[i];
+            global[i] = parent * Mat4::from_scale_rotation_translation(node.scale, node.rotation, node.translation);
+            visited.push(i);
+            for c in &node.children {
+                stack.push((*c, global[i]));
+            }
+        }
+        // Keep the output in the same order no matter how the stack was walked
+        visited.sort_unstable();
+
+        let mut verts = Vec::new();
+        let mut inds = Vec::new();
+        for i in visited {
+            let node = &nodes[i];
+            let mesh = match node.mesh {
+                Some(mesh) => &self.meshes[mesh],
+                None => continue,
+            };
+            let joint_mats: Option<Vec<Mat4>> = node.skin.map(|s| {
+                let skin = &self.skins[s];
+                skin.joints
+                    .iter()
+                    .zip(&skin.inverse_bind)
+                    .map(|(j, ibm)| global[*j] * *ibm)
+                    .collect()
+            });
+
+            for prim in mesh {
+                let base = verts.len();
+                for v in 0..prim.positions.len() {
+                    let mut pos = prim.positions[v];
+                    let mut norm = prim.normals.get(v).copied().unwrap_or(Vec3::Y);
+                    for (w, (positions, normals)) in node.weights.iter().zip(&prim.targets) {
+                        pos += *w * positions.get(v).copied().unwrap_or(Vec3::ZERO);
+                        norm += *w * normals.get(v).copied().unwrap_or(Vec3::ZERO);
+                    }
+
+                    // Skinned meshes ignore the transform of their own node
+                    let mat = match &joint_mats {
+                        Some(joint_mats) if v < prim.joints.len() && v < prim.weights.len() => {
+                            let mut mat = Mat4::ZERO;
+                            for (j, w) in prim.joints[v].iter().zip(prim.weights[v]) {
+                                if let Some(joint) = joint_mats.get(*j as usize) {
+                                    mat += *joint * w;
+                                }
+                            }
+                            mat
+                        }
+                        _ => global[i],
+                    };
+
+                    let pos = mat.transform_point3(pos);
+                    let norm = mat.transform_vector3(norm).normalize_or_zero();
+                    verts.push(Vertex::new(pos.into(), norm.into()));
+                }
+                inds.extend(prim.inds.iter().map(|i| i + base));
+            }
+        }
+
+        let mut model = Model::new(verts, inds);
+        model.pos = self.pos;
+        model.rot = self.rot;
+        model.scale = self.scale;
+        model.material = self.material;
+        model
+    }
+}
+
+impl Channel {
+    fn value(&self, key: usize) -> &[f32] {
+        let key = match self.interpolation {
+            Interpolation::CubicSpline => key * 3 + 1,
+            _ => key,
+        };
+        &self.values[key * self.width..(key + 1) * self.width]
+    }
+
+    /// Sample the channel `time` seconds in, holding the first and last keyframes outside of it
+    fn sample(&self, time: f32) -> Vec<f32> {
+        let next = self.times.partition_point(|t| *t <= time);
+        if next == 0 {
+            return self.value(0).to_vec();
+        }
+        if next == self.times.len() {
+            return self.value(next - 1).to_vec();
+        }
+
+        let key = next - 1;
+        let dt = self.times[next] - self.times[key];
+        let u = if dt > 0.0 { (time - self.times[key]) / dt } else { 0.0 };
+        let (a, b) = (self.value(key), self.value(next));
+
+        match self.interpolation {
+            Interpolation::Step => a.to_vec(),
+            Interpolation::Linear if self.property == Property::Rotation => {
+                Quat::from_slice(a).slerp(Quat::from_slice(b), u).to_array().to_vec()
+            }
+            Interpolation::Linear => a.iter().zip(b).map(|(a, b)| a + (b - a) * u).collect(),
+            Interpolation::CubicSpline => {
+                let w = self.width;
+                let out_tangent = &self.values[(key * 3 + 2) * w..(key * 3 + 3) * w];
+                let in_tangent = &self.values[(next * 3) * w..(next * 3 + 1) * w];
+
+                let (u2, u3) = (u * u, u * u * u);
+                (0..w)
+                    .map(|i| {
+                        (2.0 * u3 - 3.0 * u2 + 1.0) * a[i]
+                            + (u3 - 2.0 * u2 + u) * dt * out_tangent[i]
+                            + (-2.0 * u3 + 3.0 * u2) * b[i]
+                            + (u3 - u2) * dt * in_tangent[i]
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+/// Load a glTF model along with everything needed to play back its animations
+pub fn load_animated(file: &Path) -> Result<AnimatedModel, String> {
+    let (doc, buffers, _) = match gltf::import(file) {
+        Ok(imported) => imported,
+        Err(e) => return Err(format!("Failed to read file - {}", e)),
+    };
+    let get_buffer = |b: gltf::Buffer| buffers.get(b.index()).map(|d| &d.0[..]);
+
+    let nodes: Vec<Node> = doc
+        .nodes()
+        .map(|n| {
+            let (translation, rotation, scale) = n.transform().decomposed();
+            let weights = n
+                .weights()
+                .or_else(|| n.mesh().and_then(|m| m.weights()))
+                .map(|w| w.to_vec())
+                .unwrap_or_default();
+            Node {
+                translation: Vec3::from(translation),
+                rotation: Quat::from_array(rotation),
+                scale: Vec3::from(scale),
+                weights,
+
+                children: n.children().map(|c| c.index()).collect(),
+                mesh: n.mesh().map(|m| m.index()),
+                skin: n.skin().map(|s| s.index()),
+            }
+        })
+        .collect();
+
+    let roots: Vec<usize> = match doc.default_scene().or_else(|| doc.scenes().next()) {
+        Some(scene) => scene.nodes().map(|n| n.index()).collect(),
+        None => (0..nodes.len())
+            .filter(|i| !nodes.iter().any(|n| n.children.contains(i)))
+            .collect(),
+    };
+
+    let meshes = doc
+        .meshes()
+        .map(|mesh| {
+            mesh.primitives()
+                .filter(|p| p.mode() == gltf::mesh::Mode::Triangles)
+                .filter_map(|p| {
+                    let reader = p.reader(get_buffer);
+                    let positions: Vec<Vec3> = reader.read_positions()?.map(Vec3::from).collect();
+                    let inds = match reader.read_indices() {
+                        Some(inds) => inds.into_u32().map(|i| i as usize).collect(),
+                        None => (0..positions.len()).collect(),
+                    };
+                    Some(Primitive {
+                        normals: reader
+                            .read_normals()
+                            .map(|n| n.map(Vec3::from).collect())
+                            .unwrap_or_default(),
+                        joints: reader
+                            .read_joints(0)
+                            .map(|j| j.into_u16().collect())
+                            .unwrap_or_default(),
+                        weights: reader
+                            .read_weights(0)
+                            .map(|w| w.into_f32().collect())
+                            .unwrap_or_default(),
+                        targets: reader
+                            .read_morph_targets()
+                            .map(|(positions, normals, _)| {
+                                (
+                                    positions.map(|p| p.map(Vec3::from).collect()).unwrap_or_default(),
+                                    normals.map(|n| n.map(Vec3::from).collect()).unwrap_or_default(),
+                                )
+                            })
+                            .collect(),
+                        positions,
+                        inds,
+                    })
+                })
+                .collect()
+        })
+        .collect();
+
+    let skins = doc
+        .skins()
+        .map(|skin| {
+            let joints: Vec<usize> = skin.joints().map(|j| j.index()).collect();
+            let inverse_bind = match skin.reader(get_buffer).read_inverse_bind_matrices() {
+                Some(mats) => mats.map(|m| Mat4::from_cols_array_2d(&m)).collect(),
+                None => vec![Mat4::IDENTITY; joints.len()],
+            };
+            Skin {
+                joints,
+                inverse_bind,
+            }
+        })
+        .collect();
+
+    let animations = doc
+        .animations()
+        .map(|anim| {
+            let channels: Vec<Channel> = anim
+                .channels()
+                .filter_map(|channel| {
+                    let reader = channel.reader(get_buffer);
+                    let times: Vec<f32> = reader.read_inputs()?.collect();
+                    let values: Vec<f32> = match reader.read_outputs()? {
+                        ReadOutputs::Translations(t) => t.flatten().collect(),
+                        ReadOutputs::Rotations(r) => r.into_f32().flatten().collect(),
+                        ReadOutputs::Scales(s) => s.flatten().collect(),
+                        ReadOutputs::MorphTargetWeights(w) => w.into_f32().collect(),
+                    };
+
+                    let interpolation = channel.sampler().interpolation();
+                    let per_key = match interpolation {
+                        Interpolation::CubicSpline => 3,
+                        _ => 1,
+                    };
+                    if times.is_empty() || values.len() < times.len() * per_key {
+                        return None;
+                    }
+
+                    Some(Channel {
+                        node: channel.target().node().index(),
+                        property: channel.target().property(),
+                        interpolation,
+                        width: values.len() / (times.len() * per_key),
+                        times,
+                        values,
+                    })
+                })
+                .collect();
+
+            Animation {
+                name: anim.name().map(String::from).unwrap_or_else(|| format!("Animation {}", anim.index())),
+                duration: channels
+                    .iter()
+                    .filter_map(|c| c.times.last().copied())
+                    .fold(0.0, f32::max),
+                channels,
+            }
+        })
+        .collect();
+
+    Ok(AnimatedModel {
+        nodes,
+        roots,
+        meshes,
+        skins,
+        animations,
+
+        pos: Vec3::splat(0.0),
+        rot: Vec3::splat(0.0),
+        scale: 1.0,
+        material: 0,
+    })
+}
@@ -5,10 +5,13 @@ use obj::{load_obj, TexturedVertex};
 use super::vertex::Vertex;
 
 pub fn load_model(file: PathBuf) -> Result<(Vec<Vertex>, Vec<usize>), String> {
-    match file.extension().unwrap().to_str().unwrap() {
+    match file.extension().and_then(|e| e.to_str()).unwrap_or_default() {
         "gltf" | "glb" => match easy_gltf::load(&file) {
             Ok(scenes) => {
-                let model = &scenes[0].models[0];
+                let model = match scenes.first().and_then(|s| s.models.first()) {
+                    Some(model) => model,
+                    None => return Err(String::from("File doesn't have any models in it")),
+                };
 
                 if let Some(inds) = model.indices() {
                     let verts = model.vertices().clone();
@@ -29,7 +32,10 @@ pub fn load_model(file: PathBuf) -> Result<(Vec<Vertex>, Vec<usize>), String> {
             Err(e) => Err(format!("Failed to read file - {}", e)),
         },
         "obj" => {
-            let input = BufReader::new(File::open(&file).unwrap());
+            let input = match File::open(&file) {
+                Ok(f) => BufReader::new(f),
+                Err(e) => return Err(format!("Failed to open file - {}", e)),
+            };
             match load_obj::<TexturedVertex, BufReader<File>, usize>(input) {
                 Ok(obj) => {
                     let inds = obj.indices;
@@ -1,26 +1,34 @@
 use glam::Vec3;
-use glium::implement_vertex;
 
 #[derive(Debug, Copy, Clone)]
 pub struct Vertex {
     pub pos: [f32; 3],
     pub norm: [f32; 3],
+    /// How much ambient light reaches the vertex, from 0 for none to 1 for all of it
+    pub ao: f32,
 }
 
-implement_vertex!(Vertex, pos, norm);
+#[cfg(feature = "gui")]
+glium::implement_vertex!(Vertex, pos, norm, ao);
 
 impl Vertex {
     pub fn new(position: [f32; 3], normal: [f32; 3]) -> Vertex {
         Vertex {
             pos: position,
             norm: normal,
+            ao: 1.0,
         }
     }
 
+    pub fn with_ao(self, ao: f32) -> Vertex {
+        Vertex { ao, ..self }
+    }
+
     pub fn from_gltf(vert: &easy_gltf::model::Vertex) -> Vertex {
         Vertex {
             pos: [vert.position.x, vert.position.y, vert.position.z],
             norm: [vert.normal.x, vert.normal.y, vert.normal.z],
+            ao: 1.0,
         }
     }
 
@@ -0,0 +1,68 @@
+//! Colours for materials, for file formats that store colours rather than materials
+
+use crate::grid::MaterialId;
+
+/// An RGBA colour for each material, with materials that weren't given one falling back to
+/// [`default_colour`]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Palette {
+    colours: Vec<Option<[u8; 4]>>,
+}
+
+impl Palette {
+    /// A palette where every material has its default colour
+    pub fn new() -> Palette {
+        Palette::default()
+    }
+
+    /// A palette giving each material the colour at its index
+    pub fn from_colours(colours: impl IntoIterator<Item = [u8; 4]>) -> Palette {
+        Palette {
+            colours: colours.into_iter().map(Some).collect(),
+        }
+    }
+
+    pub fn colour(&self, material: MaterialId) -> [u8; 4] {
+        match self.colours.get(material as usize) {
+            Some(Some(colour)) => *colour,
+            _ => default_colour(material),
+        }
+    }
+
+    pub fn set(&mut self, material: MaterialId, colour: [u8; 4]) {
+        let i = material as usize;
+        if i >= self.colours.len() {
+            self.colours.resize(i + 1, None);
+        }
+        self.colours[i] = Some(colour);
+    }
+}
+
+/// Darken a colour by the ambient occlusion of a vertex, from 0 for black to 1 for unchanged,
+/// the same way the viewer shades it. Alpha is left alone.
+pub fn shade(colour: [u8; 4], ao: f32) -> [u8; 4] {
+    let [r, g, b, a] = colour;
+    let [r, g, b] = [r, g, b].map(|c| (c as f32 * ao.clamp(0.0, 1.0)).round() as u8);
+    [r, g, b, a]
+}
+
+/// A colour for a material that wasn't given one. Hues are spread out by the golden angle so
+/// neighbouring materials are easy to tell apart.
+pub fn default_colour(material: MaterialId) -> [u8; 4] {
+    let hue = (material as f32 * 0.618_034).fract() * 6.0;
+    let (saturation, value) = (0.6, 0.9);
+
+    let chroma = value * saturation;
+    let x = chroma * (1.0 - (hue % 2.0 - 1.0).abs());
+    let (r, g, b) = match hue as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let base = value - chroma;
+    let [r, g, b] = [r, g, b].map(|c| ((c + base) * 255.0).round() as u8);
+    [r, g, b, 255]
+}
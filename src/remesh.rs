@@ -0,0 +1,293 @@
+//! Repairing broken meshes by going through voxels.
+//!
+//! The models are voxelised, small holes and cracks are closed up, and the inside is filled in
+//! so that only one surface is left. That surface is then extracted again, giving a closed mesh
+//! however broken the source was.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{mpsc::{self, Receiver}, Arc},
+    thread,
+};
+
+use glam::Vec3;
+
+use crate::{
+    grid::VoxelGrid,
+    mesher::{self, simplify::{simplify, SimplifySettings}, MeshStyle, VoxelMesh},
+    model::Model,
+    voxeliser,
+};
+
+/// How a remesh is done
+#[derive(Debug, Clone, Copy)]
+pub struct RemeshSettings {
+    /// Side length of each voxel, the detail of the result
+    pub voxel_size: f32,
+    /// Holes and cracks up to about twice this many voxels across are closed up
+    pub closing: usize,
+    /// How the surface is extracted. Marching cubes always gives a closed manifold, the other
+    /// smooth styles can leave edges shared by more than two triangles where thin parts touch.
+    pub style: MeshStyle,
+    /// Number of rounds of Taubin smoothing done on the extracted surface. Greedy meshes have too
+    /// few vertices to smooth and shrink away.
+    pub smoothing: usize,
+    /// How the surface is simplified once it's smoothed, if it is
+    pub simplify: Option<SimplifySettings>,
+}
+
+impl Default for RemeshSettings {
+    fn default() -> RemeshSettings {
+        RemeshSettings {
+            voxel_size: 0.05,
+            closing: 1,
+            style: MeshStyle::MarchingCubes { smoothing: 1 },
+            smoothing: 0,
+            simplify: None,
+        }
+    }
+}
+
+/// Turn several placed models into one closed surface, blocking until it is done
+pub fn remesh<'a>(models: impl IntoIterator<Item = &'a Model>, settings: &RemeshSettings) -> VoxelMesh {
+    remesh_grid(&voxeliser::voxelise_scene(models, settings.voxel_size), settings)
+}
+
+/// Remesh several placed models on a background thread, sending back the surface once done
+pub fn generate_remesh<'a>(models: impl IntoIterator<Item = &'a Model>, settings: RemeshSettings) -> Receiver<VoxelMesh> {
+    let (send_mesh, receive_mesh) = mpsc::channel::<VoxelMesh>();
+
+    let scene = Arc::new(voxeliser::get_scene_data(models));
+    thread::spawn(move || {
+        let shell = voxeliser::voxelise_data(scene, settings.voxel_size, &Arc::default());
+        // Nobody is listening anymore if the remesh was cancelled
+        send_mesh.send(remesh_grid(&shell, &settings)).ok();
+    });
+
+    receive_mesh
+}
+
+/// Turn the voxels of a model's surface into one closed surface.
+///
+/// Closing moves the surface away from the source triangles, so the smooth styles are meshed
+/// without Hermite data.
+pub fn remesh_grid(shell: &VoxelGrid, settings: &RemeshSettings) -> VoxelMesh {
+    let grid = solidify(shell, settings.closing);
+
+    let mut mesh = mesher::mesh(&grid, settings.style);
+    if settings.smoothing > 0 {
+        taubin_smooth(&mut mesh, settings.smoothing);
+        mesh.recompute_normals();
+    }
+    match &settings.simplify {
+        Some(simplify_settings) => simplify(&mesh, simplify_settings),
+        None => mesh,
+    }
+}
+
+/// Turn the voxels of a model's surface into a solid grid, closing up holes and cracks up to
+/// about `closing * 2` voxels across and filling in everything they enclose. The grid grows by
+/// `closing + 1` voxels on every side.
+pub fn solidify(shell: &VoxelGrid, closing: usize) -> VoxelGrid {
+    // Leave room around the edges so closing doesn't eat into the model
+    let grid = pad(shell, closing + 1);
+    let grid = close(&grid, closing);
+    fill_interior(&grid)
+}
+
+/// A copy of the grid with `amount` empty voxels added on every side
+fn pad(grid: &VoxelGrid, amount: usize) -> VoxelGrid {
+    let size = grid.size.map(|s| s + amount * 2);
+    let mut padded = VoxelGrid::new(grid.min - Vec3::splat(amount as f32 * grid.scale), grid.scale, size);
+    for x in 0..grid.size[0] {
+        for y in 0..grid.size[1] {
+            for z in 0..grid.size[2] {
+                padded.set(x + amount, y + amount, z + amount, grid.get(x, y, z));
+            }
+        }
+    }
+    padded
+}
+
+/// Morphological closing with a cube `radius` voxels out from the centre: everything is grown
+/// out by `radius` then shrunk back again, which fills in gaps narrower than the cube without
+/// changing the rest of the shape.
+pub fn close(grid: &VoxelGrid, radius: usize) -> VoxelGrid {
+    if radius == 0 {
+        return grid.clone();
+    }
+    let mut closed = grid.clone();
+    for axis in 0..3 {
+        closed = dilate_axis(&closed, axis, radius);
+    }
+    for axis in 0..3 {
+        closed = erode_axis(&closed, axis, radius);
+    }
+    closed
+}
+
+/// Fill every empty voxel within `radius` of a filled one along an axis, taking the material of
+/// the closest one
+fn dilate_axis(grid: &VoxelGrid, axis: usize, radius: usize) -> VoxelGrid {
+    let mut out = grid.clone();
+    let r = radius as i32;
+    for x in 0..grid.size[0] {
+        for y in 0..grid.size[1] {
+            for z in 0..grid.size[2] {
+                if grid.get(x, y, z).is_some() {
+                    continue;
+                }
+                let pos = [x as i32, y as i32, z as i32];
+                let material = (1..=r).find_map(|d| {
+                    [-d, d].into_iter().find_map(|d| {
+                        let mut p = pos;
+                        p[axis] += d;
+                        grid.material(p[0], p[1], p[2])
+                    })
+                });
+                out.set(x, y, z, material);
+            }
+        }
+    }
+    out
+}
+
+/// Empty every filled voxel within `radius` of an empty one along an axis. Outside of the grid
+/// counts as empty.
+fn erode_axis(grid: &VoxelGrid, axis: usize, radius: usize) -> VoxelGrid {
+    let mut out = grid.clone();
+    let r = radius as i32;
+    for x in 0..grid.size[0] {
+        for y in 0..grid.size[1] {
+            for z in 0..grid.size[2] {
+                if grid.get(x, y, z).is_none() {
+                    continue;
+                }
+                let pos = [x as i32, y as i32, z as i32];
+                let exposed = (-r..=r).any(|d| {
+                    let mut p = pos;
+                    p[axis] += d;
+                    !grid.is_filled(p[0], p[1], p[2])
+                });
+                if exposed {
+                    out.set(x, y, z, None);
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Fill in every empty voxel that can't be reached from outside of the grid without passing
+/// through a filled one. Filled in voxels take the material of the last filled voxel before them
+/// along z.
+pub fn fill_interior(grid: &VoxelGrid) -> VoxelGrid {
+    let [res_x, res_y, res_z] = grid.size;
+    let index = |x: usize, y: usize, z: usize| (x * res_y + y) * res_z + z;
+
+    // Flood the outside in from every empty voxel on the sides of the grid
+    let mut outside = vec![false; res_x * res_y * res_z];
+    let mut queue = VecDeque::new();
+    for x in 0..res_x {
+        for y in 0..res_y {
+            for z in 0..res_z {
+                let on_side = x == 0 || y == 0 || z == 0 || x == res_x - 1 || y == res_y - 1 || z == res_z - 1;
+                if on_side && grid.get(x, y, z).is_none() {
+                    outside[index(x, y, z)] = true;
+                    queue.push_back([x, y, z]);
+                }
+            }
+        }
+    }
+    while let Some(pos) = queue.pop_front() {
+        for axis in 0..3 {
+            for d in [-1, 1] {
+                let next = pos[axis] as i64 + d;
+                if next < 0 || next >= grid.size[axis] as i64 {
+                    continue;
+                }
+                let mut p = pos;
+                p[axis] = next as usize;
+                let i = index(p[0], p[1], p[2]);
+                if !outside[i] && grid.get(p[0], p[1], p[2]).is_none() {
+                    outside[i] = true;
+                    queue.push_back(p);
+                }
+            }
+        }
+    }
+
+    let mut filled = grid.clone();
+    for x in 0..res_x {
+        for y in 0..res_y {
+            let mut last = None;
+            for z in 0..res_z {
+                match grid.get(x, y, z) {
+                    Some(m) => last = Some(m),
+                    None if !outside[index(x, y, z)] => filled.set(x, y, z, last.or(Some(0))),
+                    None => {}
+                }
+            }
+        }
+    }
+    filled
+}
+
+/// Smooth the surface of a mesh without shrinking it, by moving each vertex towards the average
+/// of its neighbours then back out a little further.
+///
+/// Vertices in the same place move together, so meshes with a vertex per face like
+/// [`MeshStyle::Blocks`] stay joined up.
+pub fn taubin_smooth(mesh: &mut VoxelMesh, iterations: usize) {
+    const LAMBDA: f32 = 0.5;
+    const MU: f32 = -0.53;
+
+    // Give every distinct position one point to be smoothed
+    let mut lookup: HashMap<[u32; 3], usize> = HashMap::new();
+    let mut points: Vec<Vec3> = Vec::new();
+    let point_of: Vec<usize> = mesh
+        .verts
+        .iter()
+        .map(|v| {
+            *lookup.entry(v.pos.map(f32::to_bits)).or_insert_with(|| {
+                points.push(v.pos_vec());
+                points.len() - 1
+            })
+        })
+        .collect();
+
+    let mut neighbours: Vec<Vec<usize>> = vec![Vec::new(); points.len()];
+    for t in mesh.inds.chunks_exact(3) {
+        for e in 0..3 {
+            let (a, b) = (point_of[t[e]], point_of[t[(e + 1) % 3]]);
+            if a == b {
+                continue;
+            }
+            if !neighbours[a].contains(&b) {
+                neighbours[a].push(b);
+            }
+            if !neighbours[b].contains(&a) {
+                neighbours[b].push(a);
+            }
+        }
+    }
+
+    let step = |points: &mut [Vec3], factor: f32| {
+        let before = points.to_vec();
+        for (p, around) in points.iter_mut().zip(&neighbours) {
+            if around.is_empty() {
+                continue;
+            }
+            let average = around.iter().fold(Vec3::ZERO, |acc, n| acc + before[*n]) / around.len() as f32;
+            *p += (average - *p) * factor;
+        }
+    };
+    for _ in 0..iterations {
+        step(&mut points, LAMBDA);
+        step(&mut points, MU);
+    }
+
+    for (v, p) in mesh.verts.iter_mut().zip(point_of) {
+        v.pos = points[p].into();
+    }
+}
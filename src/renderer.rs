@@ -1,15 +1,17 @@
 use std::path::Path;
 
-use glam::{EulerRot, Mat4, Vec3};
+use glam::{Mat4, Vec3};
 use glium::{
     draw_parameters, uniform, BackfaceCullingMode, Depth, Display, DrawParameters, Frame, Program,
     Surface,
 };
 
-use crate::model::Model;
-
-use self::camera::Camera;
+use self::{
+    buffers::{InstanceBuffers, ModelBuffers},
+    camera::Camera,
+};
 
+pub mod buffers;
 pub mod camera;
 pub mod shader;
 
@@ -17,6 +19,8 @@ pub struct Renderer {
     pub cam: Camera,
 
     normal_prog: Program,
+    /// Like `normal_prog`, but moving each copy of the mesh by its instance's offset
+    instanced_prog: Program,
 }
 
 impl Renderer {
@@ -28,6 +32,12 @@ impl Renderer {
             Path::new("shaders/f.glsl"),
         )
         .expect("Failed to compile shaders");
+        let instanced_prog = shader::read_shader(
+            dis,
+            Path::new("shaders/instanced_v.glsl"),
+            Path::new("shaders/f.glsl"),
+        )
+        .expect("Failed to compile shaders");
 
         Renderer {
             cam: Camera::new_with_values(
@@ -38,11 +48,12 @@ impl Renderer {
             ),
 
             normal_prog,
+            instanced_prog,
         }
     }
 
-    pub fn render_model(&mut self, target: &mut Frame, model: &Model) {
-        let params = DrawParameters {
+    fn draw_parameters() -> DrawParameters<'static> {
+        DrawParameters {
             depth: Depth {
                 test: draw_parameters::DepthTest::IfLess,
                 write: true,
@@ -50,12 +61,14 @@ impl Renderer {
             },
             backface_culling: BackfaceCullingMode::CullClockwise,
             ..Default::default()
-        };
+        }
+    }
+
+    /// Draw a mesh, placed in the world by `tmat`
+    pub fn render_model(&mut self, target: &mut Frame, model: &ModelBuffers, tmat: Mat4) {
+        let params = Renderer::draw_parameters();
 
         let pvmat = self.cam.get_pvmat().to_cols_array_2d();
-        let mut tmat: Mat4 = Mat4::from_translation(model.pos);
-        tmat *= Mat4::from_scale(Vec3::splat(model.scale));
-        tmat *= Mat4::from_euler(EulerRot::XYZ, model.rot.x, model.rot.y, model.rot.z);
 
         let uniforms = uniform! {
             pvmat: pvmat,
@@ -71,4 +84,24 @@ impl Renderer {
             )
             .unwrap();
     }
+
+    /// Draw a copy of a mesh at each instance, all placed in the world by `tmat`
+    pub fn render_instances(&mut self, target: &mut Frame, buffers: &InstanceBuffers, tmat: Mat4) {
+        let params = Renderer::draw_parameters();
+
+        let uniforms = uniform! {
+            pvmat: self.cam.get_pvmat().to_cols_array_2d(),
+            tmat: tmat.to_cols_array_2d(),
+        };
+        let instances = buffers.instances.per_instance().expect("Instancing isn't supported");
+        target
+            .draw(
+                (&buffers.model.vbo, instances),
+                &buffers.model.ind_buf,
+                &self.instanced_prog,
+                &uniforms,
+                &params,
+            )
+            .unwrap();
+    }
 }
@@ -0,0 +1,52 @@
+use glium::{index::PrimitiveType, Display, IndexBuffer, VertexBuffer};
+
+use crate::{
+    mesher::instances::{VoxelInstance, VoxelInstances},
+    model::vertex::Vertex,
+};
+
+/// The vertex and index buffers of a mesh uploaded to the gpu
+pub struct ModelBuffers {
+    pub vbo: VertexBuffer<Vertex>,
+    pub ind_buf: IndexBuffer<u32>,
+}
+
+impl ModelBuffers {
+    /// Upload a mesh, failing if it has too many vertices for 32 bit indices or the buffers
+    /// couldn't be made
+    pub fn new(dis: &Display, verts: &[Vertex], inds: &[usize]) -> Result<ModelBuffers, String> {
+        if verts.len() > u32::MAX as usize {
+            return Err(format!("Mesh has too many vertices to draw ({})", verts.len()));
+        }
+
+        let ind_buf: Vec<u32> = inds.iter().map(|v| *v as u32).collect();
+        let ind_buf = match IndexBuffer::new(dis, PrimitiveType::TrianglesList, &ind_buf) {
+            Ok(ind_buf) => ind_buf,
+            Err(e) => return Err(format!("Failed to create index buffer - {}", e)),
+        };
+        let vbo = match VertexBuffer::new(dis, verts) {
+            Ok(vbo) => vbo,
+            Err(e) => return Err(format!("Failed to create vertex buffer - {}", e)),
+        };
+
+        Ok(ModelBuffers { vbo, ind_buf })
+    }
+}
+
+/// A cube mesh and the places to draw copies of it, uploaded to the gpu
+pub struct InstanceBuffers {
+    pub model: ModelBuffers,
+    pub instances: VertexBuffer<VoxelInstance>,
+}
+
+impl InstanceBuffers {
+    pub fn new(dis: &Display, voxels: &VoxelInstances) -> Result<InstanceBuffers, String> {
+        let model = ModelBuffers::new(dis, &voxels.cube.verts, &voxels.cube.inds)?;
+        let instances = match VertexBuffer::new(dis, &voxels.instances) {
+            Ok(instances) => instances,
+            Err(e) => return Err(format!("Failed to create instance buffer - {}", e)),
+        };
+
+        Ok(InstanceBuffers { model, instances })
+    }
+}
@@ -1,353 +1,470 @@
-use std::{sync::{Arc, mpsc::{self, Receiver, Sender}}, thread, time::Duration};
+use std::{cmp::Reverse, sync::{Arc, atomic::{AtomicBool, Ordering}, mpsc::{self, Receiver, Sender}}, thread};
 
-use glam::{EulerRot, Vec3, Mat4, Vec4Swizzles};
+use glam::{Vec3, Vec4Swizzles};
 use threadpool::ThreadPool;
 
-use crate::model::{Model, vertex::Vertex};
+use crate::{grid::{EdgeIntersection, HermiteData, MaterialId, VoxelAnimation, VoxelGrid}, mesher::{chunks::{self, ChunkedMesh}, simplify::SimplifySettings, MeshStyle, VoxelMesh}, model::{Model, animation::AnimatedModel, vertex::Vertex}};
 
-struct ModelData {
+pub(crate) struct ModelData {
     pub verts: Vec<Vertex>,
     pub inds: Vec<usize>,
+    /// Normal of each triangle
+    pub normals: Vec<Vec3>,
+
+    pub material: MaterialId,
+    pub priority: i32,
 }
 
 fn get_model_data(model: &Model) -> ModelData {
     let mut verts = Vec::new();
-    let mut tmat: Mat4 = Mat4::from_translation(model.pos);
-        tmat *= Mat4::from_scale(Vec3::splat(model.scale));
-        tmat *= Mat4::from_euler(EulerRot::XYZ, model.rot.x, model.rot.y, model.rot.z);
+    let tmat = model.transform();
 
     for v in &model.verts {
         let pos = tmat * Vec3::from_slice(&v.pos).extend(1.0);
         verts.push(Vertex::new(pos.xyz().into(), v.norm));
     }
-    
+
+    let normals = model
+        .inds
+        .chunks_exact(3)
+        .map(|t| {
+            let (a, b, c) = (verts[t[0]].pos_vec(), verts[t[1]].pos_vec(), verts[t[2]].pos_vec());
+            (b - a).cross(c - a).normalize_or_zero()
+        })
+        .collect();
+
     ModelData {
         verts,
         inds: model.inds.clone(),
+        normals,
+
+        material: model.material,
+        priority: model.priority,
     }
 }
 
-pub fn generate_voxels(model: &Model, scale: f32) -> Receiver<(Vec<Vertex>, Vec<usize>)> {
-    let (send_model, receive_model) = mpsc::channel::<(Vec<Vertex>, Vec<usize>)>();
+/// Transform each of the models into the world, ordered by who wins when they overlap
+pub(crate) fn get_scene_data<'a>(models: impl IntoIterator<Item = &'a Model>) -> Vec<ModelData> {
+    let mut scene: Vec<ModelData> = models.into_iter().map(get_model_data).collect();
+    // Stable, so ties stay in the order they were given
+    scene.sort_by_key(|m| Reverse(m.priority));
+    scene
+}
 
-    let model_data = Arc::new(get_model_data(model));
+/// Voxelise a scene on a background thread, sending back the voxels meshed in chunks once done,
+/// with each chunk simplified if `simplify` is given.
+///
+/// Setting `cancel` stops the work as soon as the slabs already being voxelised are done, and
+/// nothing is sent back.
+pub fn generate_voxels<'a>(models: impl IntoIterator<Item = &'a Model>, scale: f32, style: MeshStyle, simplify: Option<SimplifySettings>, cancel: Arc<AtomicBool>) -> Receiver<ChunkedMesh> {
+    let (send_model, receive_model) = mpsc::channel::<ChunkedMesh>();
+
+    let scene = Arc::new(get_scene_data(models));
     thread::spawn(move || {
-        let (tx, rx) = mpsc::channel::<(usize, usize, usize, bool)>();
+        let grid = voxelise_data(scene.clone(), scale, &cancel);
+        if cancel.load(Ordering::Relaxed) {
+            return;
+        }
+        let hermite = if style.uses_hermite() {
+            hermite_data(scene, &grid)
+        } else {
+            HermiteData::new()
+        };
+        if cancel.load(Ordering::Relaxed) {
+            return;
+        }
+        let mesh = ChunkedMesh::with_simplify(grid, hermite, style, simplify);
+
+        // Nobody is listening anymore if the result was replaced by a newer one
+        send_model.send(mesh).ok();
+    });
 
-        let pool = ThreadPool::new(16);
+    receive_model
+}
 
-        let mut min = model_data.verts[0].pos;
-        for v in &model_data.verts {
-            min[0] = min[0].min(v.pos[0]);
-            min[1] = min[1].min(v.pos[1]);
-            min[2] = min[2].min(v.pos[2]);
-        }
-        let min = min;
+/// Voxelise a model, blocking until it is done
+pub fn voxelise(model: &Model, scale: f32) -> VoxelGrid {
+    voxelise_scene([model], scale)
+}
+
+/// Voxelise several placed models into one shared grid, blocking until it is done.
+///
+/// Every voxel records the material of the model it came from. Where models overlap the voxel
+/// goes to the one with the highest `priority`, with ties going to whichever came first.
+pub fn voxelise_scene<'a>(models: impl IntoIterator<Item = &'a Model>, scale: f32) -> VoxelGrid {
+    voxelise_data(Arc::new(get_scene_data(models)), scale, &Arc::default())
+}
 
-        let mut max = model_data.verts[0].pos;
+/// Voxelise several placed models like [`voxelise_scene`], also finding exactly where their
+/// surfaces cross between each filled voxel and its empty neighbours, for meshing styles that
+/// can keep sharp features.
+pub fn voxelise_scene_hermite<'a>(models: impl IntoIterator<Item = &'a Model>, scale: f32) -> (VoxelGrid, HermiteData) {
+    let scene = Arc::new(get_scene_data(models));
+    let grid = voxelise_data(scene.clone(), scale, &Arc::default());
+    let hermite = hermite_data(scene, &grid);
+    (grid, hermite)
+}
+
+/// Voxelise the transformed scene data into a grid just big enough to fit it, leaving the rest of
+/// the grid empty if `cancel` is set part way through
+pub(crate) fn voxelise_data(scene: Arc<Vec<ModelData>>, scale: f32, cancel: &Arc<AtomicBool>) -> VoxelGrid {
+    match scene_bounds(&scene) {
+        Some((min, max)) => fill_grid(scene, VoxelGrid::new(min, scale, grid_size(min, max, scale)), cancel),
+        // Nothing to voxelise
+        None => VoxelGrid::new(Vec3::splat(0.0), scale, [0, 0, 0]),
+    }
+}
+
+/// The smallest and largest corners of the scene, if there is anything in it
+fn scene_bounds(scene: &[ModelData]) -> Option<(Vec3, Vec3)> {
+    let mut min = Vec3::splat(f32::INFINITY);
+    let mut max = Vec3::splat(f32::NEG_INFINITY);
+    for model_data in scene {
         for v in &model_data.verts {
-            max[0] = max[0].max(v.pos[0]);
-            max[1] = max[1].max(v.pos[1]);
-            max[2] = max[2].max(v.pos[2]);
+            min = min.min(v.pos_vec());
+            max = max.max(v.pos_vec());
         }
-        let max = max;
+    }
 
-        let res_x = ((max[0] - min[0]) / scale).ceil() as usize;
-        let res_y = ((max[1] - min[1]) / scale).ceil() as usize;
-        let res_z = ((max[2] - min[2]) / scale).ceil() as usize;
+    if min.x > max.x {
+        None
+    } else {
+        Some((min, max))
+    }
+}
 
-        for x in 0..res_x {
-            for y in 0..res_y {
-                for z in 0..res_z {
-                    let tx = tx.clone();
-                    let model_data = model_data.clone();
+/// Make sure a voxel size can be voxelised with, being a finite size above 0
+pub fn check_scale(scale: f32) -> Result<(), String> {
+    if scale.is_finite() && scale > 0.0 {
+        Ok(())
+    } else {
+        Err(format!("Invalid voxel size {}, it has to be above 0", scale))
+    }
+}
 
-                    pool.execute(move || {
-                        generate_voxel(tx, model_data, min.into(), x, y, z, scale);
-                    });
-                }
-            }
-        }
+/// Voxels along each axis needed to cover the bounds, or none at all for a voxel size that
+/// fails [`check_scale`], rather than more than could ever be allocated
+fn grid_size(min: Vec3, max: Vec3, scale: f32) -> [usize; 3] {
+    if let Err(e) = check_scale(scale) {
+        log::error!("{}", e);
+        return [0; 3];
+    }
+    let res = ((max - min) / scale).ceil();
+    [res.x as usize, res.y as usize, res.z as usize]
+}
 
-        // Consolidate voxels into a grid
-        let mut voxels = vec![vec![vec![false; res_z]; res_y]; res_x];
+/// Voxelise the transformed scene data into an empty grid.
+///
+/// Each x-slab of the grid is worked out on the thread pool and written back to its own place in
+/// the grid, so the result does not depend on the order the slabs finish in, and the same models
+/// and scale will always give the same grid. Slabs that haven't been started when `cancel` is set
+/// are skipped and left empty.
+fn fill_grid(scene: Arc<Vec<ModelData>>, mut grid: VoxelGrid, cancel: &Arc<AtomicBool>) -> VoxelGrid {
+    let (tx, rx) = mpsc::channel::<(usize, Vec<Option<MaterialId>>)>();
 
-        for _ in 0..(res_x * res_y * res_z) {
-            match rx.recv() {
-                Ok((x, y, z, voxel)) => {
-                    voxels[x][y][z] = voxel;
-                },
-                Err(e) => {
-                    println!("Error receiving: {}", e);
-                    break;
-                },
+    let pool = ThreadPool::new(16);
+
+    let [res_x, res_y, res_z] = grid.size;
+    let (min, scale) = (grid.min, grid.scale);
+
+    for x in 0..res_x {
+        let tx = tx.clone();
+        let scene = scene.clone();
+        let cancel = cancel.clone();
+
+        pool.execute(move || {
+            if !cancel.load(Ordering::Relaxed) {
+                generate_slab(tx, scene, min, x, res_y, res_z, scale);
             }
-        }
+        });
+    }
+    // Only the slabs hold senders now, so receiving stops once they're all done or skipped
+    drop(tx);
 
-        // Create verts from voxels
-        let mut verts: Vec<Vertex> = Vec::new();
-        for x in 0..res_x {
-            for y in 0..res_y {
-                for z in 0..res_z {
-                    if !voxels[x][y][z] {continue}
-
-                    let px = if x == res_x-1 {
-                        false
-                    } else {
-                        voxels[x+1][y][z]
-                    };
-                    let py = if y == res_y-1 {
-                        false
-                    } else {
-                        voxels[x][y+1][z]
-                    };
-                    let pz = if z == res_z-1 {
-                        false
-                    } else {
-                        voxels[x][y][z+1]
-                    };
-
-                    let nx = if x == 0 {
-                        false
-                    } else {
-                        voxels[x-1][y][z]
-                    };
-                    let ny = if y == 0 {
-                        false
-                    } else {
-                        voxels[x][y-1][z]
-                    };
-                    let nz = if z == 0 {
-                        false
-                    } else {
-                        voxels[x][y][z-1]
-                    };
-
-                    verts.append(&mut generate_block_mesh(Vec3::new(x as f32, y as f32, z as f32), scale, min.into(), px, py, pz, nx, ny, nz));
+    // Consolidate voxels into the grid
+    for _ in 0..res_x {
+        match rx.recv() {
+            Ok((x, slab)) => {
+                for y in 0..res_y {
+                    for z in 0..res_z {
+                        grid.set(x, y, z, slab[y * res_z + z]);
+                    }
                 }
-            }
+            },
+            Err(e) => {
+                if !cancel.load(Ordering::Relaxed) {
+                    println!("Error receiving: {}", e);
+                }
+                break;
+            },
         }
-        let inds: Vec<usize> = (0..verts.len()).collect();
+    }
 
-        std::thread::sleep(Duration::from_millis(2000));
+    grid
+}
+
+/// Voxelise an animation on a background thread, sending back the frames and the chunk meshes of
+/// each of them once done
+pub fn generate_animation(model: Arc<AnimatedModel>, animation: usize, fps: f32, scale: f32, style: MeshStyle) -> Receiver<(VoxelAnimation, Vec<Vec<VoxelMesh>>)> {
+    let (send_anim, receive_anim) = mpsc::channel::<(VoxelAnimation, Vec<Vec<VoxelMesh>>)>();
+
+    thread::spawn(move || {
+        let anim = voxelise_animation(&model, animation, fps, scale);
+        let meshes = anim.frames.iter().map(|frame| chunks::mesh_chunks(frame, style)).collect();
 
-        send_model.send((verts, inds)).unwrap();
+        send_anim.send((anim, meshes)).ok();
     });
 
-    receive_model
+    receive_anim
+}
+
+/// Voxelise one of a model's animations, sampling it `fps` times a second.
+///
+/// Every frame is voxelised onto the same grid, big enough to fit the model in all of its poses,
+/// so the frames line up with each other.
+pub fn voxelise_animation(model: &AnimatedModel, animation: usize, fps: f32, scale: f32) -> VoxelAnimation {
+    let duration = model.animations.get(animation).map(|a| a.duration).unwrap_or(0.0);
+    let frame_count = ((duration * fps).ceil() as usize).max(1);
+
+    let poses: Vec<Arc<Vec<ModelData>>> = (0..frame_count)
+        .map(|f| Arc::new(vec![get_model_data(&model.pose(animation, f as f32 / fps))]))
+        .collect();
+
+    let mut min = Vec3::splat(f32::INFINITY);
+    let mut max = Vec3::splat(f32::NEG_INFINITY);
+    for (pose_min, pose_max) in poses.iter().filter_map(|p| scene_bounds(p)) {
+        min = min.min(pose_min);
+        max = max.max(pose_max);
+    }
+    let (min, size) = if min.x > max.x {
+        (Vec3::splat(0.0), [0, 0, 0])
+    } else {
+        (min, grid_size(min, max, scale))
+    };
+
+    VoxelAnimation {
+        fps,
+        frames: poses
+            .into_iter()
+            .map(|pose| fill_grid(pose, VoxelGrid::new(min, scale, size), &Arc::default()))
+            .collect(),
+    }
 }
 
-fn generate_voxel(tx: Sender<(usize, usize, usize, bool)>, model: Arc<ModelData>, min: Vec3, x: usize, y: usize, z: usize, size: f32) {
-    let mut overlap = false;
-
-    let v_min = min + Vec3::new(x as f32 * size, y as f32 * size, z as f32 * size);
-    let v_max = v_min + Vec3::splat(size);
-
-    for i in 0..(model.inds.len() / 3) {
-        let v1 = model.verts[model.inds[i * 3]];
-        let v2 = model.verts[model.inds[i * 3 + 1]];
-        let v3 = model.verts[model.inds[i * 3 + 2]];
-        
-        let t_min = Vec3::new(
-            v1.pos[0].min(v2.pos[0].min(v3.pos[0])),
-            v1.pos[1].min(v2.pos[1].min(v3.pos[1])),
-            v1.pos[2].min(v2.pos[2].min(v3.pos[2]))
-        );
-        let t_max = Vec3::new(
-            v1.pos[0].max(v2.pos[0].max(v3.pos[0])),
-            v1.pos[1].max(v2.pos[1].max(v3.pos[1])),
-            v1.pos[2].max(v2.pos[2].max(v3.pos[2]))
-        );
-
-        if v_min.x <= t_max.x && v_max.x >= t_min.x
-            && v_min.y <= t_max.y && v_max.y >= t_min.y
-            && v_min.z <= t_max.z && v_max.z >= t_min.z {
-
-            overlap = true;
-            break;
+/// Work out every voxel with the given x coordinate, sending them back in y then z order
+fn generate_slab(tx: Sender<(usize, Vec<Option<MaterialId>>)>, scene: Arc<Vec<ModelData>>, min: Vec3, x: usize, res_y: usize, res_z: usize, size: f32) {
+    let mut slab = Vec::with_capacity(res_y * res_z);
+
+    for y in 0..res_y {
+        for z in 0..res_z {
+            let v_min = min + Vec3::new(x as f32 * size, y as f32 * size, z as f32 * size);
+            let v_max = v_min + Vec3::splat(size);
+
+            let voxel = scene
+                .iter()
+                .find(|model| voxel_overlaps(model, v_min, v_max))
+                .map(|model| model.material);
+            slab.push(voxel);
         }
     }
 
-    if let Err(e) = tx.send((x, y, z, overlap)) {
-        panic!("Failed to send voxel: {}", e);
+    if let Err(e) = tx.send((x, slab)) {
+        panic!("Failed to send voxels: {}", e);
     }
 }
 
-fn generate_block_mesh(
-    pos: Vec3,
-    size: f32,
-    min: Vec3,
-    px: bool,
-    py: bool,
-    pz: bool,
-    nx: bool,
-    ny: bool,
-    nz: bool,
-) -> Vec<Vertex> {
-    let mut verts: Vec<Vertex> = Vec::new();
-
-    // Positive y
-    if !py {
-        verts.push(Vertex {
-            pos: [min.x + pos.x * size + size, min.y + pos.y * size + size, min.z + pos.z * size + size],
-            norm: [0.0, 1.0, 0.0],
-        });
-        verts.push(Vertex {
-            pos: [min.x + pos.x * size + size, min.y + pos.y * size + size, min.z + pos.z * size],
-            norm: [0.0, 1.0, 0.0],
-        });
-        verts.push(Vertex {
-            pos: [min.x + pos.x * size, min.y + pos.y * size + size, min.z + pos.z * size],
-            norm: [0.0, 1.0, 0.0],
-        });
-        verts.push(Vertex {
-            pos: [min.x + pos.x * size + size, min.y + pos.y * size + size, min.z + pos.z * size + size],
-            norm: [0.0, 1.0, 0.0],
-        });
-        verts.push(Vertex {
-            pos: [min.x + pos.x * size, min.y + pos.y * size + size, min.z + pos.z * size],
-            norm: [0.0, 1.0, 0.0],
-        });
-        verts.push(Vertex {
-            pos: [min.x + pos.x * size, min.y + pos.y * size + size, min.z + pos.z * size + size],
-            norm: [0.0, 1.0, 0.0],
+fn voxel_overlaps(model: &ModelData, v_min: Vec3, v_max: Vec3) -> bool {
+    (0..(model.inds.len() / 3)).any(|i| triangle_overlaps(model, i, v_min, v_max))
+}
+
+fn triangle_overlaps(model: &ModelData, i: usize, v_min: Vec3, v_max: Vec3) -> bool {
+    let v1 = model.verts[model.inds[i * 3]];
+    let v2 = model.verts[model.inds[i * 3 + 1]];
+    let v3 = model.verts[model.inds[i * 3 + 2]];
+
+    let t_min = Vec3::new(
+        v1.pos[0].min(v2.pos[0].min(v3.pos[0])),
+        v1.pos[1].min(v2.pos[1].min(v3.pos[1])),
+        v1.pos[2].min(v2.pos[2].min(v3.pos[2]))
+    );
+    let t_max = Vec3::new(
+        v1.pos[0].max(v2.pos[0].max(v3.pos[0])),
+        v1.pos[1].max(v2.pos[1].max(v3.pos[1])),
+        v1.pos[2].max(v2.pos[2].max(v3.pos[2]))
+    );
+
+    v_min.x <= t_max.x && v_max.x >= t_min.x
+        && v_min.y <= t_max.y && v_max.y >= t_min.y
+        && v_min.z <= t_max.z && v_max.z >= t_min.z
+}
+
+/// Find the Hermite data of a voxelised scene.
+///
+/// Like [`fill_grid`], each x-slab is worked out on the thread pool. For each filled voxel, the
+/// line from its centre to the centre of each empty neighbour is tested against the triangles
+/// touching the voxel, taking the crossing closest to the empty side. Where no triangle crosses
+/// the line the crossing is put halfway, facing straight out of the voxel.
+fn hermite_data(scene: Arc<Vec<ModelData>>, grid: &VoxelGrid) -> HermiteData {
+    let (tx, rx) = mpsc::channel::<Vec<([i32; 3], usize, EdgeIntersection)>>();
+
+    let pool = ThreadPool::new(16);
+    let grid = Arc::new(grid.clone());
+
+    let res_x = grid.size[0];
+    for x in 0..res_x {
+        let tx = tx.clone();
+        let scene = scene.clone();
+        let grid = grid.clone();
+
+        pool.execute(move || {
+            // Nobody is listening anymore if the receiver was dropped
+            tx.send(hermite_slab(&scene, &grid, x as i32)).ok();
         });
     }
-    // Negative y
-    if !ny {
-        verts.push(Vertex {
-            pos: [min.x + pos.x * size + size, min.y + pos.y * size, min.z + pos.z * size + size],
-            norm: [0.0, -1.0, 0.0],
-        });
-        verts.push(Vertex {
-            pos: [min.x + pos.x * size, min.y + pos.y * size, min.z + pos.z * size + size],
-            norm: [0.0, -1.0, 0.0],
-        });
-        verts.push(Vertex {
-            pos: [min.x + pos.x * size, min.y + pos.y * size, min.z + pos.z * size],
-            norm: [0.0, -1.0, 0.0],
-        });
-        verts.push(Vertex {
-            pos: [min.x + pos.x * size + size, min.y + pos.y * size, min.z + pos.z * size + size],
-            norm: [0.0, -1.0, 0.0],
-        });
-        verts.push(Vertex {
-            pos: [min.x + pos.x * size, min.y + pos.y * size, min.z + pos.z * size],
-            norm: [0.0, -1.0, 0.0],
-        });
-        verts.push(Vertex {
-            pos: [min.x + pos.x * size + size, min.y + pos.y * size, min.z + pos.z * size],
-            norm: [0.0, -1.0, 0.0],
-        });
+    // Only the slabs hold senders now, so receiving stops once they're all done
+    drop(tx);
+
+    let mut hermite = HermiteData::new();
+    let mut received = 0;
+    for edges in rx {
+        for (voxel, axis, intersection) in edges {
+            hermite.insert(voxel, axis, intersection);
+        }
+        received += 1;
     }
-    // Negative z
-    if !nz {
-        verts.push(Vertex {
-            pos: [min.x + pos.x * size + size, min.y + pos.y * size + size, min.z + pos.z * size],
-            norm: [0.0, 0.0, -1.0],
-        });
-        verts.push(Vertex {
-            pos: [min.x + pos.x * size, min.y + pos.y * size, min.z + pos.z * size],
-            norm: [0.0, 0.0, -1.0],
-        });
-        verts.push(Vertex {
-            pos: [min.x + pos.x * size, min.y + pos.y * size + size, min.z + pos.z * size],
-            norm: [0.0, 0.0, -1.0],
-        });
-        verts.push(Vertex {
-            pos: [min.x + pos.x * size + size, min.y + pos.y * size + size, min.z + pos.z * size],
-            norm: [0.0, 0.0, -1.0],
-        });
-        verts.push(Vertex {
-            pos: [min.x + pos.x * size + size, min.y + pos.y * size, min.z + pos.z * size],
-            norm: [0.0, 0.0, -1.0],
-        });
-        verts.push(Vertex {
-            pos: [min.x + pos.x * size, min.y + pos.y * size, min.z + pos.z * size],
-            norm: [0.0, 0.0, -1.0],
-        });
+    if received < res_x {
+        log::error!("Only got the Hermite data of {} of {} slabs", received, res_x);
     }
-    // Positive x
-    if !px {
-        verts.push(Vertex {
-            pos: [min.x + pos.x * size + size, min.y + pos.y * size + size, min.z + pos.z * size + size],
-            norm: [1.0, 0.0, 0.0],
-        });
-        verts.push(Vertex {
-            pos: [min.x + pos.x * size + size, min.y + pos.y * size, min.z + pos.z * size],
-            norm: [1.0, 0.0, 0.0],
-        });
-        verts.push(Vertex {
-            pos: [min.x + pos.x * size + size, min.y + pos.y * size + size, min.z + pos.z * size],
-            norm: [1.0, 0.0, 0.0],
-        });
-        verts.push(Vertex {
-            pos: [min.x + pos.x * size + size, min.y + pos.y * size + size, min.z + pos.z * size + size],
-            norm: [1.0, 0.0, 0.0],
-        });
-        verts.push(Vertex {
-            pos: [min.x + pos.x * size + size, min.y + pos.y * size, min.z + pos.z * size + size],
-            norm: [1.0, 0.0, 0.0],
-        });
-        verts.push(Vertex {
-            pos: [min.x + pos.x * size + size, min.y + pos.y * size, min.z + pos.z * size],
-            norm: [1.0, 0.0, 0.0],
-        });
+
+    hermite
+}
+
+/// Hermite data for the edges leading out of the filled voxels with the given x coordinate
+fn hermite_slab(scene: &[ModelData], grid: &VoxelGrid, x: i32) -> Vec<([i32; 3], usize, EdgeIntersection)> {
+    const DIRECTIONS: [(usize, i32); 6] = [(0, 1), (0, -1), (1, 1), (1, -1), (2, 1), (2, -1)];
+
+    let centre = |p: [i32; 3]| grid.min + (Vec3::new(p[0] as f32, p[1] as f32, p[2] as f32) + 0.5) * grid.scale;
+
+    let mut edges = Vec::new();
+    for y in 0..grid.size[1] as i32 {
+        for z in 0..grid.size[2] as i32 {
+            if !grid.is_filled(x, y, z) {
+                continue;
+            }
+            let voxel = [x, y, z];
+            let v_min = grid.voxel_min(x as usize, y as usize, z as usize);
+            let v_max = v_min + Vec3::splat(grid.scale);
+
+            let mut touching: Option<Vec<(&ModelData, usize)>> = None;
+            for (axis, dir) in DIRECTIONS {
+                let mut next = voxel;
+                next[axis] += dir;
+                if grid.is_filled(next[0], next[1], next[2]) {
+                    continue;
+                }
+
+                let touching = touching.get_or_insert_with(|| {
+                    scene
+                        .iter()
+                        .flat_map(|m| (0..m.inds.len() / 3).map(move |i| (m, i)))
+                        .filter(|(m, i)| triangle_overlaps(m, *i, v_min, v_max))
+                        .collect()
+                });
+
+                let (from, to) = (centre(voxel), centre(next));
+                let mut out = Vec3::ZERO;
+                out[axis] = dir as f32;
+
+                let crossing = touching
+                    .iter()
+                    .filter_map(|(m, i)| {
+                        let [a, b, c] = [0, 1, 2].map(|k| m.verts[m.inds[i * 3 + k]].pos_vec());
+                        line_triangle(from, to, a, b, c).map(|t| (t, m.normals[*i]))
+                    })
+                    .fold(None, |best: Option<(f32, Vec3)>, hit| match best {
+                        Some(b) if b.0 >= hit.0 => Some(b),
+                        _ => Some(hit),
+                    });
+
+                let intersection = match crossing {
+                    Some((t, normal)) => EdgeIntersection {
+                        pos: from.lerp(to, t.clamp(0.0, 1.0)),
+                        normal: if normal.dot(out) < 0.0 { -normal } else { normal },
+                    },
+                    None => EdgeIntersection {
+                        pos: from.lerp(to, 0.5),
+                        normal: out,
+                    },
+                };
+
+                // Edges are named by their lower voxel
+                let lower = if dir > 0 { voxel } else { next };
+                edges.push((lower, axis, intersection));
+            }
+        }
     }
-    // Positive z
-    if !pz {
-        verts.push(Vertex {
-            pos: [min.x + pos.x * size + size, min.y + pos.y * size + size, min.z + pos.z * size + size],
-            norm: [0.0, 0.0, 1.0],
-        });
-        verts.push(Vertex {
-            pos: [min.x + pos.x * size, min.y + pos.y * size + size, min.z + pos.z * size + size],
-            norm: [0.0, 0.0, 1.0],
-        });
-        verts.push(Vertex {
-            pos: [min.x + pos.x * size, min.y + pos.y * size, min.z + pos.z * size + size],
-            norm: [0.0, 0.0, 1.0],
-        });
-        verts.push(Vertex {
-            pos: [min.x + pos.x * size + size, min.y + pos.y * size + size, min.z + pos.z * size + size],
-            norm: [0.0, 0.0, 1.0],
-        });
-        verts.push(Vertex {
-            pos: [min.x + pos.x * size, min.y + pos.y * size, min.z + pos.z * size + size],
-            norm: [0.0, 0.0, 1.0],
-        });
-        verts.push(Vertex {
-            pos: [min.x + pos.x * size + size, min.y + pos.y * size, min.z + pos.z * size + size],
-            norm: [0.0, 0.0, 1.0],
-        });
+
+    edges
+}
+
+/// Where the line through `from` and `to` crosses a triangle, as a fraction of the way along,
+/// ignoring crossings more than a voxel behind `from`
+fn line_triangle(from: Vec3, to: Vec3, a: Vec3, b: Vec3, c: Vec3) -> Option<f32> {
+    let dir = to - from;
+    let (e1, e2) = (b - a, c - a);
+    let p = dir.cross(e2);
+    let det = e1.dot(p);
+    if det.abs() < f32::EPSILON {
+        return None;
     }
-    // Negative X
-    if !nx {
-        verts.push(Vertex {
-            pos: [min.x + pos.x * size, min.y + pos.y * size + size, min.z + pos.z * size + size],
-            norm: [-1.0, 0.0, 0.0],
-        });
-        verts.push(Vertex {
-            pos: [min.x + pos.x * size, min.y + pos.y * size + size, min.z + pos.z * size],
-            norm: [-1.0, 0.0, 0.0],
-        });
-        verts.push(Vertex {
-            pos: [min.x + pos.x * size, min.y + pos.y * size, min.z + pos.z * size],
-            norm: [-1.0, 0.0, 0.0],
-        });
-        verts.push(Vertex {
-            pos: [min.x + pos.x * size, min.y + pos.y * size + size, min.z + pos.z * size + size],
-            norm: [-1.0, 0.0, 0.0],
-        });
-        verts.push(Vertex {
-            pos: [min.x + pos.x * size, min.y + pos.y * size, min.z + pos.z * size],
-            norm: [-1.0, 0.0, 0.0],
-        });
-        verts.push(Vertex {
-            pos: [min.x + pos.x * size, min.y + pos.y * size, min.z + pos.z * size + size],
-            norm: [-1.0, 0.0, 0.0],
-        });
+
+    let s = from - a;
+    let u = s.dot(p) / det;
+    let q = s.cross(e1);
+    let v = dir.dot(q) / det;
+    if u < 0.0 || v < 0.0 || u + v > 1.0 {
+        return None;
     }
 
-    verts
-}
\ No newline at end of file
+    let t = e2.dot(q) / det;
+    if !(-1.0..=1.0).contains(&t) {
+        return None;
+    }
+    Some(t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesher;
+
+    /// An octahedron with corners one unit from the origin
+    fn octahedron() -> Model {
+        let corners = [[1.0, 0.0, 0.0], [-1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, -1.0, 0.0], [0.0, 0.0, 1.0], [0.0, 0.0, -1.0]];
+        let verts = corners.iter().map(|c| Vertex::new(*c, *c)).collect();
+        let mut inds = Vec::new();
+        for x in [0, 1] {
+            for y in [2, 3] {
+                for z in [4, 5] {
+                    inds.extend([x, y, z]);
+                }
+            }
+        }
+        Model::new(verts, inds)
+    }
+
+    #[test]
+    fn voxelising_is_deterministic() {
+        let model = octahedron();
+        let (a, b) = (voxelise(&model, 0.1), voxelise(&model, 0.1));
+        assert_eq!(a.fingerprint(), b.fingerprint());
+        assert!(a.count() > 0);
+        assert_eq!(a.voxels(), b.voxels());
+        // Changes to how voxels are found or hashed show up here
+        assert_eq!(a.fingerprint(), 17153372075718168122);
+
+        for style in [MeshStyle::Blocks, MeshStyle::Greedy, MeshStyle::MarchingCubes { smoothing: 1 }, MeshStyle::SurfaceNets] {
+            assert_eq!(mesher::mesh(&a, style).fingerprint(), mesher::mesh(&b, style).fingerprint(), "{:?}", style);
+        }
+    }
+}